@@ -0,0 +1,143 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::set::{Setting, Status};
+use narictl_lib::*;
+
+/// A setting that differs between the headset and the file
+#[derive(Debug)]
+pub struct Change {
+    pub from: Setting,
+    pub to: Setting,
+    /// `None` when it was only planned
+    pub status: Option<Status>,
+}
+
+/// Reads the state of the headset and sends every setting `desired` changes, with
+/// `dry_run` nothing is sent. A failed setting does not stop the others.
+pub fn run<T: Transport>(
+    device: &Device<T>,
+    desired: &DesiredState,
+    dry_run: bool,
+) -> Result<Vec<Change>> {
+    let current = device.state()?;
+    let target = desired.target(&current, device.model()?)?;
+
+    let pairs = [
+        (
+            Setting::Effect(current.effect),
+            Setting::Effect(target.effect),
+        ),
+        (
+            Setting::Brightness(current.brightness),
+            Setting::Brightness(target.brightness),
+        ),
+        (
+            Setting::Haptics {
+                enabled: current.haptics_enabled,
+                intensity: current.haptic_intensity,
+            },
+            Setting::Haptics {
+                enabled: target.haptics_enabled,
+                intensity: target.haptic_intensity,
+            },
+        ),
+        (
+            Setting::Sidetone(current.mic_monitor),
+            Setting::Sidetone(target.mic_monitor),
+        ),
+    ];
+    Ok(pairs
+        .into_iter()
+        .filter(|(from, to)| from != to)
+        .map(|(from, to)| Change {
+            from,
+            to,
+            status: (!dry_run).then(|| match to.apply(device) {
+                Ok(()) => Status::Applied,
+                Err(e) => Status::Failed(e),
+            }),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A headset answering that it is static green at 50%, haptics at 30 and mic monitoring off
+    fn mock() -> Device<MockTransport> {
+        let device = Device::with_transport(MockTransport::new()).with_experimental(false);
+        for (command, arguments) in [
+            (Command::HAPTICS, &[1, 30][..]),
+            (Command::COLOR, &[0, 255, 0]),
+            (Command::BRIGHTNESS, &[50]),
+            (Command::MIC_MONITOR, &[0x04, 0x00, MIC_MONITOR_OFF]),
+        ] {
+            let answer = Report::new(Direction::Get, command, arguments).unwrap();
+            device.transport().push_response(answer.to_bytes());
+        }
+        device
+    }
+
+    /// The set requests sent, the state is read first with get requests
+    fn writes(device: &Device<MockTransport>) -> Vec<Vec<u8>> {
+        device
+            .transport()
+            .sent()
+            .into_iter()
+            .filter(|report| Report::parse(report).unwrap().direction == Direction::Set)
+            .collect()
+    }
+
+    #[test]
+    fn nothing_is_written_when_the_state_matches() {
+        let device = mock();
+        let desired = DesiredState {
+            effect: Some(LightingEffect::Static((0, 255, 0))),
+            brightness: Some(50),
+            haptics_enabled: Some(true),
+            haptic_intensity: Some(30),
+            sidetone_enabled: Some(false),
+            ..DesiredState::default()
+        };
+        assert!(run(&device, &desired, false).unwrap().is_empty());
+        assert!(writes(&device).is_empty());
+    }
+
+    #[test]
+    fn only_the_changed_settings_are_written() {
+        let device = mock();
+        let desired = DesiredState {
+            effect: Some(LightingEffect::Static((0, 255, 0))),
+            brightness: Some(80),
+            haptic_intensity: Some(30),
+            ..DesiredState::default()
+        };
+        let changes = run(&device, &desired, false).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].from, Setting::Brightness(50));
+        assert_eq!(changes[0].to, Setting::Brightness(80));
+        assert!(matches!(changes[0].status, Some(Status::Applied)));
+        assert_eq!(
+            writes(&device),
+            [Report::set(Command::BRIGHTNESS, &[80]).unwrap().to_bytes()]
+        );
+    }
+
+    #[test]
+    fn dry_run_writes_nothing() {
+        let device = mock();
+        let desired = DesiredState {
+            brightness: Some(80),
+            haptics_enabled: Some(false),
+            ..DesiredState::default()
+        };
+        let changes = run(&device, &desired, true).unwrap();
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().all(|change| change.status.is_none()));
+        assert!(writes(&device).is_empty());
+    }
+}
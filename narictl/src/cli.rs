@@ -0,0 +1,299 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::output::Format;
+use clap::{ArgAction, ArgGroup, Args, Parser, Subcommand};
+use clap_complete::Shell;
+use narictl_lib::{
+    DEFAULT_MONITOR_INTERVAL, DEFAULT_THRESHOLDS, DeviceSelector, MIC_MONITOR_MAX_LEVEL,
+    REPORT_LEN, decode_hex,
+};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "narictl",
+    version,
+    about = "Control the Razer Nari family of headsets",
+    after_help = "Leaving out the value of a setting prints its current state (EXPERIMENTAL)\n\n\
+                  Supported headsets: Razer Nari Ultimate (1532:051a), Razer Nari (1532:051c, unverified) \
+                  and Razer Nari Essential (1532:051e, unverified)"
+)]
+pub struct Cli {
+    /// Use this headset: bus:address, port path or serial as listed by `narictl devices`.
+    /// Talks to the headset directly, without going through narictld
+    #[arg(short, long, global = true, value_parser = parse_selector)]
+    pub device: Option<DeviceSelector>,
+
+    /// How results and errors are printed
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+
+    /// Same as --format=json
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    /// Log what is sent to the headset, `-vv` logs every USB transfer.
+    /// `--version --verbose` also prints firmware versions and serial numbers
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Write a trace of every USB transfer to this file instead of stderr
+    #[arg(long, global = true, value_name = "FILE")]
+    pub trace_file: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+impl Cli {
+    pub fn format(&self) -> Format {
+        if self.json { Format::Json } else { self.format }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Change the led color
+    Color {
+        /// Red, green and blue from 0 to 255, ex: 255,0,0
+        #[arg(value_name = "R,G,B")]
+        color: Option<Rgb>,
+    },
+    /// Change the lighting effect
+    Effect {
+        #[command(subcommand)]
+        effect: Option<EffectCommand>,
+    },
+    /// Play a keyframe animation from a TOML or JSON file
+    Animate { file: PathBuf },
+    /// Change the led brightness
+    Brightness {
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=100))]
+        value: Option<u8>,
+    },
+    /// Change the haptic intensity, `off` turns haptics off
+    Haptics {
+        #[arg(value_parser = parse_haptics)]
+        value: Option<Haptics>,
+    },
+    /// Change the mic monitor level, 0 turns mic monitoring off
+    #[command(visible_alias = "mic_monitor", visible_alias = "mic-monitor")]
+    Sidetone {
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=MIC_MONITOR_MAX_LEVEL as i64))]
+        level: Option<u8>,
+    },
+    /// Change several settings at once, without claiming the headset for each of them
+    Set(SetArgs),
+    /// Bring the headset to the state described in a TOML, JSON or YAML file, changing only what differs
+    Apply {
+        file: PathBuf,
+        /// Print the changes without sending them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Get the current voltage of the battery (EXPERIMENTAL)
+    #[command(visible_alias = "mv")]
+    Voltage,
+    /// Get the battery charge and charging state (EXPERIMENTAL)
+    Battery,
+    /// Print everything known about the dongle and the headset (EXPERIMENTAL)
+    Status,
+    /// Keep reading the battery, sending desktop notifications when it runs low, starts or stops
+    /// charging and when the headset disconnects or comes back (EXPERIMENTAL)
+    Monitor(MonitorArgs),
+    /// Manage the profiles stored in $XDG_CONFIG_HOME/narictl/profiles.toml
+    Profile {
+        #[command(subcommand)]
+        action: Option<ProfileCommand>,
+    },
+    /// List the plugged in headset dongles
+    Devices,
+    /// Decode the headset reports in a usbmon text log or a pcap/pcapng file
+    Capture {
+        file: PathBuf,
+        /// Compare every report sent with what narictl sends for the same command
+        #[arg(long)]
+        replay: bool,
+    },
+    /// Print the shell completions for narictl
+    Completions { shell: Shell },
+    /// Print the man page of narictl
+    Manpage,
+    /// Send raw feature reports to the headset, for finding out what new commands do.
+    /// Wrong reports can leave the headset in an odd state until it is turned off and on again
+    #[command(hide = true)]
+    Raw {
+        /// Confirms that you know what the reports you send do
+        #[arg(long, required = true)]
+        allow_raw: bool,
+        #[command(subcommand)]
+        action: RawCommand,
+    },
+}
+
+#[derive(Debug, Args)]
+#[command(group(
+    ArgGroup::new("settings")
+        .args(["color", "brightness", "haptics", "sidetone"])
+        .required(true)
+        .multiple(true)
+))]
+pub struct SetArgs {
+    /// Red, green and blue from 0 to 255, ex: 255,0,0
+    #[arg(long, value_name = "R,G,B")]
+    pub color: Option<Rgb>,
+    /// Led brightness from 0 to 100
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub brightness: Option<u8>,
+    /// Haptic intensity from 0 to 100, `off` turns haptics off
+    #[arg(long, value_parser = parse_haptics)]
+    pub haptics: Option<Haptics>,
+    /// Mic monitor level, 0 turns mic monitoring off
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=MIC_MONITOR_MAX_LEVEL as i64))]
+    pub sidetone: Option<u8>,
+    /// Restore the settings already changed when one of them fails
+    #[arg(long)]
+    pub rollback: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct MonitorArgs {
+    /// Seconds between two battery readings
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = DEFAULT_MONITOR_INTERVAL.as_secs(),
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub interval: u64,
+    /// Charges in percent to warn about, ex: 20,10,5
+    #[arg(
+        long,
+        value_name = "PCT",
+        value_delimiter = ',',
+        default_values_t = DEFAULT_THRESHOLDS,
+        value_parser = clap::value_parser!(u8).range(1..=100)
+    )]
+    pub thresholds: Vec<u8>,
+    /// Only print the alerts, without desktop notifications
+    #[arg(long)]
+    pub no_notify: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RawCommand {
+    /// Send a report without reading an answer
+    Send(RawArgs),
+    /// Send a report and print the answer of the headset
+    Query(RawArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct RawArgs {
+    /// Report as hex, ex: `ff0a00fd0412f10205`, padded with zeros to 64 bytes
+    #[arg(value_name = "HEX", value_parser = parse_report)]
+    pub report: RawReport,
+    /// Number of times the report is sent
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub repeat: u32,
+    /// Milliseconds to wait between two repeats
+    #[arg(long, value_name = "MS", default_value_t = 500)]
+    pub interval: u64,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum EffectCommand {
+    Off,
+    Static {
+        #[arg(value_name = "R,G,B")]
+        color: Rgb,
+    },
+    /// One color, two alternating colors, or random colors when none are given
+    Breathing {
+        #[arg(value_name = "R,G,B")]
+        first: Option<Rgb>,
+        #[arg(value_name = "R,G,B", requires = "first")]
+        second: Option<Rgb>,
+    },
+    Spectrum,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ProfileCommand {
+    /// Save the current settings as a profile (EXPERIMENTAL)
+    Save { name: String },
+    /// Apply a saved profile
+    Load { name: String },
+    /// List the saved profiles
+    List,
+    /// Delete a saved profile
+    Delete { name: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl FromStr for Rgb {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let rgb = value
+            .split(',')
+            .map(|color| color.trim().parse::<u8>())
+            .collect::<Result<Vec<u8>, _>>();
+        match rgb.as_deref() {
+            Ok([r, g, b]) => Ok(Rgb(*r, *g, *b)),
+            _ => Err(String::from(
+                "expected R,G,B with values from 0 to 255 (ex: 255,0,0)",
+            )),
+        }
+    }
+}
+
+impl From<Rgb> for (u8, u8, u8) {
+    fn from(Rgb(r, g, b): Rgb) -> Self {
+        (r, g, b)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Haptics {
+    Off,
+    Intensity(u8),
+}
+
+fn parse_haptics(value: &str) -> Result<Haptics, String> {
+    if value == "off" {
+        return Ok(Haptics::Off);
+    }
+    match value.parse::<u8>() {
+        Ok(intensity) if intensity <= 100 => Ok(Haptics::Intensity(intensity)),
+        _ => Err(String::from("expected off or an intensity from 0 to 100")),
+    }
+}
+
+/// A full size feature report
+#[derive(Debug, Clone)]
+pub struct RawReport(pub Vec<u8>);
+
+fn parse_report(value: &str) -> Result<RawReport, String> {
+    let mut report = decode_hex(value).map_err(|e| e.to_string())?;
+    if report.len() > REPORT_LEN {
+        return Err(format!(
+            "a report is at most {REPORT_LEN} bytes, got {}",
+            report.len()
+        ));
+    }
+    report.resize(REPORT_LEN, 0);
+    Ok(RawReport(report))
+}
+
+fn parse_selector(value: &str) -> Result<DeviceSelector, String> {
+    value
+        .parse()
+        .map_err(|e: narictl_lib::NariError| e.to_string())
+}
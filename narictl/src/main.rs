@@ -3,105 +3,264 @@ Copyright d22592 and contributors
 SPDX-License-Identifier: GPL-3.0-or-later
 */
 
-use narictl_lib::*;
-use std::env;
+mod apply;
+mod cli;
+mod monitor;
+mod output;
+mod raw;
+mod set;
 
-const GENERIC_ERROR: &str = "Failed to communicate with the headset";
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use cli::{Cli, Command, EffectCommand, Haptics, ProfileCommand};
+use env_logger::{Env, Target};
+use log::LevelFilter;
+use narictl_lib::*;
+use output::{Format, Output, print_error};
+use std::fs::File;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 fn main() {
-    let arguments: Vec<String> = env::args().skip(1).collect();
-
-    if arguments.is_empty() {
-        eprintln!("Please provide an argument");
-        help();
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) if e.kind() == ErrorKind::DisplayVersion && has_argument(&["-v", "--verbose"]) => {
+            let format = if wants_json() {
+                Format::Json
+            } else {
+                Format::Text
+            };
+            version().print(format);
+            return;
+        }
+        Err(e) if e.kind() == ErrorKind::DisplayHelp || e.kind() == ErrorKind::DisplayVersion => {
+            e.exit()
+        }
+        // The arguments could not be parsed, so look for the format by hand
+        Err(e) if wants_json() => {
+            let message = e.to_string();
+            let message = message.lines().next().unwrap_or_default();
+            print_error(Format::Json, "usage", message.trim_start_matches("error: "));
+            std::process::exit(2);
+        }
+        Err(e) => e.exit(),
+    };
+    let format = cli.format();
+    if let Err(e) = init_logging(&cli) {
+        print_error(format, e.code(), &e.to_string());
         std::process::exit(1);
     }
+    match run(cli) {
+        Ok(output) => {
+            output.print(format);
+            if output.failed() {
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            print_error(format, e.code(), &e.to_string());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Warnings are always logged, `-v` and `-vv` add more of the library's messages.
+/// `$NARICTL_LOG` takes env_logger filters, ex: `NARICTL_LOG=narictl_lib::transport=trace`
+fn init_logging(cli: &Cli) -> Result<()> {
+    let level = match (cli.verbose, &cli.trace_file) {
+        (_, Some(_)) | (2.., None) => LevelFilter::Trace,
+        (1, None) => LevelFilter::Debug,
+        (0, None) => LevelFilter::Warn,
+    };
+    let mut builder = env_logger::Builder::from_env(Env::new().filter_or("NARICTL_LOG", "warn"));
+    if level > LevelFilter::Warn {
+        builder.filter_module("narictl_lib", level);
+    }
+    if let Some(path) = &cli.trace_file {
+        let file = File::create(path)
+            .map_err(|e| NariError::Config(format!("failed to create {}: {e}", path.display())))?;
+        builder.target(Target::Pipe(Box::new(file)));
+    }
+    builder.init();
+    Ok(())
+}
 
-    let device = Device::init().expect("Unable to initialize the headset");
+fn wants_json() -> bool {
+    let arguments: Vec<String> = std::env::args().collect();
+    has_argument(&["--json", "--format=json"])
+        || arguments
+            .windows(2)
+            .any(|pair| pair[0] == "--format" && pair[1] == "json")
+}
 
-    match arguments.get(0).unwrap_or(&String::new()).as_str() {
-        "color" => {
-            let args = arguments.get(1);
-            let rgb = args
-                .unwrap_or(&String::new())
-                .split(',')
-                .map(|color| color.parse::<u8>().unwrap_or_default())
-                .collect::<Vec<u8>>();
-            if rgb.len() != 3 {
-                eprintln!("Invalid value, (ex: 255,0,0)");
-                return;
-            }
-            device
-                .set_fixed(rgb[0], rgb[1], rgb[2])
-                .expect(GENERIC_ERROR);
-        }
-        "brightness" => {
-            let percentage = arguments
-                .get(1)
-                .unwrap_or(&String::new())
-                .parse::<u8>()
-                .unwrap_or_default();
-            if percentage > 100 {
-                eprintln!("[WARN]: percentage should not be more than 100.");
+/// Looks for a flag by hand, for when clap stopped before parsing every argument
+fn has_argument(names: &[&str]) -> bool {
+    std::env::args().any(|argument| names.contains(&argument.as_str()))
+}
+
+/// Version of narictl followed by the model, firmware versions and serial numbers of the headset
+fn version() -> Output {
+    let device = Device::connect();
+    Output::Version(device.and_then(|device| Ok((device.model()?, Box::new(device.identity())))))
+}
+
+fn run(cli: Cli) -> Result<Output> {
+    let format = cli.format();
+    let selector = cli.device.unwrap_or_default();
+    let device = || Device::connect_selected(selector.clone());
+
+    Ok(match cli.command {
+        Command::Color { color: Some(color) } => {
+            let (r, g, b) = color.into();
+            device()?.set_fixed(r, g, b)?;
+            Output::Color((r, g, b)).applied()
+        }
+        Command::Color { color: None } => Output::Color(device()?.get_color()?),
+        Command::Effect {
+            effect: Some(effect),
+        } => {
+            let effect = match effect {
+                EffectCommand::Off => LightingEffect::Off,
+                EffectCommand::Static { color } => LightingEffect::Static(color.into()),
+                EffectCommand::Breathing { first, second } => {
+                    LightingEffect::Breathing(match (first, second) {
+                        (Some(first), Some(second)) => Breathing::Dual(first.into(), second.into()),
+                        (Some(first), None) => Breathing::Single(first.into()),
+                        _ => Breathing::Random,
+                    })
+                }
+                EffectCommand::Spectrum => LightingEffect::Spectrum,
+            };
+            device()?.set_effect(effect)?;
+            Output::Effect(effect).applied()
+        }
+        Command::Effect { effect: None } => Output::Effect(device()?.get_effect()?),
+        Command::Animate { file } => {
+            animate(&device()?, &Animation::load(&file)?)?;
+            Output::Animation(file.display().to_string()).applied()
+        }
+        Command::Brightness { value: Some(value) } => {
+            device()?.set_brightness(value)?;
+            Output::Brightness(value).applied()
+        }
+        Command::Brightness { value: None } => Output::Brightness(device()?.get_brightness()?),
+        Command::Haptics {
+            value: Some(Haptics::Intensity(intensity)),
+        } => {
+            device()?.set_haptic_intensity(intensity, true)?;
+            Output::Haptics {
+                enabled: true,
+                intensity,
             }
-            device
-                .set_brightness(percentage)
-                .expect(GENERIC_ERROR);
-        }
-        "haptics" => {
-            let percentage = arguments
-                .get(1)
-                .unwrap_or(&String::new())
-                .parse::<u8>()
-                .unwrap_or_default();
-            if percentage > 100 {
-                eprintln!("[WARN]: percentage should be more than 100.");
+            .applied()
+        }
+        Command::Haptics {
+            value: Some(Haptics::Off),
+        } => {
+            let device = device()?;
+            let (intensity, _) = device.get_haptic_intensity()?;
+            device.set_haptic_intensity(intensity, false)?;
+            Output::Haptics {
+                enabled: false,
+                intensity,
             }
-            device
-                .set_haptic_intensity(percentage, true)
-                .expect(GENERIC_ERROR);
-        }
-        "mic_monitor" | "sidetone" => {
-            let mut enable = arguments
-                .get(1)
-                .unwrap_or(&String::default())
-                .parse::<u8>()
-                .unwrap_or_default();
-            if enable > 88 {
-                eprintln!("[WARN]: value should not be more than 88.");
-                enable = 0;
+            .applied()
+        }
+        Command::Haptics { value: None } => {
+            let (intensity, enabled) = device()?.get_haptic_intensity()?;
+            Output::Haptics { enabled, intensity }
+        }
+        Command::Sidetone { level: Some(0) } => {
+            device()?.set_mic_monitor(MIC_MONITOR_OFF)?;
+            Output::Sidetone(None).applied()
+        }
+        Command::Sidetone { level: Some(level) } => {
+            let value = mic_monitor_to_raw(level);
+            device()?.set_mic_monitor(value)?;
+            Output::Sidetone(Some(mic_monitor_from_raw(value))).applied()
+        }
+        Command::Sidetone { level: None } => match device()?.get_mic_monitor()? {
+            MIC_MONITOR_OFF => Output::Sidetone(None),
+            value => Output::Sidetone(Some(mic_monitor_from_raw(value))),
+        },
+        Command::Set(args) => Output::Set(set::run(&device()?, args)?),
+        Command::Apply { file, dry_run } => {
+            let desired = DesiredState::load(&file)?;
+            Output::Apply {
+                changes: apply::run(&device()?, &desired, dry_run)?,
+                dry_run,
             }
-            enable /= 2;
-            if enable % 2 != 0 {
-                enable -= 1;
+        }
+        Command::Voltage => Output::Voltage(device()?.get_voltage()?),
+        Command::Battery => Output::Battery(device()?.get_battery()?),
+        Command::Status => Output::Status(Box::new(device()?.status()?)),
+        Command::Monitor(args) => {
+            monitor::run(selector.clone(), args, format)?;
+            Output::Nothing
+        }
+        Command::Profile { action } => profile(action.unwrap_or(ProfileCommand::List), selector)?,
+        Command::Devices => Output::Devices(Device::enumerate()?),
+        Command::Capture { file, replay } => {
+            let reports = capture::load(&file)?;
+            match replay {
+                true => Output::Replayed(capture::replay(&reports)),
+                false => Output::Captured(reports),
             }
-            enable += 192;
-            device
-                .set_mic_monitor(enable)
-                .expect(GENERIC_ERROR);
-        }
-        "voltage" | "mv" => println!("Voltage: {} mv", device.get_voltage().expect(GENERIC_ERROR)),
-        "--help" | "-h" => help(),
-        "--version" | "-v" => println!("{} v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
-        cmd => {
-            eprintln!("Invalid choice: {cmd}");
-            help();
         }
-    }
+        Command::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "narictl", &mut io::stdout());
+            Output::Nothing
+        }
+        Command::Raw { action, .. } => {
+            raw::run(&device()?, action, format)?;
+            Output::Nothing
+        }
+        Command::Manpage => {
+            clap_mangen::Man::new(Cli::command())
+                .render(&mut io::stdout())
+                .map_err(|e| NariError::Config(format!("failed to write the man page: {e}")))?;
+            Output::Nothing
+        }
+    })
 }
 
-fn help() {
-    println!(r#"Usage: {} [options] [arguments]
+/// Plays `animation` until it finishes or Ctrl-C is pressed. A stopped animation puts back the
+/// lighting shown before it started instead of leaving whichever frame was showing.
+fn animate<T: Transport>(device: &Device<T>, animation: &Animation) -> Result<()> {
+    let previous = device.get_effect()?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    if let Err(e) = ctrlc::set_handler(move || handler_stop.store(true, Ordering::Relaxed)) {
+        log::warn!("Ctrl-C will end narictl without restoring the lighting: {e}");
+    }
+    animation.play(device, &stop)?;
+    if stop.load(Ordering::Relaxed) {
+        device.set_effect(previous)?;
+    }
+    Ok(())
+}
 
-Options:
-    color <R,G,B>                     | Change led color to values (max value for each 255)
-    brightness <value>                | Change led brighness to value (max 100)
-    haptics <value>                   | Change haptic intensity to value (max 100)
-    mic_monitor, sidetone <value>     | Change mic monitor state to value (max 86)
-    mv, voltage                       | Get the current voltage of the battery (EXPERIMENTAL)
-    -h, --help                        | Shows this help menu
-    -v, --version                     | Shows the application's version 
-"#,
-        env!("CARGO_PKG_NAME")
-    );
+fn profile(action: ProfileCommand, selector: DeviceSelector) -> Result<Output> {
+    let mut profiles = Profiles::load()?;
+    Ok(match action {
+        ProfileCommand::Save { name } => {
+            let state = Device::connect_selected(selector)?.state()?;
+            let profile = Profile::from(state);
+            profiles.insert(&name, profile)?;
+            profiles.save()?;
+            Output::ProfileSaved { name, profile }
+        }
+        ProfileCommand::Load { name } => {
+            let profile = profiles.get(&name)?;
+            Device::connect_selected(selector)?.apply_state(&profile.state())?;
+            Output::Profile { name, profile }.applied()
+        }
+        ProfileCommand::Delete { name } => {
+            profiles.remove(&name)?;
+            profiles.save()?;
+            Output::ProfileDeleted(name).applied()
+        }
+        ProfileCommand::List => Output::Profiles(profiles.profiles.into_keys().collect()),
+    })
 }
@@ -0,0 +1,40 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::cli::MonitorArgs;
+use crate::output::{Format, Output};
+use narictl_lib::*;
+use std::thread;
+use std::time::Duration;
+
+/// Reads the battery every `interval` seconds, printing each alert and showing it as a
+/// notification. Only returns when the headset has no battery to watch.
+///
+/// The headset is opened for each reading and released right after, so other commands can
+/// use it in between.
+pub fn run(selector: DeviceSelector, args: MonitorArgs, format: Format) -> Result<()> {
+    let mut monitor = BatteryMonitor::new(&args.thresholds);
+    loop {
+        let battery =
+            Device::connect_selected(selector.clone()).and_then(|device| device.get_battery());
+        let battery = match battery {
+            Ok(battery) => Some(battery),
+            Err(e @ NariError::Unsupported { .. }) => return Err(e),
+            Err(e) => {
+                log::debug!("Failed to read the battery: {e}");
+                None
+            }
+        };
+        for alert in monitor.update(battery) {
+            Output::Alert(alert).print(format);
+            if !args.no_notify
+                && let Err(e) = alert.notify()
+            {
+                log::warn!("{e}");
+            }
+        }
+        thread::sleep(Duration::from_secs(args.interval));
+    }
+}
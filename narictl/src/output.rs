@@ -0,0 +1,433 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::apply::Change;
+use crate::raw::hex_dump;
+use crate::set::Outcome;
+use clap::ValueEnum;
+use narictl_lib::capture::{CapturedReport, ControlRequest, Replayed};
+use narictl_lib::{
+    ARGUMENTS_LEN, Alert, Battery, ChargingStatus, DeviceInfo, Identity, LightingEffect,
+    MIC_MONITOR_OFF, Model, Profile, Report, Result, Status, encode_hex, mic_monitor_from_raw,
+};
+use serde_json::{Value, json};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Result of a command, printed as text or as a JSON object
+pub enum Output {
+    Nothing,
+    /// A setting that was sent to the headset, only printed in JSON
+    Applied(Box<Output>),
+    Color((u8, u8, u8)),
+    Effect(LightingEffect),
+    Brightness(u8),
+    Haptics {
+        enabled: bool,
+        intensity: u8,
+    },
+    /// Mic monitor level, `None` when off
+    Sidetone(Option<u8>),
+    Voltage(u16),
+    Battery(Battery),
+    Status(Box<Status>),
+    /// Printed by `narictl monitor` as soon as it happens
+    Alert(Alert),
+    /// Every setting of `narictl set` and what happened to it
+    Set(Vec<Outcome>),
+    /// Settings `narictl apply` changed, or would change with `dry_run`
+    Apply {
+        changes: Vec<Change>,
+        dry_run: bool,
+    },
+    Animation(String),
+    Profiles(Vec<String>),
+    Profile {
+        name: String,
+        profile: Profile,
+    },
+    ProfileSaved {
+        name: String,
+        profile: Profile,
+    },
+    ProfileDeleted(String),
+    Devices(Vec<DeviceInfo>),
+    Captured(Vec<CapturedReport>),
+    Replayed(Vec<Replayed>),
+    /// A raw report and the answer of the headset, if one was read
+    Raw {
+        sent: Vec<u8>,
+        received: Option<Vec<u8>>,
+    },
+    /// Printed by `--version --verbose`
+    Version(Result<(&'static Model, Box<Identity>)>),
+}
+
+impl Output {
+    pub fn applied(self) -> Output {
+        Output::Applied(Box::new(self))
+    }
+
+    /// Whether part of the command failed after the output was produced
+    pub fn failed(&self) -> bool {
+        match self {
+            Output::Set(outcomes) => outcomes
+                .iter()
+                .any(|outcome| outcome.status.error().is_some()),
+            Output::Apply { changes, .. } => changes
+                .iter()
+                .any(|change| change.status.as_ref().is_some_and(|s| s.error().is_some())),
+            _ => false,
+        }
+    }
+
+    pub fn print(&self, format: Format) {
+        match format {
+            Format::Text => {
+                if let Some(text) = self.text() {
+                    println!("{text}");
+                }
+            }
+            Format::Json => {
+                if !matches!(self, Output::Nothing) {
+                    println!("{}", self.json());
+                }
+            }
+        }
+    }
+
+    fn text(&self) -> Option<String> {
+        Some(match self {
+            Output::Nothing | Output::Applied(_) => return None,
+            Output::Color((r, g, b)) => format!("Color: {r},{g},{b}"),
+            Output::Effect(effect) => format!("Effect: {}", effect.mode()),
+            Output::Brightness(brightness) => format!("Brightness: {brightness}"),
+            Output::Haptics {
+                enabled: true,
+                intensity,
+            } => format!("Haptics: {intensity}"),
+            Output::Haptics { enabled: false, .. } => String::from("Haptics: off"),
+            Output::Sidetone(Some(level)) => format!("Mic monitor: {level}"),
+            Output::Sidetone(None) => String::from("Mic monitor: off"),
+            Output::Voltage(voltage) => format!("Voltage: {voltage} mv"),
+            Output::Battery(battery) => {
+                format!("Battery: {battery}")
+            }
+            Output::Status(status) => status.to_string(),
+            Output::Alert(alert) => alert.to_string(),
+            Output::Set(outcomes) => outcomes
+                .iter()
+                .map(|outcome| {
+                    let value = outcome.setting.output().text().unwrap_or_default();
+                    format!("{value} ({})", outcome.status)
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Output::Apply { changes, .. } if changes.is_empty() => {
+                String::from("Nothing to change")
+            }
+            Output::Apply { changes, .. } => changes
+                .iter()
+                .map(|change| {
+                    let status = match &change.status {
+                        Some(status) => status.to_string(),
+                        None => String::from("planned"),
+                    };
+                    format!(
+                        "{}: {} -> {} ({status})",
+                        change.to.name(),
+                        change.from.value(),
+                        change.to.value()
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Output::Animation(_) | Output::Profile { .. } | Output::ProfileDeleted(_) => {
+                return None;
+            }
+            Output::Profiles(names) => names.join("\n"),
+            Output::ProfileSaved { name, .. } => format!("Saved profile {name}"),
+            Output::Devices(devices) => devices
+                .iter()
+                .map(DeviceInfo::to_string)
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Output::Captured(reports) => reports
+                .iter()
+                .map(captured_text)
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Output::Replayed(replayed) => replayed_text(replayed),
+            Output::Raw { sent, received } => {
+                let mut text = format!("Sent: {}", raw_text(sent));
+                if let Some(received) = received {
+                    text += &format!("\nReceived: {}", raw_text(received));
+                }
+                text
+            }
+            Output::Version(Ok((model, identity))) => {
+                format!("narictl {VERSION}\nModel: {model}\n{identity}")
+            }
+            Output::Version(Err(e)) => format!("narictl {VERSION}\nNo headset: {e}"),
+        })
+    }
+
+    fn json(&self) -> Value {
+        match self {
+            Output::Nothing => Value::Null,
+            Output::Applied(output) => output.json(),
+            Output::Color((r, g, b)) => json!({ "color": [r, g, b] }),
+            Output::Effect(effect) => {
+                json!({ "mode": effect.mode().to_string(), "effect": effect })
+            }
+            Output::Brightness(brightness) => json!({ "brightness": brightness }),
+            Output::Haptics { enabled, intensity } => {
+                json!({ "haptics_enabled": enabled, "haptic_intensity": intensity })
+            }
+            Output::Sidetone(level) => json!({
+                "sidetone_enabled": level.is_some(),
+                "sidetone_level": level.unwrap_or(0),
+            }),
+            Output::Voltage(voltage) => json!({ "voltage_mv": voltage }),
+            Output::Battery(battery) => battery_json(battery),
+            Output::Status(status) => status_json(status),
+            Output::Alert(alert) => json!({
+                "alert": alert.code(),
+                "summary": alert.summary(),
+                "body": alert.body(),
+                "battery": alert.battery().as_ref().map(battery_json),
+            }),
+            Output::Set(outcomes) => {
+                let settings: Vec<Value> = outcomes
+                    .iter()
+                    .map(|outcome| {
+                        let mut value = outcome.setting.output().json();
+                        value["setting"] = json!(outcome.setting.name());
+                        value["status"] = json!(outcome.status.name());
+                        value["error"] = match outcome.status.error() {
+                            Some(e) => json!({ "code": e.code(), "message": e.to_string() }),
+                            None => Value::Null,
+                        };
+                        value
+                    })
+                    .collect();
+                json!({ "settings": settings, "ok": !self.failed() })
+            }
+            Output::Apply { changes, dry_run } => {
+                let changes: Vec<Value> = changes
+                    .iter()
+                    .map(|change| {
+                        json!({
+                            "setting": change.to.name(),
+                            "from": change.from.output().json(),
+                            "to": change.to.output().json(),
+                            "status": change.status.as_ref().map_or("planned", |s| s.name()),
+                            "error": change.status.as_ref().and_then(|s| s.error()).map(|e| {
+                                json!({ "code": e.code(), "message": e.to_string() })
+                            }),
+                        })
+                    })
+                    .collect();
+                json!({ "changes": changes, "dry_run": dry_run, "ok": !self.failed() })
+            }
+            Output::Animation(file) => json!({ "animation": file }),
+            Output::Profiles(names) => json!({ "profiles": names }),
+            Output::Profile { name, profile } | Output::ProfileSaved { name, profile } => {
+                json!({ "name": name, "profile": profile })
+            }
+            Output::ProfileDeleted(name) => json!({ "deleted": name }),
+            Output::Devices(devices) => {
+                let devices: Vec<Value> = devices
+                    .iter()
+                    .map(|info| {
+                        let mut value = json!(info);
+                        value["model"] = json!(info.model().map(|model| model.name));
+                        value
+                    })
+                    .collect();
+                json!({ "devices": devices })
+            }
+            Output::Captured(reports) => {
+                let reports: Vec<Value> = reports
+                    .iter()
+                    .map(|captured| {
+                        json!({
+                            "frame": captured.frame,
+                            "bus": captured.bus,
+                            "address": captured.address,
+                            "request": captured.request,
+                            "label": captured.label(),
+                            "report": raw_json(&captured.bytes),
+                        })
+                    })
+                    .collect();
+                json!({ "reports": reports })
+            }
+            Output::Replayed(replayed) => {
+                let replayed: Vec<Value> = replayed
+                    .iter()
+                    .map(|replayed| {
+                        json!({
+                            "frame": replayed.frame,
+                            "label": replayed.label,
+                            "captured": encode_hex(&replayed.captured),
+                            "emitted": replayed.emitted.as_deref().map(encode_hex),
+                            "matches": replayed.matches(),
+                        })
+                    })
+                    .collect();
+                json!({ "replayed": replayed })
+            }
+            Output::Raw { sent, received } => json!({
+                "sent": raw_json(sent),
+                "received": received.as_deref().map(raw_json),
+            }),
+            Output::Version(Ok((model, identity))) => json!({
+                "version": VERSION,
+                "model": model.name,
+                "dongle": { "firmware": identity.dongle_firmware, "serial": identity.dongle_serial },
+                "headset": { "firmware": identity.headset_firmware, "serial": identity.headset_serial },
+            }),
+            Output::Version(Err(e)) => json!({
+                "version": VERSION,
+                "error": { "code": e.code(), "message": e.to_string() },
+            }),
+        }
+    }
+}
+
+pub fn battery_json(battery: &Battery) -> Value {
+    json!({
+        "voltage_mv": battery.voltage,
+        "battery_pct": battery.percentage,
+        "charging": battery.status == Some(ChargingStatus::Charging),
+        "status": battery.status,
+    })
+}
+
+fn status_json(status: &Status) -> Value {
+    json!({
+        "model": status.model.name,
+        "product_id": status.model.product_id,
+        "port": status.dongle.as_ref().map(DeviceInfo::port),
+        "dongle": {
+            "firmware": status.identity.dongle_firmware,
+            "serial": status.identity.dongle_serial,
+        },
+        "headset": {
+            "firmware": status.identity.headset_firmware,
+            "serial": status.identity.headset_serial,
+        },
+        "connection": status.connection,
+        "battery": status.battery.as_ref().map(battery_json),
+        "effect": status.effect,
+        "mode": status.effect.map(|effect| effect.mode()),
+        "brightness": status.brightness,
+        "haptics_enabled": status.haptics_enabled,
+        "haptic_intensity": status.haptic_intensity,
+        "sidetone_enabled": status.mic_monitor.map(|value| value != MIC_MONITOR_OFF),
+        "sidetone_level": status.mic_monitor.map(mic_monitor_from_raw),
+    })
+}
+
+fn captured_text(captured: &CapturedReport) -> String {
+    let request = match captured.request {
+        ControlRequest::SetReport => "SET_REPORT",
+        ControlRequest::GetReport => "GET_REPORT",
+    };
+    let report = match captured.report() {
+        Ok(report) => {
+            let len = (report.command.data_size as usize).min(ARGUMENTS_LEN);
+            format!(
+                "{report}\n    arguments {}",
+                encode_hex(&report.arguments[..len])
+            )
+        }
+        Err(_) => encode_hex(&captured.bytes),
+    };
+    format!(
+        "#{} {}:{:03} {request} {}: {report}",
+        captured.frame,
+        captured.bus,
+        captured.address,
+        captured.label().unwrap_or("UNKNOWN")
+    )
+}
+
+fn replayed_text(replayed: &[Replayed]) -> String {
+    let mut lines = Vec::new();
+    let (mut matching, mut differing, mut unknown) = (0, 0, 0);
+    for replayed in replayed {
+        let label = replayed.label.unwrap_or("UNKNOWN");
+        match &replayed.emitted {
+            _ if replayed.matches() => {
+                matching += 1;
+                lines.push(format!("#{} {label}: same", replayed.frame));
+            }
+            Some(emitted) => {
+                differing += 1;
+                lines.push(format!(
+                    "#{} {label}: differs\n    captured {}\n    narictl  {}",
+                    replayed.frame,
+                    encode_hex(&replayed.captured),
+                    encode_hex(emitted)
+                ));
+            }
+            None => {
+                unknown += 1;
+                lines.push(format!(
+                    "#{} {label}: no equivalent in narictl\n    captured {}",
+                    replayed.frame,
+                    encode_hex(&replayed.captured)
+                ));
+            }
+        }
+    }
+    lines.push(format!(
+        "{} reports sent: {matching} same, {differing} different, {unknown} without equivalent",
+        replayed.len()
+    ));
+    lines.join("\n")
+}
+
+fn raw_text(bytes: &[u8]) -> String {
+    let header = match Report::parse(bytes) {
+        Ok(report) => report.to_string(),
+        Err(_) => format!("{} bytes", bytes.len()),
+    };
+    format!("{header}\n{}", hex_dump(bytes))
+}
+
+fn raw_json(bytes: &[u8]) -> Value {
+    let header = Report::parse(bytes).ok().map(|report| {
+        json!({
+            "report_id": report.report_id,
+            "transaction_id": report.transaction_id,
+            "direction": report.direction.to_string(),
+            "class": report.command.class,
+            "command": report.command.id,
+            "data_size": report.command.data_size,
+        })
+    });
+    json!({ "hex": encode_hex(bytes), "header": header })
+}
+
+/// Prints an error on stderr in the requested format
+pub fn print_error(format: Format, code: &str, message: &str) {
+    match format {
+        Format::Text => eprintln!("Error: {message}"),
+        Format::Json => eprintln!(
+            "{}",
+            json!({ "error": { "code": code, "message": message } })
+        ),
+    }
+}
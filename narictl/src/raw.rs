@@ -0,0 +1,61 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::cli::RawCommand;
+use crate::output::{Format, Output};
+use narictl_lib::*;
+use std::thread;
+use std::time::Duration;
+
+/// Sends the report of `command` as often as asked, printing every exchange as it happens
+pub fn run<T: Transport>(device: &Device<T>, command: RawCommand, format: Format) -> Result<()> {
+    let (args, query) = match command {
+        RawCommand::Send(args) => (args, false),
+        RawCommand::Query(args) => (args, true),
+    };
+    let report = &args.report.0;
+    for i in 0..args.repeat {
+        if i > 0 {
+            thread::sleep(Duration::from_millis(args.interval));
+        }
+        let transport = device.transport();
+        let received = match query {
+            true => {
+                let mut buffer = [0; REPORT_LEN];
+                let read = transport.exchange(report, &mut buffer)?;
+                Some(buffer[..read].to_vec())
+            }
+            false => {
+                let written = transport.send_report(report)?;
+                if written < report.len() {
+                    return Err(NariError::ShortWrite {
+                        expected: report.len(),
+                        written,
+                    });
+                }
+                None
+            }
+        };
+        Output::Raw {
+            sent: report.clone(),
+            received,
+        }
+        .print(format);
+    }
+    Ok(())
+}
+
+/// Prints 16 bytes per line, prefixed by the offset of the first one
+pub fn hex_dump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(line, chunk)| {
+            let bytes: Vec<String> = chunk.iter().map(|byte| format!("{byte:02x}")).collect();
+            format!("{:04x}: {}", line * 16, bytes.join(" "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
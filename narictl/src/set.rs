@@ -0,0 +1,362 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::cli::{Haptics, SetArgs};
+use crate::output::Output;
+use narictl_lib::*;
+use std::fmt;
+
+/// One setting changed by `narictl set`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    Color((u8, u8, u8)),
+    /// Used by `narictl apply` and to restore the lighting a color replaced
+    Effect(LightingEffect),
+    Brightness(u8),
+    Haptics {
+        enabled: bool,
+        intensity: u8,
+    },
+    /// Raw mic monitor value, `MIC_MONITOR_OFF` when off
+    Sidetone(u8),
+}
+
+impl Setting {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Setting::Color(_) => "color",
+            Setting::Effect(_) => "effect",
+            Setting::Brightness(_) => "brightness",
+            Setting::Haptics { .. } => "haptics",
+            Setting::Sidetone(_) => "sidetone",
+        }
+    }
+
+    /// The value alone, ex: `255,0,0` or `off`
+    pub fn value(&self) -> String {
+        let rgb = |(r, g, b): (u8, u8, u8)| format!("{r},{g},{b}");
+        match *self {
+            Setting::Color(color) => rgb(color),
+            Setting::Effect(LightingEffect::Static(color)) => format!("static {}", rgb(color)),
+            Setting::Effect(LightingEffect::Breathing(Breathing::Single(color))) => {
+                format!("breathing {}", rgb(color))
+            }
+            Setting::Effect(LightingEffect::Breathing(Breathing::Dual(first, second))) => {
+                format!("breathing {} {}", rgb(first), rgb(second))
+            }
+            Setting::Effect(LightingEffect::Breathing(Breathing::Random)) => {
+                String::from("breathing random")
+            }
+            Setting::Effect(effect) => effect.mode().to_string(),
+            Setting::Brightness(brightness) => brightness.to_string(),
+            Setting::Haptics {
+                enabled: false,
+                intensity,
+            } => format!("off (intensity {intensity})"),
+            Setting::Haptics { intensity, .. } => intensity.to_string(),
+            Setting::Sidetone(MIC_MONITOR_OFF) => String::from("off"),
+            Setting::Sidetone(value) => mic_monitor_from_raw(value).to_string(),
+        }
+    }
+
+    /// The value as the single setting commands print it
+    pub fn output(&self) -> Output {
+        match *self {
+            Setting::Color(color) => Output::Color(color),
+            Setting::Effect(effect) => Output::Effect(effect),
+            Setting::Brightness(brightness) => Output::Brightness(brightness),
+            Setting::Haptics { enabled, intensity } => Output::Haptics { enabled, intensity },
+            Setting::Sidetone(MIC_MONITOR_OFF) => Output::Sidetone(None),
+            Setting::Sidetone(value) => Output::Sidetone(Some(mic_monitor_from_raw(value))),
+        }
+    }
+
+    pub fn apply<T: Transport>(&self, device: &Device<T>) -> Result<()> {
+        match *self {
+            Setting::Color((r, g, b)) => device.set_fixed(r, g, b),
+            Setting::Effect(effect) => device.set_effect(effect),
+            Setting::Brightness(brightness) => device.set_brightness(brightness),
+            Setting::Haptics { enabled, intensity } => {
+                device.set_haptic_intensity(intensity, enabled)
+            }
+            Setting::Sidetone(value) => device.set_mic_monitor(value),
+        }
+    }
+
+    /// What applying this setting replaces on the headset
+    fn current<T: Transport>(&self, device: &Device<T>) -> Result<Setting> {
+        Ok(match self {
+            Setting::Color(_) | Setting::Effect(_) => Setting::Effect(device.get_effect()?),
+            Setting::Brightness(_) => Setting::Brightness(device.get_brightness()?),
+            Setting::Haptics { .. } => {
+                let (intensity, enabled) = device.get_haptic_intensity()?;
+                Setting::Haptics { enabled, intensity }
+            }
+            Setting::Sidetone(_) => Setting::Sidetone(device.get_mic_monitor()?),
+        })
+    }
+}
+
+/// What happened to one setting of the batch
+#[derive(Debug)]
+pub enum Status {
+    Applied,
+    Failed(NariError),
+    /// Not sent because an earlier setting failed and the batch was rolled back
+    Skipped,
+    RolledBack,
+    /// Applied, then restoring the previous value failed
+    RollbackFailed(NariError),
+}
+
+impl Status {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::Applied => "applied",
+            Status::Failed(_) => "failed",
+            Status::Skipped => "skipped",
+            Status::RolledBack => "rolled_back",
+            Status::RollbackFailed(_) => "rollback_failed",
+        }
+    }
+
+    pub fn error(&self) -> Option<&NariError> {
+        match self {
+            Status::Failed(e) | Status::RollbackFailed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Applied => write!(f, "applied"),
+            Status::Failed(e) => write!(f, "failed, {e}"),
+            Status::Skipped => write!(f, "skipped"),
+            Status::RolledBack => write!(f, "rolled back"),
+            Status::RollbackFailed(e) => write!(f, "rollback failed, {e}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Outcome {
+    pub setting: Setting,
+    pub status: Status,
+}
+
+/// Applies every setting in `args` on the same device, in the order of `narictl set --help`.
+///
+/// Without `--rollback` a failed setting does not stop the others. With it, the current
+/// values are read before anything is changed and restored in reverse order on the first
+/// failure, nothing is changed when they can not be read.
+pub fn run<T: Transport>(device: &Device<T>, args: SetArgs) -> Result<Vec<Outcome>> {
+    let mut settings = Vec::new();
+    if let Some(color) = args.color {
+        settings.push(Setting::Color(color.into()));
+    }
+    if let Some(brightness) = args.brightness {
+        settings.push(Setting::Brightness(brightness));
+    }
+    match args.haptics {
+        Some(Haptics::Intensity(intensity)) => settings.push(Setting::Haptics {
+            enabled: true,
+            intensity,
+        }),
+        Some(Haptics::Off) => {
+            let (intensity, _) = device.get_haptic_intensity()?;
+            settings.push(Setting::Haptics {
+                enabled: false,
+                intensity,
+            });
+        }
+        None => {}
+    }
+    match args.sidetone {
+        Some(0) => settings.push(Setting::Sidetone(MIC_MONITOR_OFF)),
+        Some(level) => settings.push(Setting::Sidetone(mic_monitor_to_raw(level))),
+        None => {}
+    }
+
+    let previous = match args.rollback {
+        true => settings
+            .iter()
+            .map(|setting| setting.current(device))
+            .collect::<Result<Vec<Setting>>>()?,
+        false => Vec::new(),
+    };
+
+    let mut outcomes: Vec<Outcome> = Vec::with_capacity(settings.len());
+    for setting in settings {
+        let status = match outcomes.last() {
+            Some(Outcome {
+                status: Status::Failed(_) | Status::Skipped,
+                ..
+            }) if args.rollback => Status::Skipped,
+            _ => match setting.apply(device) {
+                Ok(()) => Status::Applied,
+                Err(e) => Status::Failed(e),
+            },
+        };
+        outcomes.push(Outcome { setting, status });
+    }
+
+    let failed = outcomes
+        .iter()
+        .any(|outcome| matches!(outcome.status, Status::Failed(_)));
+    if args.rollback && failed {
+        for (outcome, previous) in outcomes.iter_mut().zip(previous).rev() {
+            if matches!(outcome.status, Status::Applied) {
+                outcome.status = match previous.apply(device) {
+                    Ok(()) => Status::RolledBack,
+                    Err(e) => Status::RollbackFailed(e),
+                };
+            }
+        }
+    }
+    Ok(outcomes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Rgb;
+
+    fn args() -> SetArgs {
+        SetArgs {
+            color: None,
+            brightness: None,
+            haptics: None,
+            sidetone: None,
+            rollback: false,
+        }
+    }
+
+    fn mock() -> Device<MockTransport> {
+        Device::with_transport(MockTransport::new()).with_experimental(false)
+    }
+
+    fn respond(device: &Device<MockTransport>, command: Command, arguments: &[u8]) {
+        let answer = Report::new(Direction::Get, command, arguments).unwrap();
+        device.transport().push_response(answer.to_bytes());
+    }
+
+    fn sent(reports: &[Result<Report>]) -> Vec<Vec<u8>> {
+        reports
+            .iter()
+            .map(|report| report.as_ref().unwrap().to_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn haptics_off_fails_when_the_intensity_can_not_be_read() {
+        let device = mock();
+        let args = SetArgs {
+            brightness: Some(80),
+            haptics: Some(Haptics::Off),
+            ..args()
+        };
+        assert!(matches!(run(&device, args), Err(NariError::Timeout)));
+        // Nothing is changed with an intensity made up
+        assert_eq!(
+            device.transport().sent(),
+            sent(&[Report::get(Command::HAPTICS)])
+        );
+    }
+
+    #[test]
+    fn haptics_off_keeps_the_intensity() {
+        let device = mock();
+        respond(&device, Command::HAPTICS, &[1, 60]);
+        let args = SetArgs {
+            haptics: Some(Haptics::Off),
+            ..args()
+        };
+        let outcomes = run(&device, args).unwrap();
+        assert_eq!(
+            outcomes[0].setting,
+            Setting::Haptics {
+                enabled: false,
+                intensity: 60
+            }
+        );
+        assert_eq!(
+            device.transport().sent()[1],
+            Report::set(Command::HAPTICS, &[0, 60]).unwrap().to_bytes()
+        );
+    }
+
+    #[test]
+    fn a_failure_does_not_stop_the_others() {
+        let device = mock();
+        let args = SetArgs {
+            color: Some(Rgb(255, 0, 0)),
+            brightness: Some(101),
+            haptics: Some(Haptics::Intensity(60)),
+            ..args()
+        };
+        let outcomes = run(&device, args).unwrap();
+        let statuses: Vec<&str> = outcomes.iter().map(|o| o.status.name()).collect();
+        assert_eq!(statuses, ["applied", "failed", "applied"]);
+        assert_eq!(
+            device.transport().sent(),
+            sent(&[
+                Report::set(Command::COLOR, &[255, 0, 0]),
+                Report::set(Command::HAPTICS, &[1, 60]),
+            ])
+        );
+    }
+
+    #[test]
+    fn a_failure_rolls_back_what_was_applied() {
+        let device = mock();
+        // Current values: static green, brightness 50, haptics at 30
+        respond(&device, Command::COLOR, &[0, 255, 0]);
+        respond(&device, Command::BRIGHTNESS, &[50]);
+        respond(&device, Command::HAPTICS, &[1, 30]);
+        let args = SetArgs {
+            color: Some(Rgb(255, 0, 0)),
+            // Refused before anything is sent, like a setting the headset rejects
+            brightness: Some(101),
+            haptics: Some(Haptics::Intensity(60)),
+            rollback: true,
+            ..args()
+        };
+        let outcomes = run(&device, args).unwrap();
+        let statuses: Vec<&str> = outcomes.iter().map(|o| o.status.name()).collect();
+        assert_eq!(statuses, ["rolled_back", "failed", "skipped"]);
+        assert_eq!(
+            device.transport().sent(),
+            sent(&[
+                Report::get(Command::COLOR),
+                Report::get(Command::BRIGHTNESS),
+                Report::get(Command::HAPTICS),
+                Report::set(Command::COLOR, &[255, 0, 0]),
+                Report::set(Command::COLOR, &[0, 255, 0]),
+            ])
+        );
+    }
+
+    #[test]
+    fn nothing_is_changed_when_the_rollback_values_can_not_be_read() {
+        let device = mock();
+        respond(&device, Command::BRIGHTNESS, &[50]);
+        let args = SetArgs {
+            brightness: Some(80),
+            haptics: Some(Haptics::Intensity(60)),
+            rollback: true,
+            ..args()
+        };
+        assert!(matches!(run(&device, args), Err(NariError::Timeout)));
+        assert!(
+            device
+                .transport()
+                .sent()
+                .iter()
+                .all(|report| Report::parse(report).unwrap().direction == Direction::Get)
+        );
+    }
+}
@@ -0,0 +1,373 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::config::FileError;
+use crate::{Device, NariError, Result, Transport};
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Frames are never sent closer together than this, whatever the animation asks for,
+/// so the control pipe stays free for other requests
+pub const MIN_FRAME_INTERVAL: Duration = Duration::from_millis(20);
+const DEFAULT_FPS: f64 = 30.;
+/// Slowest and fastest frame rates taken, faster ones would be limited by `MIN_FRAME_INTERVAL` anyway
+const FPS_RANGE: std::ops::RangeInclusive<f64> = 0.01..=1000.;
+/// Keyframes can be at most a day from the start
+const MAX_KEYFRAME_TIME: f64 = 24. * 60. * 60.;
+
+/// How the color moves from the previous keyframe to this one
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    /// Holds the previous color and jumps at the keyframe, for strobes and blinks
+    Step,
+    /// CSS style cubic bezier curve through (0, 0), (x1, y1), (x2, y2) and (1, 1)
+    CubicBezier(f64, f64, f64, f64),
+}
+
+impl Easing {
+    /// Maps the linear progress `t` (0 to 1) of a segment to the eased progress
+    pub fn apply(self, t: f64) -> f64 {
+        let t = t.clamp(0., 1.);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2. - t),
+            Easing::EaseInOut if t < 0.5 => 2. * t * t,
+            Easing::EaseInOut => -1. + (4. - 2. * t) * t,
+            Easing::Step if t < 1. => 0.,
+            Easing::Step => 1.,
+            Easing::CubicBezier(x1, y1, x2, y2) => cubic_bezier(t, x1, y1, x2, y2),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Keyframe {
+    /// Seconds from the start of the animation
+    pub time: f64,
+    pub color: (u8, u8, u8),
+    #[serde(default)]
+    pub easing: Easing,
+}
+
+/// A keyframe timeline that is played by setting a static color on every frame.
+///
+/// ```toml
+/// fps = 30
+/// loops = 0 # forever
+///
+/// [[keyframes]]
+/// time = 0.0
+/// color = [255, 0, 0]
+///
+/// [[keyframes]]
+/// time = 1.5
+/// color = [0, 0, 255]
+/// easing = "ease-in-out"
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Animation {
+    #[serde(default = "default_fps")]
+    pub fps: f64,
+    /// How many times the timeline is played, 0 repeats it until stopped
+    #[serde(default = "default_loops")]
+    pub loops: u32,
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl Animation {
+    /// Loads an animation from a JSON (`.json`) or TOML (any other extension) file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = FileError::invalid("animation", path);
+        let data = std::fs::read_to_string(path).map_err(|e| file.error(e))?;
+        let animation: Animation = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&data).map_err(|e| file.error(e))?
+        } else {
+            toml::from_str(&data).map_err(|e| file.error(e))?
+        };
+        animation.validate().map_err(|e| file.wrap(e))?;
+        Ok(animation)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.keyframes.is_empty() {
+            return Err(NariError::InvalidArgument(String::from(
+                "an animation needs at least one keyframe",
+            )));
+        }
+        if !FPS_RANGE.contains(&self.fps) {
+            return Err(NariError::InvalidArgument(format!(
+                "fps must be between {} and {}, got {}",
+                FPS_RANGE.start(),
+                FPS_RANGE.end(),
+                self.fps
+            )));
+        }
+        let mut last = 0.;
+        for keyframe in &self.keyframes {
+            // Also false for NaN
+            if !(last..=MAX_KEYFRAME_TIME).contains(&keyframe.time) {
+                return Err(NariError::InvalidArgument(format!(
+                    "keyframe times must start at 0 or later, never go back and be at most \
+                    {MAX_KEYFRAME_TIME} seconds, got {} after {last}",
+                    keyframe.time
+                )));
+            }
+            last = keyframe.time;
+        }
+        Ok(())
+    }
+
+    /// Length of one run through the timeline, `Duration::MAX` for times `validate` refuses
+    pub fn duration(&self) -> Duration {
+        let time = self.keyframes.last().map_or(0., |keyframe| keyframe.time);
+        Duration::try_from_secs_f64(time).unwrap_or(Duration::MAX)
+    }
+
+    /// Time between two frames once the frame rate is limited to `MIN_FRAME_INTERVAL`
+    pub fn frame_interval(&self) -> Duration {
+        Duration::try_from_secs_f64(1. / self.fps).map_or(MIN_FRAME_INTERVAL, |interval| {
+            interval.max(MIN_FRAME_INTERVAL)
+        })
+    }
+
+    /// An animation too long to count its loops never finishes
+    pub fn is_finished(&self, elapsed: Duration) -> bool {
+        self.loops != 0
+            && self
+                .duration()
+                .checked_mul(self.loops)
+                .is_some_and(|total| elapsed >= total)
+    }
+
+    /// Color shown `elapsed` after the animation started
+    pub fn color_at(&self, elapsed: Duration) -> (u8, u8, u8) {
+        let duration = self.duration().as_secs_f64();
+        let mut time = elapsed.as_secs_f64();
+        if self.is_finished(elapsed) {
+            time = duration;
+        } else if duration > 0. {
+            time %= duration;
+        }
+
+        let mut previous = &self.keyframes[0];
+        for keyframe in &self.keyframes[1..] {
+            if time < keyframe.time {
+                let length = keyframe.time - previous.time;
+                let progress = keyframe.easing.apply((time - previous.time) / length);
+                return mix(previous.color, keyframe.color, progress);
+            }
+            previous = keyframe;
+        }
+        previous.color
+    }
+
+    /// Plays the animation on `device` until it finishes or `stop` is set.
+    /// Frames that would repeat the color already shown are skipped.
+    pub fn play<T: Transport>(&self, device: &Device<T>, stop: &AtomicBool) -> Result<()> {
+        self.validate()?;
+        let interval = self.frame_interval();
+        let start = Instant::now();
+        let mut next_frame = start;
+        let mut shown = None;
+
+        while !stop.load(Ordering::Relaxed) {
+            let elapsed = start.elapsed();
+            let color = self.color_at(elapsed);
+            if shown != Some(color) {
+                device.set_fixed(color.0, color.1, color.2)?;
+                shown = Some(color);
+            }
+            if self.is_finished(elapsed) {
+                break;
+            }
+            next_frame += interval;
+            if let Some(wait) = next_frame.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            } else {
+                // Fell behind (slow transfer), drop the missed frames instead of bursting them out
+                next_frame = Instant::now();
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_fps() -> f64 {
+    DEFAULT_FPS
+}
+
+fn default_loops() -> u32 {
+    1
+}
+
+fn mix(from: (u8, u8, u8), to: (u8, u8, u8), progress: f64) -> (u8, u8, u8) {
+    let channel = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * progress).round() as u8;
+    (
+        channel(from.0, to.0),
+        channel(from.1, to.1),
+        channel(from.2, to.2),
+    )
+}
+
+// Solves the curve's x(s) = t with a few Newton steps, then returns y(s)
+fn cubic_bezier(t: f64, x1: f64, y1: f64, x2: f64, y2: f64) -> f64 {
+    let bezier = |s: f64, p1: f64, p2: f64| {
+        3. * (1. - s).powi(2) * s * p1 + 3. * (1. - s) * s * s * p2 + s.powi(3)
+    };
+    let slope = |s: f64, p1: f64, p2: f64| {
+        3. * (1. - s).powi(2) * p1 + 6. * (1. - s) * s * (p2 - p1) + 3. * s * s * (1. - p2)
+    };
+    let mut s = t;
+    for _ in 0..8 {
+        let dx = slope(s, x1, x2);
+        if dx.abs() < 1e-6 {
+            break;
+        }
+        s = (s - (bezier(s, x1, x2) - t) / dx).clamp(0., 1.);
+    }
+    bezier(s, y1, y2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn animation(fps: f64, loops: u32, times: &[f64]) -> Animation {
+        Animation {
+            fps,
+            loops,
+            keyframes: times
+                .iter()
+                .enumerate()
+                .map(|(i, &time)| Keyframe {
+                    time,
+                    color: if i % 2 == 0 { (0, 0, 0) } else { (200, 100, 0) },
+                    easing: Easing::Linear,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn out_of_range_times_are_refused() {
+        for time in [f64::INFINITY, f64::NAN, 1e300, -1., MAX_KEYFRAME_TIME + 1.] {
+            assert!(animation(30., 1, &[0., time]).validate().is_err(), "{time}");
+        }
+        assert!(animation(30., 1, &[1., 0.5]).validate().is_err());
+        assert!(animation(30., 1, &[]).validate().is_err());
+        animation(30., 1, &[0., MAX_KEYFRAME_TIME])
+            .validate()
+            .unwrap();
+    }
+
+    #[test]
+    fn out_of_range_fps_are_refused() {
+        for fps in [0., -1., 1e-320, 0.001, f64::NAN, f64::INFINITY, 1e6] {
+            assert!(animation(fps, 1, &[0., 1.]).validate().is_err(), "{fps}");
+        }
+        animation(0.01, 1, &[0., 1.]).validate().unwrap();
+        animation(1000., 1, &[0., 1.]).validate().unwrap();
+    }
+
+    #[test]
+    fn unvalidated_animations_do_not_panic() {
+        let endless = animation(1e-320, u32::MAX, &[0., f64::INFINITY]);
+        assert_eq!(endless.duration(), Duration::MAX);
+        assert_eq!(endless.frame_interval(), MIN_FRAME_INTERVAL);
+        assert!(!endless.is_finished(Duration::MAX));
+
+        let long = animation(30., u32::MAX, &[0., MAX_KEYFRAME_TIME]);
+        assert!(!long.is_finished(Duration::from_secs(1)));
+        long.color_at(Duration::MAX);
+    }
+
+    #[test]
+    fn frame_interval_is_limited() {
+        assert_eq!(
+            animation(1000., 1, &[0.]).frame_interval(),
+            MIN_FRAME_INTERVAL
+        );
+        assert_eq!(
+            animation(2., 1, &[0.]).frame_interval(),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn colors_are_interpolated_between_keyframes() {
+        let animation = animation(30., 2, &[0., 1., 2.]);
+        assert_eq!(animation.color_at(Duration::ZERO), (0, 0, 0));
+        assert_eq!(animation.color_at(Duration::from_millis(500)), (100, 50, 0));
+        assert_eq!(animation.color_at(Duration::from_secs(1)), (200, 100, 0));
+        assert_eq!(
+            animation.color_at(Duration::from_millis(1500)),
+            (100, 50, 0)
+        );
+        // Second loop
+        assert_eq!(
+            animation.color_at(Duration::from_millis(2500)),
+            (100, 50, 0)
+        );
+        // Finished animations stay on the last keyframe
+        assert!(animation.is_finished(Duration::from_secs(4)));
+        assert_eq!(animation.color_at(Duration::from_secs(9)), (0, 0, 0));
+    }
+
+    #[test]
+    fn easings_keep_their_ends() {
+        let easings = [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+            Easing::Step,
+            Easing::CubicBezier(0.25, 0.1, 0.25, 1.),
+        ];
+        for easing in easings {
+            assert!(easing.apply(0.).abs() < 1e-9, "{easing:?}");
+            assert!((easing.apply(1.) - 1.).abs() < 1e-9, "{easing:?}");
+        }
+        assert_eq!(Easing::Step.apply(0.99), 0.);
+        assert_eq!(Easing::EaseIn.apply(0.5), 0.25);
+        assert_eq!(Easing::EaseOut.apply(0.5), 0.75);
+        let linear = Easing::CubicBezier(0., 0., 1., 1.).apply(0.3);
+        assert!((linear - 0.3).abs() < 1e-3);
+    }
+
+    #[test]
+    fn animations_load_from_toml() {
+        let animation: Animation = toml::from_str(
+            r#"
+            loops = 0
+            [[keyframes]]
+            time = 0.0
+            color = [255, 0, 0]
+            [[keyframes]]
+            time = 1.5
+            color = [0, 0, 255]
+            easing = { cubic-bezier = [0.4, 0.0, 0.2, 1.0] }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(animation.fps, DEFAULT_FPS);
+        assert_eq!(animation.loops, 0);
+        assert_eq!(
+            animation.keyframes[1].easing,
+            Easing::CubicBezier(0.4, 0., 0.2, 1.)
+        );
+        animation.validate().unwrap();
+    }
+}
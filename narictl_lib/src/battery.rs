@@ -0,0 +1,99 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Open circuit voltage (mv) to charge (%) of the single Li-ion cell in the Nari Ultimate,
+/// measured at the light load the headset draws while playing audio. Voltages between two
+/// points are interpolated linearly.
+const DISCHARGE_CURVE: [(u16, u8); 11] = [
+    (3300, 0),
+    (3500, 5),
+    (3600, 10),
+    (3650, 20),
+    (3700, 30),
+    (3750, 40),
+    (3800, 50),
+    (3900, 65),
+    (4000, 80),
+    (4100, 90),
+    (4200, 100),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChargingStatus {
+    Discharging,
+    Charging,
+    Full,
+}
+
+impl fmt::Display for ChargingStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChargingStatus::Discharging => write!(f, "discharging"),
+            ChargingStatus::Charging => write!(f, "charging"),
+            ChargingStatus::Full => write!(f, "full"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Battery {
+    pub voltage: u16,
+    pub percentage: u8,
+    /// `None` when the headset did not answer the charging query, which is still EXPERIMENTAL
+    pub status: Option<ChargingStatus>,
+}
+
+impl fmt::Display for Battery {
+    /// `72% (charging)`, or only `72%` while the charging status is unknown
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}%", self.percentage)?;
+        if let Some(status) = self.status {
+            write!(f, " ({status})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Estimates the remaining charge from the battery voltage using `DISCHARGE_CURVE`
+pub fn battery_percentage(voltage: u16) -> u8 {
+    let (first_mv, first_pct) = DISCHARGE_CURVE[0];
+    if voltage <= first_mv {
+        return first_pct;
+    }
+    for window in DISCHARGE_CURVE.windows(2) {
+        let ((low_mv, low_pct), (high_mv, high_pct)) = (window[0], window[1]);
+        if voltage <= high_mv {
+            let progress = (voltage - low_mv) as u32 * (high_pct - low_pct) as u32;
+            return low_pct + (progress / (high_mv - low_mv) as u32) as u8;
+        }
+    }
+    100
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charge_follows_the_discharge_curve() {
+        assert_eq!(battery_percentage(0), 0);
+        assert_eq!(battery_percentage(3300), 0);
+        assert_eq!(battery_percentage(3800), 50);
+        assert_eq!(battery_percentage(4200), 100);
+        assert_eq!(battery_percentage(4350), 100);
+        // Halfway between 3900 mv (65%) and 4000 mv (80%)
+        assert_eq!(battery_percentage(3950), 72);
+    }
+
+    #[test]
+    fn charge_never_drops_as_the_voltage_rises() {
+        let charges: Vec<u8> = (3000..4400).map(battery_percentage).collect();
+        assert!(charges.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+}
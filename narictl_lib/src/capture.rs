@@ -0,0 +1,564 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+//! Reads the headset's feature reports out of USB captures, to compare what other software
+//! sends (ex: Razer Synapse) with what the library sends.
+//!
+//! Understands usbmon text logs (`cat /sys/kernel/debug/usb/usbmon/3u`) and pcap or pcapng
+//! files recorded by Wireshark, either from usbmon on Linux or from USBPcap on Windows.
+
+use crate::config::FileError;
+use crate::model::is_supported;
+use crate::transport::{
+    GET_REPORT, HID, REPORT_VALUE, REQUEST_TYPE_IN, REQUEST_TYPE_OUT, SET_REPORT,
+};
+use crate::{
+    Command, Device, Direction, LightingEffect, MockTransport, NariError, Report, Result, Target,
+    decode_hex,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+const LINKTYPE_USB_LINUX: u16 = 189;
+const LINKTYPE_USB_LINUX_MMAPPED: u16 = 220;
+const LINKTYPE_USBPCAP: u16 = 249;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const PCAPNG_INTERFACE: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const TRANSFER_CONTROL: u8 = 2;
+const GET_DESCRIPTOR: u8 = 0x06;
+const DESCRIPTOR_DEVICE: u8 = 0x01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// A report sent to the headset
+    SetReport,
+    /// A report read from the headset
+    GetReport,
+}
+
+/// A feature report found in a capture
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CapturedReport {
+    /// Packet carrying the report, counted from 1 like Wireshark does.
+    /// Line number for usbmon text logs.
+    pub frame: usize,
+    pub bus: u16,
+    pub address: u16,
+    pub request: ControlRequest,
+    /// usbmon text logs keep only the first 32 bytes unless told otherwise
+    pub bytes: Vec<u8>,
+}
+
+impl CapturedReport {
+    pub fn report(&self) -> Result<Report> {
+        Report::parse(&self.bytes)
+    }
+
+    /// Name of the command, `None` when the library does not know it
+    pub fn label(&self) -> Option<&'static str> {
+        self.report().ok()?.command.name()
+    }
+}
+
+/// A captured report next to the one the library sends for the same command
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Replayed {
+    pub frame: usize,
+    pub label: Option<&'static str>,
+    pub captured: Vec<u8>,
+    /// `None` when the library has no call for the command
+    pub emitted: Option<Vec<u8>>,
+}
+
+impl Replayed {
+    /// Truncated captures only compare the bytes they kept
+    pub fn matches(&self) -> bool {
+        self.emitted
+            .as_ref()
+            .is_some_and(|emitted| emitted.get(..self.captured.len()) == Some(&self.captured))
+    }
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<CapturedReport>> {
+    let path = path.as_ref();
+    let file = FileError::invalid("capture", path);
+    let data = std::fs::read(path).map_err(|e| file.error(e))?;
+    parse(&data).map_err(|e| file.wrap(e))
+}
+
+/// Finds every feature report exchanged with a supported headset, in the order they were sent.
+/// Captures without the device descriptors of the dongle keep the reports of every device.
+pub fn parse(data: &[u8]) -> Result<Vec<CapturedReport>> {
+    let packets = match data.get(..4) {
+        Some([0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1]) => pcap(data, false)?,
+        Some([0xa1, 0xb2, 0xc3, 0xd4] | [0xa1, 0xb2, 0x3c, 0x4d]) => pcap(data, true)?,
+        Some([0x0a, 0x0d, 0x0d, 0x0a]) => pcapng(data)?,
+        _ => usbmon_text(
+            std::str::from_utf8(data)
+                .map_err(|_| invalid("not a pcap, pcapng or usbmon text capture".to_string()))?,
+        )?,
+    };
+    let transfers = transfers(packets);
+
+    let headsets: Vec<(u16, u16)> = transfers
+        .iter()
+        .filter(|transfer| {
+            transfer.setup[..2] == [0x80, GET_DESCRIPTOR]
+                && transfer.setup[3] == DESCRIPTOR_DEVICE
+                && transfer.data.len() >= 12
+        })
+        .filter(|transfer| {
+            let vendor_id = u16::from_le_bytes([transfer.data[8], transfer.data[9]]);
+            let product_id = u16::from_le_bytes([transfer.data[10], transfer.data[11]]);
+            is_supported(vendor_id, product_id)
+        })
+        .map(|transfer| (transfer.bus, transfer.address))
+        .collect();
+
+    let [value_low, value_high] = REPORT_VALUE.to_le_bytes();
+    let setup = |request_type, request| [request_type, request, value_low, value_high, HID, 0];
+    let mut reports = Vec::new();
+    for transfer in transfers {
+        if !headsets.is_empty() && !headsets.contains(&(transfer.bus, transfer.address)) {
+            continue;
+        }
+        let request = if transfer.setup[..6] == setup(REQUEST_TYPE_OUT, SET_REPORT) {
+            ControlRequest::SetReport
+        } else if transfer.setup[..6] == setup(REQUEST_TYPE_IN, GET_REPORT) {
+            ControlRequest::GetReport
+        } else {
+            continue;
+        };
+        reports.push(CapturedReport {
+            frame: transfer.frame,
+            bus: transfer.bus,
+            address: transfer.address,
+            request,
+            bytes: transfer.data,
+        });
+    }
+    Ok(reports)
+}
+
+/// Sends every captured report through the `Device` call for its command on a `MockTransport`
+pub fn replay(reports: &[CapturedReport]) -> Vec<Replayed> {
+    let device = Device::with_transport(MockTransport::new());
+    reports
+        .iter()
+        .filter(|captured| captured.request == ControlRequest::SetReport)
+        .map(|captured| Replayed {
+            frame: captured.frame,
+            label: captured.label(),
+            captured: captured.bytes.clone(),
+            emitted: captured
+                .report()
+                .ok()
+                .and_then(|report| emit(&device, &report)),
+        })
+        .collect()
+}
+
+/// Returns what the library sends for the command of `report`, calls sending several
+/// reports are narrowed down to the one of the same command
+fn emit(device: &Device<MockTransport>, report: &Report) -> Option<Vec<u8>> {
+    let transport = device.transport();
+    transport.clear();
+    let arguments = &report.arguments;
+    let command = report.command;
+    match report.direction {
+        Direction::Set => {
+            let result = if command.is(Command::COLOR) {
+                device.set_fixed(arguments[0], arguments[1], arguments[2])
+            } else if command.is(Command::BRIGHTNESS) {
+                device.set_brightness(arguments[0])
+            } else if command.is(Command::HAPTICS) {
+                device.set_haptic_intensity(arguments[1], arguments[0] != 0)
+            } else if command.is(Command::MIC_MONITOR) {
+                device.set_mic_monitor(arguments[2])
+            } else if command.is(Command::EFFECT) {
+                LightingEffect::from_arguments(arguments, (0xff, 0xff, 0xff))
+                    .and_then(|effect| device.set_effect(effect))
+            } else {
+                return None;
+            };
+            result.ok()?;
+        }
+        Direction::Get => {
+            // Lets `get_effect` get past the color it reads first, the other getters fail on
+            // the missing answer after sending their query, which is all that is needed here
+            transport.push_response(Report::get(Command::COLOR).ok()?.to_bytes());
+            let _ = if command.is(Command::MIC_MONITOR) {
+                device.get_mic_monitor().map(drop)
+            } else if command.is(Command::HAPTICS) {
+                device.get_haptic_intensity().map(drop)
+            } else if command.is(Command::BRIGHTNESS) {
+                device.get_brightness().map(drop)
+            } else if command.is(Command::COLOR) {
+                device.get_color().map(drop)
+            } else if command.is(Command::EFFECT) {
+                device.get_effect().map(drop)
+            } else if command.is(Command::VOLTAGE) {
+                device.get_voltage().map(drop)
+            } else if command.is(Command::CHARGING) {
+                device.get_charging_status().map(drop)
+            } else if command.is(Command::FIRMWARE_VERSION) {
+                device.get_firmware_version(Target::Headset).map(drop)
+            } else if command.is(Command::SERIAL) {
+                device.get_serial(Target::Headset).map(drop)
+            } else {
+                return None;
+            };
+        }
+        Direction::Other(_) => return None,
+    }
+    let sent = transport.sent();
+    sent.iter()
+        .find(|bytes| Report::parse(bytes).is_ok_and(|sent| sent.command.is(command)))
+        .or(sent.first())
+        .cloned()
+}
+
+/// One control transfer, the setup packet and the data that went with it
+struct Transfer {
+    frame: usize,
+    bus: u16,
+    address: u16,
+    setup: [u8; 8],
+    data: Vec<u8>,
+}
+
+/// A control transfer event on endpoint 0
+struct Packet {
+    frame: usize,
+    /// Pairs the submission of a transfer with its completion
+    id: u64,
+    bus: u16,
+    address: u16,
+    /// Only present on the submission
+    setup: Option<[u8; 8]>,
+    completion: bool,
+    data: Vec<u8>,
+}
+
+/// Pairs submissions with completions. Data sent to the device travels with the submission,
+/// data read from it with the completion.
+fn transfers(packets: Vec<Packet>) -> Vec<Transfer> {
+    let mut pending: HashMap<u64, Transfer> = HashMap::new();
+    let mut transfers = Vec::new();
+    for packet in packets {
+        match (packet.completion, packet.setup) {
+            (false, Some(setup)) => {
+                pending.insert(
+                    packet.id,
+                    Transfer {
+                        frame: packet.frame,
+                        bus: packet.bus,
+                        address: packet.address,
+                        setup,
+                        data: packet.data,
+                    },
+                );
+            }
+            // USBPcap sends the data of an outgoing transfer in a packet of its own
+            (false, None) => {
+                if let Some(transfer) = pending.get_mut(&packet.id) {
+                    transfer.data.extend(packet.data);
+                }
+            }
+            (true, _) => {
+                let Some(mut transfer) = pending.remove(&packet.id) else {
+                    continue;
+                };
+                if transfer.setup[0] & 0x80 != 0 {
+                    transfer.frame = packet.frame;
+                    transfer.data = packet.data;
+                }
+                transfers.push(transfer);
+            }
+        }
+    }
+    transfers.sort_by_key(|transfer| transfer.frame);
+    transfers
+}
+
+fn pcap(data: &[u8], big_endian: bool) -> Result<Vec<Packet>> {
+    let link_type = read_u32(data, 20, big_endian)? as u16;
+    let mut packets = Vec::new();
+    let mut offset = 24;
+    let mut frame = 0;
+    while offset < data.len() {
+        let captured = read_u32(data, offset + 8, big_endian)? as usize;
+        let packet = slice(data, offset + 16, captured)?;
+        frame += 1;
+        packets.extend(link_packet(link_type, frame, packet)?);
+        offset += 16 + captured;
+    }
+    Ok(packets)
+}
+
+fn pcapng(data: &[u8]) -> Result<Vec<Packet>> {
+    let mut big_endian = false;
+    let mut link_types = Vec::new();
+    let mut packets = Vec::new();
+    let mut offset = 0;
+    let mut frame = 0;
+    while offset < data.len() {
+        let block_type = read_u32(data, offset, big_endian)?;
+        if block_type == PCAPNG_SECTION_HEADER {
+            big_endian = slice(data, offset + 8, 4)? == [0x1a, 0x2b, 0x3c, 0x4d];
+            link_types.clear();
+        }
+        let len = read_u32(data, offset + 4, big_endian)? as usize;
+        if len < 12 {
+            return Err(invalid(format!("block of {len} bytes at {offset}")));
+        }
+        let body = slice(data, offset + 8, len - 12)?;
+        match block_type {
+            PCAPNG_INTERFACE => link_types.push(read_u16(body, 0, big_endian)?),
+            PCAPNG_ENHANCED_PACKET => {
+                let interface = read_u32(body, 0, big_endian)? as usize;
+                let captured = read_u32(body, 12, big_endian)? as usize;
+                let link_type = *link_types
+                    .get(interface)
+                    .ok_or_else(|| invalid(format!("packet of unknown interface {interface}")))?;
+                frame += 1;
+                packets.extend(link_packet(link_type, frame, slice(body, 20, captured)?)?);
+            }
+            PCAPNG_SIMPLE_PACKET => {
+                let link_type = *link_types
+                    .first()
+                    .ok_or_else(|| invalid("packet before any interface".to_string()))?;
+                let len = (read_u32(body, 0, big_endian)? as usize).min(body.len() - 4);
+                frame += 1;
+                packets.extend(link_packet(link_type, frame, slice(body, 4, len)?)?);
+            }
+            _ => {}
+        }
+        offset += len;
+    }
+    Ok(packets)
+}
+
+fn link_packet(link_type: u16, frame: usize, data: &[u8]) -> Result<Option<Packet>> {
+    Ok(match link_type {
+        LINKTYPE_USB_LINUX => usbmon_packet(frame, data, 48),
+        LINKTYPE_USB_LINUX_MMAPPED => usbmon_packet(frame, data, 64),
+        LINKTYPE_USBPCAP => usbpcap_packet(frame, data),
+        link_type => {
+            return Err(invalid(format!(
+                "link type {link_type} is not a USB capture"
+            )));
+        }
+    })
+}
+
+/// Packet header of Linux usbmon, in the byte order of the machine that recorded it
+fn usbmon_packet(frame: usize, data: &[u8], header_len: usize) -> Option<Packet> {
+    if data.len() < header_len || data[9] != TRANSFER_CONTROL {
+        return None;
+    }
+    let kind = data[8];
+    let captured = u32::from_le_bytes(data[36..40].try_into().ok()?) as usize;
+    let end = (header_len + captured).min(data.len());
+    Some(Packet {
+        frame,
+        id: u64::from_le_bytes(data[..8].try_into().ok()?),
+        bus: u16::from_le_bytes([data[12], data[13]]),
+        address: data[11] as u16,
+        // A flag of 0 means the setup packet was captured
+        setup: (kind == b'S' && data[14] == 0)
+            .then(|| data[40..48].try_into().ok())
+            .flatten(),
+        completion: kind == b'C',
+        data: data[header_len..end].to_vec(),
+    })
+}
+
+/// Packet header of USBPcap, always little endian
+fn usbpcap_packet(frame: usize, data: &[u8]) -> Option<Packet> {
+    let header_len = u16::from_le_bytes([*data.first()?, *data.get(1)?]) as usize;
+    if header_len < 28 || data.len() < header_len || data[22] != TRANSFER_CONTROL {
+        return None;
+    }
+    // Bit 0 of the info byte is set on the way back from the device
+    let completion = data[16] & 1 != 0;
+    let payload = &data[header_len..];
+    let (setup, payload) = match (data[27], completion) {
+        // Setup stage, followed by the outgoing data in some USBPcap versions
+        (0, false) if payload.len() >= 8 => (Some(payload[..8].try_into().ok()?), &payload[8..]),
+        (1, _) | (3, true) => (None, payload),
+        _ => return None,
+    };
+    Some(Packet {
+        frame,
+        id: u64::from_le_bytes(data[2..10].try_into().ok()?),
+        bus: u16::from_le_bytes([data[17], data[18]]),
+        address: u16::from_le_bytes([data[19], data[20]]),
+        setup,
+        completion,
+        data: payload.to_vec(),
+    })
+}
+
+/// Parses the text format of `/sys/kernel/debug/usb/usbmon/<bus>u`, ex:
+/// `ffff8881 3575914555 S Co:3:007:0 s 21 09 03ff 0005 0040 64 = ff0a00ff 0412f103 7150`
+fn usbmon_text(text: &str) -> Result<Vec<Packet>> {
+    let mut packets = Vec::new();
+    for (line, event) in text.lines().enumerate() {
+        let words: Vec<&str> = event.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        let malformed = || invalid(format!("line {} is not a usbmon event", line + 1));
+        if words.len() < 5 {
+            return Err(malformed());
+        }
+        let id = u64::from_str_radix(words[0], 16).map_err(|_| malformed())?;
+        let completion = match words[2] {
+            "S" => false,
+            "C" => true,
+            // Errors carry no data
+            _ => continue,
+        };
+        // Type and direction, bus, device, endpoint. The older `0t` format has no bus.
+        let address: Vec<&str> = words[3].split(':').collect();
+        let (bus, device) = match address[..] {
+            [kind, bus, device, _] if kind.starts_with('C') => (bus, device),
+            [kind, device, _] if kind.starts_with('C') => ("0", device),
+            _ => continue,
+        };
+        let mut rest = &words[4..];
+        let setup = match rest {
+            ["s", request_type, request, value, index, length, ..] => {
+                let (Some(value), Some(index), Some(length)) =
+                    (swap(value), swap(index), swap(length))
+                else {
+                    return Err(malformed());
+                };
+                let setup = decode_hex(&format!("{request_type}{request}{value}{index}{length}"))?;
+                rest = &rest[6..];
+                Some(setup.try_into().map_err(|_| malformed())?)
+            }
+            _ => {
+                rest = &rest[1..];
+                None
+            }
+        };
+        let data = match rest {
+            [_, "=", words @ ..] => decode_hex(&words.concat())?,
+            _ => Vec::new(),
+        };
+        packets.push(Packet {
+            frame: line + 1,
+            id,
+            bus: bus.parse().map_err(|_| malformed())?,
+            address: device.parse().map_err(|_| malformed())?,
+            setup,
+            completion,
+            data,
+        });
+    }
+    Ok(packets)
+}
+
+/// usbmon prints 16 bit setup fields as numbers, the setup packet holds them little endian.
+/// `None` when the field is not made of ASCII characters.
+fn swap(word: &str) -> Option<String> {
+    match word.len() {
+        _ if !word.is_ascii() => None,
+        4 => Some(format!("{}{}", &word[2..], &word[..2])),
+        _ => Some(word.to_string()),
+    }
+}
+
+fn slice(data: &[u8], offset: usize, len: usize) -> Result<&[u8]> {
+    data.get(offset..offset + len).ok_or_else(|| {
+        invalid(format!(
+            "the capture ends in the middle of a packet at {offset}"
+        ))
+    })
+}
+
+fn read_u16(data: &[u8], offset: usize, big_endian: bool) -> Result<u16> {
+    let bytes = slice(data, offset, 2)?
+        .try_into()
+        .expect("2 bytes were read");
+    Ok(match big_endian {
+        true => u16::from_be_bytes(bytes),
+        false => u16::from_le_bytes(bytes),
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize, big_endian: bool) -> Result<u32> {
+    let bytes = slice(data, offset, 4)?
+        .try_into()
+        .expect("4 bytes were read");
+    Ok(match big_endian {
+        true => u32::from_be_bytes(bytes),
+        false => u32::from_le_bytes(bytes),
+    })
+}
+
+fn invalid(reason: String) -> NariError {
+    NariError::InvalidArgument(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USBMON: &str = "\
+ffff8881 3575914555 S Co:3:007:0 s 21 09 03ff 0005 0040 64 = ff0a00ff 0412f103 7150
+ffff8881 3575915208 C Co:3:007:0 0 64 >
+ffff8882 3575916001 S Ci:3:007:0 s a1 01 03ff 0005 0040 64 <
+ffff8882 3575916912 C Ci:3:007:0 0 64 = ff0a00fd 0412f103 7150
+ffff8883 3575917000 S Co:3:002:0 s 00 09 0001 0000 0000 0
+";
+
+    #[test]
+    fn usbmon_text_logs_are_parsed() {
+        let reports = parse(USBMON.as_bytes()).unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].frame, 1);
+        assert_eq!((reports[0].bus, reports[0].address), (3, 7));
+        assert_eq!(reports[0].request, ControlRequest::SetReport);
+        assert_eq!(reports[0].label(), Some("brightness"));
+        assert_eq!(reports[0].report().unwrap().arguments[0], 0x50);
+        // Data read from the headset travels with the completion
+        assert_eq!(reports[1].frame, 4);
+        assert_eq!(reports[1].request, ControlRequest::GetReport);
+        assert_eq!(reports[1].report().unwrap().direction, Direction::Get);
+    }
+
+    #[test]
+    fn malformed_usbmon_lines_are_rejected() {
+        for line in [
+            "ffff8881 3575914555 S Co:3:007:0 s 21 09 0\u{e9}0 0005 0040 64 = ff0a00ff",
+            "ffff8881 3575914555 S Co:3:007:0 s 21 09 \u{e9}\u{e9} 0005 0040 64",
+            "ffff8881 3575914555 S Co:3:007:0 s 21 09 03ff 0005 zz40 64",
+            "ffff8881 3575914555 S",
+            "not-hex 3575914555 S Co:3:007:0 s 21 09 03ff 0005 0040 64",
+            "ffff8881 3575914555 S Co:3:bus:0 s 21 09 03ff 0005 0040 64",
+        ] {
+            assert!(
+                matches!(parse(line.as_bytes()), Err(NariError::InvalidArgument(_))),
+                "{line}"
+            );
+        }
+        // Not UTF-8 at all
+        assert!(parse(&[0xff, 0xfe, 0x00, 0x01]).is_err());
+    }
+
+    #[test]
+    fn captured_reports_are_replayed() {
+        let replayed = replay(&parse(USBMON.as_bytes()).unwrap());
+        assert_eq!(replayed.len(), 1);
+        assert!(replayed[0].matches());
+    }
+}
@@ -0,0 +1,130 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::{NariError, Result};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+/// `$XDG_CONFIG_HOME/narictl`, or `~/.config/narictl` when it is unset
+pub fn config_dir() -> Result<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".config"))
+            .ok_or_else(|| {
+                NariError::Config(String::from(
+                    "neither XDG_CONFIG_HOME nor HOME is set, can not find the config directory",
+                ))
+            })?,
+    };
+    Ok(base.join("narictl"))
+}
+
+/// Reads a TOML file, a missing file gives `T::default()`
+pub fn load_toml<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    let file = FileError::config(path);
+    match std::fs::read_to_string(path) {
+        Ok(data) => toml::from_str(&data).map_err(|e| file.error(e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(file.error(e)),
+    }
+}
+
+/// Writes `value` as TOML, creating the parent directories
+pub fn save_toml<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let file = FileError::config(path);
+    let data = toml::to_string(value).map_err(|e| file.error(e))?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| file.error(e))?;
+    }
+    std::fs::write(path, data).map_err(|e| file.error(e))
+}
+
+/// Builds the errors about a file, naming it in front of the reason
+pub(crate) struct FileError<'a> {
+    path: &'a Path,
+    what: Option<&'static str>,
+    kind: fn(String) -> NariError,
+}
+
+impl<'a> FileError<'a> {
+    /// Configuration files, reported as `NariError::Config`
+    pub(crate) fn config(path: &'a Path) -> Self {
+        FileError {
+            path,
+            what: None,
+            kind: NariError::Config,
+        }
+    }
+
+    /// Files given by the user, ex: "invalid animation <path>: <reason>"
+    pub(crate) fn invalid(what: &'static str, path: &'a Path) -> Self {
+        FileError {
+            path,
+            what: Some(what),
+            kind: NariError::InvalidArgument,
+        }
+    }
+
+    pub(crate) fn error(&self, reason: impl Display) -> NariError {
+        let path = self.path.display();
+        (self.kind)(match self.what {
+            Some(what) => format!("invalid {what} {path}: {reason}"),
+            None => format!("{path}: {reason}"),
+        })
+    }
+
+    /// Names the file in a validation error about its contents
+    pub(crate) fn wrap(&self, error: NariError) -> NariError {
+        match error {
+            NariError::InvalidArgument(reason) => self.error(reason),
+            e => e,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn toml_files_round_trip() {
+        let dir = std::env::temp_dir().join(format!("narictl-config-{}", std::process::id()));
+        let path = dir.join("nested").join("test.toml");
+        let missing: BTreeMap<String, u8> = load_toml(&path).unwrap();
+        assert!(missing.is_empty());
+
+        let values = BTreeMap::from([(String::from("brightness"), 50u8)]);
+        save_toml(&path, &values).unwrap();
+        assert_eq!(load_toml::<BTreeMap<String, u8>>(&path).unwrap(), values);
+
+        std::fs::write(&path, "brightness = \"high\"").unwrap();
+        let Err(NariError::Config(reason)) = load_toml::<BTreeMap<String, u8>>(&path) else {
+            panic!("an invalid value is a config error");
+        };
+        assert!(reason.starts_with(&path.display().to_string()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file_errors_name_the_file() {
+        let path = Path::new("rainbow.toml");
+        let file = FileError::invalid("animation", path);
+        assert_eq!(
+            file.wrap(NariError::InvalidArgument(String::from("no keyframes"))),
+            NariError::InvalidArgument(String::from(
+                "invalid animation rainbow.toml: no keyframes"
+            ))
+        );
+        assert_eq!(file.wrap(NariError::Timeout), NariError::Timeout);
+        assert_eq!(
+            FileError::config(path).error("missing field"),
+            NariError::Config(String::from("rainbow.toml: missing field"))
+        );
+    }
+}
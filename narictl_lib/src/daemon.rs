@@ -0,0 +1,485 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+//! Line delimited JSON protocol spoken by `narictld` over a Unix socket.
+//!
+//! Every line sent to the daemon is one `Request`, every line sent back is one `Response`.
+//! After a `subscribe` request the connection only carries `Event`s.
+
+use crate::model::{EXPERIMENTAL_FEATURES, FEATURES};
+use crate::{Battery, DeviceInfo, MODELS, NariError, Result, Transport, decode_hex, encode_hex};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::cell::RefCell;
+use std::fs::{self, DirBuilder};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+    // narictl_lib clients tunnel raw reports, the typed requests serve the D-Bus methods and scripts
+    SetColor {
+        color: (u8, u8, u8),
+    },
+    SetBrightness {
+        brightness: u8,
+    },
+    SetHaptics {
+        intensity: u8,
+        enabled: bool,
+    },
+    /// Mic monitor level from 0 (off) to `MIC_MONITOR_MAX_LEVEL`
+    SetSidetone {
+        level: u8,
+    },
+    GetBattery,
+    GetProductId,
+    GetDeviceInfo,
+    Subscribe,
+    /// Raw feature reports, hex encoded
+    Send {
+        report: String,
+    },
+    Receive,
+    Exchange {
+        report: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reply {
+    Done,
+    Battery(Battery),
+    Subscribed,
+    Written(usize),
+    ProductId(u16),
+    DeviceInfo(Option<DeviceInfo>),
+    /// Hex encoded feature report read from the headset
+    Report(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Response {
+    Ok(Reply),
+    Error(ErrorReply),
+}
+
+/// A `NariError` as sent over the socket, the client turns it back into the same kind of error
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorReply {
+    /// `NariError::code()`
+    pub code: String,
+    pub message: String,
+    /// What the code alone does not tell, ex: the reason, the USB error or the missing feature
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub detail: Value,
+}
+
+impl From<&NariError> for ErrorReply {
+    fn from(error: &NariError) -> Self {
+        let detail = match error {
+            NariError::DeviceNotFound | NariError::PermissionDenied | NariError::Timeout => {
+                Value::Null
+            }
+            NariError::DetachKernelDriver(e) | NariError::ClaimInterface(e) | NariError::Usb(e) => {
+                json!(format!("{e:?}"))
+            }
+            NariError::ShortWrite { expected, written } => {
+                json!({ "expected": expected, "written": written })
+            }
+            NariError::Unsupported { model, feature } => {
+                json!({ "model": model, "feature": feature })
+            }
+            NariError::Experimental(feature) => json!(feature),
+            NariError::MalformedResponse(reason)
+            | NariError::InvalidArgument(reason)
+            | NariError::Config(reason)
+            | NariError::Daemon(reason)
+            | NariError::Notification(reason) => json!(reason),
+        };
+        ErrorReply {
+            code: String::from(error.code()),
+            message: error.to_string(),
+            detail,
+        }
+    }
+}
+
+impl From<ErrorReply> for NariError {
+    /// Errors the client does not know, ex: from a newer daemon, keep their message
+    fn from(reply: ErrorReply) -> Self {
+        rebuild_error(&reply).unwrap_or(NariError::Daemon(reply.message))
+    }
+}
+
+fn rebuild_error(reply: &ErrorReply) -> Option<NariError> {
+    let detail = &reply.detail;
+    let reason = || detail.as_str().map(String::from);
+    let usb = || {
+        USB_ERRORS
+            .into_iter()
+            .find(|e| Some(format!("{e:?}").as_str()) == detail.as_str())
+    };
+    Some(match reply.code.as_str() {
+        "device_not_found" => NariError::DeviceNotFound,
+        "permission_denied" => NariError::PermissionDenied,
+        "detach_kernel_driver" => NariError::DetachKernelDriver(usb()?),
+        "claim_interface" => NariError::ClaimInterface(usb()?),
+        "timeout" => NariError::Timeout,
+        "short_write" => NariError::ShortWrite {
+            expected: usize::try_from(detail["expected"].as_u64()?).ok()?,
+            written: usize::try_from(detail["written"].as_u64()?).ok()?,
+        },
+        "malformed_response" => NariError::MalformedResponse(reason()?),
+        "invalid_argument" => NariError::InvalidArgument(reason()?),
+        "unsupported" => NariError::Unsupported {
+            model: MODELS
+                .iter()
+                .find(|model| detail["model"] == model.name)?
+                .name,
+            feature: FEATURES
+                .into_iter()
+                .find(|feature| detail["feature"] == *feature)?,
+        },
+        "experimental" => NariError::Experimental(
+            EXPERIMENTAL_FEATURES
+                .into_iter()
+                .find(|feature| detail == *feature)?,
+        ),
+        "usb" => NariError::Usb(usb()?),
+        "config" => NariError::Config(reason()?),
+        "daemon" => NariError::Daemon(reason()?),
+        "notification" => NariError::Notification(reason()?),
+        _ => return None,
+    })
+}
+
+/// Every `rusb::Error`, sent by their names
+const USB_ERRORS: [rusb::Error; 14] = [
+    rusb::Error::Io,
+    rusb::Error::InvalidParam,
+    rusb::Error::Access,
+    rusb::Error::NoDevice,
+    rusb::Error::NotFound,
+    rusb::Error::Busy,
+    rusb::Error::Timeout,
+    rusb::Error::Overflow,
+    rusb::Error::Pipe,
+    rusb::Error::Interrupted,
+    rusb::Error::NoMem,
+    rusb::Error::NotSupported,
+    rusb::Error::BadDescriptor,
+    rusb::Error::Other,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A setting was changed by one of the daemon's clients
+    StateChanged,
+    Battery {
+        battery: Battery,
+    },
+}
+
+/// `$NARICTL_SOCKET`, or `narictl.sock` in `$XDG_RUNTIME_DIR`.
+///
+/// Without `$XDG_RUNTIME_DIR` the socket goes to a `narictl-<uid>` directory in the temp
+/// directory that only the user may enter, other users can not put a socket of their own there.
+pub fn socket_path() -> Result<PathBuf> {
+    if let Some(path) = std::env::var_os("NARICTL_SOCKET") {
+        return Ok(PathBuf::from(path));
+    }
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir).join("narictl.sock"));
+    }
+    let dir = std::env::temp_dir().join(format!("narictl-{}", current_uid()));
+    private_dir(&dir)?;
+    Ok(dir.join("narictl.sock"))
+}
+
+/// Removes the socket a daemon that did not exit cleanly left at `path`. Anything else found
+/// there, or a socket of another user, is left alone and reported.
+pub fn remove_stale_socket(path: &Path) -> Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(daemon_error(format!("{}: {e}", path.display()))),
+    };
+    if !metadata.file_type().is_socket() || metadata.uid() != current_uid() {
+        return Err(NariError::Daemon(format!(
+            "{} is in the way and is not a socket left behind by narictld",
+            path.display()
+        )));
+    }
+    fs::remove_file(path).map_err(|e| daemon_error(format!("{}: {e}", path.display())))
+}
+
+/// Creates `dir` for the user only, and refuses one that someone else could have prepared
+fn private_dir(dir: &Path) -> Result<()> {
+    match DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(daemon_error(format!("{}: {e}", dir.display()))),
+    }
+    let metadata =
+        fs::symlink_metadata(dir).map_err(|e| daemon_error(format!("{}: {e}", dir.display())))?;
+    if !metadata.is_dir() || metadata.uid() != current_uid() || metadata.mode() & 0o077 != 0 {
+        return Err(NariError::Daemon(format!(
+            "{} must be a directory only this user may enter",
+            dir.display()
+        )));
+    }
+    Ok(())
+}
+
+fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and can not fail
+    unsafe { libc::getuid() }
+}
+
+/// Connection to a running `narictld`.
+///
+/// It is also a `Transport`, so `Device<Client>` works the same as a directly opened headset.
+pub struct Client {
+    stream: RefCell<BufReader<UnixStream>>,
+}
+
+impl Client {
+    pub fn connect() -> Result<Self> {
+        let stream = UnixStream::connect(socket_path()?).map_err(daemon_error)?;
+        Ok(Client {
+            stream: RefCell::new(BufReader::new(stream)),
+        })
+    }
+
+    pub fn request(&self, request: &Request) -> Result<Reply> {
+        self.send_line(request)?;
+        let line = self.read_line()?;
+        log::trace!("narictld: {request:?} -> {}", line.trim_end());
+        match serde_json::from_str(&line).map_err(daemon_error)? {
+            Response::Ok(reply) => Ok(reply),
+            Response::Error(error) => Err(NariError::from(error)),
+        }
+    }
+
+    /// Turns the connection into a stream of events
+    pub fn subscribe(self) -> Result<Events> {
+        match self.request(&Request::Subscribe)? {
+            Reply::Subscribed => Ok(Events { client: self }),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn send_line<T: Serialize>(&self, message: &T) -> Result<()> {
+        let mut line = serde_json::to_string(message).map_err(daemon_error)?;
+        line.push('\n');
+        self.stream
+            .borrow_mut()
+            .get_mut()
+            .write_all(line.as_bytes())
+            .map_err(daemon_error)
+    }
+
+    fn read_line(&self) -> Result<String> {
+        let mut line = String::new();
+        match self.stream.borrow_mut().read_line(&mut line) {
+            Ok(0) => Err(NariError::Daemon(String::from(
+                "the daemon closed the connection",
+            ))),
+            Ok(_) => Ok(line),
+            Err(e) => Err(daemon_error(e)),
+        }
+    }
+}
+
+impl Transport for Client {
+    fn send_report(&self, report: &[u8]) -> Result<usize> {
+        match self.request(&Request::Send {
+            report: encode_hex(report),
+        })? {
+            Reply::Written(written) => Ok(written),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn receive_report(&self, buffer: &mut [u8]) -> Result<usize> {
+        let reply = self.request(&Request::Receive)?;
+        copy_report(reply, buffer)
+    }
+
+    fn product_id(&self) -> Result<u16> {
+        match self.request(&Request::GetProductId)? {
+            Reply::ProductId(product_id) => Ok(product_id),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn device_info(&self) -> Result<Option<DeviceInfo>> {
+        match self.request(&Request::GetDeviceInfo)? {
+            Reply::DeviceInfo(info) => Ok(info),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn exchange(&self, report: &[u8], buffer: &mut [u8]) -> Result<usize> {
+        let reply = self.request(&Request::Exchange {
+            report: encode_hex(report),
+        })?;
+        copy_report(reply, buffer)
+    }
+}
+
+pub struct Events {
+    client: Client,
+}
+
+impl Iterator for Events {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.client.read_line() {
+            Ok(line) => Some(serde_json::from_str(&line).map_err(daemon_error)),
+            Err(_) => None,
+        }
+    }
+}
+
+fn copy_report(reply: Reply, buffer: &mut [u8]) -> Result<usize> {
+    match reply {
+        Reply::Report(hex) => {
+            let report = decode_hex(&hex)?;
+            let len = report.len().min(buffer.len());
+            buffer[..len].copy_from_slice(&report[..len]);
+            Ok(len)
+        }
+        reply => Err(unexpected(reply)),
+    }
+}
+
+fn unexpected(reply: Reply) -> NariError {
+    NariError::Daemon(format!("unexpected reply {reply:?}"))
+}
+
+fn daemon_error<E: std::fmt::Display>(error: E) -> NariError {
+    NariError::Daemon(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Connection, Device, NARI, NARI_ULTIMATE};
+    use std::thread;
+
+    /// Answers like a daemon whose dongle is plugged in while the headset is turned off
+    fn headset_off() -> Client {
+        let (client, server) = UnixStream::pair().unwrap();
+        thread::spawn(move || {
+            let mut writer = server.try_clone().unwrap();
+            for line in BufReader::new(server).lines() {
+                let response = match serde_json::from_str(&line.unwrap()).unwrap() {
+                    Request::GetProductId => {
+                        Response::Ok(Reply::ProductId(NARI_ULTIMATE.product_id))
+                    }
+                    Request::GetDeviceInfo => Response::Ok(Reply::DeviceInfo(None)),
+                    _ => Response::Error(ErrorReply::from(&NariError::Timeout)),
+                };
+                let mut line = serde_json::to_string(&response).unwrap();
+                line.push('\n');
+                writer.write_all(line.as_bytes()).unwrap();
+            }
+        });
+        Client {
+            stream: RefCell::new(BufReader::new(client)),
+        }
+    }
+
+    #[test]
+    fn errors_keep_their_kind() {
+        let errors = [
+            NariError::DeviceNotFound,
+            NariError::PermissionDenied,
+            NariError::DetachKernelDriver(rusb::Error::Busy),
+            NariError::ClaimInterface(rusb::Error::Access),
+            NariError::Timeout,
+            NariError::ShortWrite {
+                expected: 64,
+                written: 12,
+            },
+            NariError::MalformedResponse(String::from("unknown product id 0x0000")),
+            NariError::InvalidArgument(String::from("brightness must be between 0 and 100")),
+            NariError::Unsupported {
+                model: NARI.name,
+                feature: "haptics",
+            },
+            NariError::Experimental(EXPERIMENTAL_FEATURES[0]),
+            NariError::Usb(rusb::Error::Pipe),
+            NariError::Config(String::from("profiles.toml: expected a table")),
+            NariError::Daemon(String::from("the daemon closed the connection")),
+            NariError::Notification(String::from("no notification server")),
+        ];
+        for error in errors {
+            let line = serde_json::to_string(&Response::Error(ErrorReply::from(&error))).unwrap();
+            let Response::Error(reply) = serde_json::from_str(&line).unwrap() else {
+                panic!("{line} is not an error");
+            };
+            assert_eq!(reply.code, error.code());
+            assert_eq!(reply.message, error.to_string());
+            assert_eq!(NariError::from(reply), error);
+        }
+    }
+
+    #[test]
+    fn unknown_errors_keep_their_message() {
+        let line = r#"{"error":{"code":"overheated","message":"The dongle is too hot"}}"#;
+        let Response::Error(reply) = serde_json::from_str(line).unwrap() else {
+            panic!("{line} is not an error");
+        };
+        assert_eq!(
+            NariError::from(reply),
+            NariError::Daemon(String::from("The dongle is too hot"))
+        );
+    }
+
+    #[test]
+    fn only_stale_sockets_are_removed() {
+        let dir = std::env::temp_dir().join(format!("narictl-test-{}", std::process::id()));
+        private_dir(&dir).unwrap();
+        let file = dir.join("file");
+        fs::write(&file, "not a socket").unwrap();
+        assert!(remove_stale_socket(&file).is_err());
+        assert!(file.exists());
+
+        let socket = dir.join("narictl.sock");
+        drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+        remove_stale_socket(&socket).unwrap();
+        assert!(!socket.exists());
+        remove_stale_socket(&socket).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn shared_socket_directories_are_refused() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("narictl-shared-{}", std::process::id()));
+        private_dir(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(private_dir(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn silent_headset_is_off_through_the_daemon() {
+        let device = Device::with_transport(headset_off());
+        assert_eq!(device.get_battery(), Err(NariError::Timeout));
+        let status = device.status().unwrap();
+        assert_eq!(status.connection, Connection::HeadsetOff);
+    }
+}
@@ -0,0 +1,139 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::{ARGUMENTS_LEN, NariError, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Effect ids follow the numbering Razer uses across its Chroma devices. The Nari has a single
+// lighting zone, so the zone based effects (wave, reactive, ripple) are not accepted by its firmware.
+// None of these ids has been seen in a capture of the Nari yet, so breathing and spectrum are
+// only sent with the experimental opt-in, see `experimental_enabled`.
+const EFFECT_NONE: u8 = 0x00;
+const EFFECT_BREATHING: u8 = 0x02;
+const EFFECT_SPECTRUM: u8 = 0x03;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LightingMode {
+    Off,
+    Static,
+    Breathing,
+    Spectrum,
+}
+
+impl LightingMode {
+    pub const ALL: [LightingMode; 4] = [
+        LightingMode::Off,
+        LightingMode::Static,
+        LightingMode::Breathing,
+        LightingMode::Spectrum,
+    ];
+}
+
+impl fmt::Display for LightingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LightingMode::Off => write!(f, "off"),
+            LightingMode::Static => write!(f, "static"),
+            LightingMode::Breathing => write!(f, "breathing"),
+            LightingMode::Spectrum => write!(f, "spectrum"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Breathing {
+    Single((u8, u8, u8)),
+    Dual((u8, u8, u8), (u8, u8, u8)),
+    Random,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LightingEffect {
+    Off,
+    Static((u8, u8, u8)),
+    Breathing(Breathing),
+    Spectrum,
+}
+
+impl LightingEffect {
+    pub fn mode(&self) -> LightingMode {
+        match self {
+            LightingEffect::Off => LightingMode::Off,
+            LightingEffect::Static(_) => LightingMode::Static,
+            LightingEffect::Breathing(_) => LightingMode::Breathing,
+            LightingEffect::Spectrum => LightingMode::Spectrum,
+        }
+    }
+
+    /// Returns the first color used by the effect, if it uses any
+    pub fn color(&self) -> Option<(u8, u8, u8)> {
+        match self {
+            LightingEffect::Static(color)
+            | LightingEffect::Breathing(Breathing::Single(color))
+            | LightingEffect::Breathing(Breathing::Dual(color, _)) => Some(*color),
+            _ => None,
+        }
+    }
+
+    /// Arguments of the `Command::EFFECT` report that starts this effect. Off and static
+    /// only stop the running effect, their color is sent with `Command::COLOR` afterwards.
+    pub(crate) fn to_arguments(self) -> Vec<u8> {
+        match self {
+            LightingEffect::Off | LightingEffect::Static(_) => vec![EFFECT_NONE],
+            LightingEffect::Breathing(Breathing::Single((r, g, b))) => {
+                vec![EFFECT_BREATHING, 0x01, r, g, b]
+            }
+            LightingEffect::Breathing(Breathing::Dual((r1, g1, b1), (r2, g2, b2))) => {
+                vec![EFFECT_BREATHING, 0x02, r1, g1, b1, r2, g2, b2]
+            }
+            LightingEffect::Breathing(Breathing::Random) => vec![EFFECT_BREATHING, 0x00],
+            LightingEffect::Spectrum => vec![EFFECT_SPECTRUM],
+        }
+    }
+
+    /// The effect shown while no firmware effect is running
+    pub(crate) fn from_color(color: (u8, u8, u8)) -> Self {
+        // The headset has no separate "off" mode, lighting is turned off by setting the color to black
+        match color {
+            (0, 0, 0) => LightingEffect::Off,
+            color => LightingEffect::Static(color),
+        }
+    }
+
+    /// Reads the effect back from the arguments of a `Command::EFFECT` response.
+    /// `color` is the static color, used when no firmware effect is running.
+    pub(crate) fn from_arguments(
+        arguments: &[u8; ARGUMENTS_LEN],
+        color: (u8, u8, u8),
+    ) -> Result<Self> {
+        let rgb = |offset: usize| {
+            (
+                arguments[offset],
+                arguments[offset + 1],
+                arguments[offset + 2],
+            )
+        };
+        match arguments[0] {
+            // The headset has no separate "off" mode, lighting is turned off by setting the color to black
+            EFFECT_NONE => Ok(LightingEffect::from_color(color)),
+            EFFECT_BREATHING => match arguments[1] {
+                0x00 => Ok(LightingEffect::Breathing(Breathing::Random)),
+                0x01 => Ok(LightingEffect::Breathing(Breathing::Single(rgb(2)))),
+                0x02 => Ok(LightingEffect::Breathing(Breathing::Dual(rgb(2), rgb(5)))),
+                count => Err(NariError::MalformedResponse(format!(
+                    "breathing effect with {count} colors"
+                ))),
+            },
+            EFFECT_SPECTRUM => Ok(LightingEffect::Spectrum),
+            effect => Err(NariError::MalformedResponse(format!(
+                "unknown lighting effect {effect:#04x}"
+            ))),
+        }
+    }
+}
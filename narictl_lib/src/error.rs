@@ -0,0 +1,123 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, NariError>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NariError {
+    /// No dongle with a matching vendor/product id is plugged in
+    DeviceNotFound,
+    /// The dongle was found but the user may not open it
+    PermissionDenied,
+    /// The kernel HID driver could not be detached from interface 5
+    DetachKernelDriver(rusb::Error),
+    /// Interface 5 could not be claimed, usually because another process holds it
+    ClaimInterface(rusb::Error),
+    /// The headset did not answer in time
+    Timeout,
+    /// Fewer bytes than the full report were written
+    ShortWrite { expected: usize, written: usize },
+    /// The headset answered with something that could not be understood
+    MalformedResponse(String),
+    /// A value passed to the library is out of range
+    InvalidArgument(String),
+    /// The connected model lacks the feature, ex: haptics on a plain Nari
+    Unsupported {
+        model: &'static str,
+        feature: &'static str,
+    },
+    /// The feature uses a command not confirmed on a Nari and `NARICTL_EXPERIMENTAL` is not set
+    Experimental(&'static str),
+    /// Any other USB failure
+    Usb(rusb::Error),
+    /// A configuration file could not be read or written
+    Config(String),
+    /// Talking to `narictld` failed, or the daemon reported an error
+    Daemon(String),
+    /// A desktop notification could not be shown
+    Notification(String),
+}
+
+impl fmt::Display for NariError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NariError::DeviceNotFound => {
+                write!(f, "Headset dongle not found, make sure it is plugged in")
+            }
+            NariError::PermissionDenied => write!(
+                f,
+                "Permission denied while opening the headset. Copy 71-narictl.rules to /usr/lib/udev/rules.d/, \
+                then run `udevadm control --reload-rules && udevadm trigger` as root and replug the dongle"
+            ),
+            NariError::DetachKernelDriver(e) => write!(
+                f,
+                "Failed to detach the kernel driver from the headset ({e})"
+            ),
+            NariError::ClaimInterface(e) => write!(
+                f,
+                "Failed to claim the headset interface ({e}), is another program using the headset?"
+            ),
+            NariError::Timeout => write!(
+                f,
+                "The headset did not respond in time, make sure it is turned on"
+            ),
+            NariError::ShortWrite { expected, written } => write!(
+                f,
+                "Only {written} of {expected} bytes were sent to the headset"
+            ),
+            NariError::MalformedResponse(reason) => write!(
+                f,
+                "Received an unexpected response from the headset: {reason}"
+            ),
+            NariError::InvalidArgument(reason) => write!(f, "Invalid argument: {reason}"),
+            NariError::Unsupported { model, feature } => write!(f, "The {model} has no {feature}"),
+            NariError::Experimental(feature) => write!(
+                f,
+                "{feature} is experimental and uses an unverified command, set NARICTL_EXPERIMENTAL=1 to try it"
+            ),
+            NariError::Usb(e) => write!(f, "Failed to communicate with the headset ({e})"),
+            NariError::Config(reason) => write!(f, "Configuration error: {reason}"),
+            NariError::Daemon(reason) => write!(f, "narictld: {reason}"),
+            NariError::Notification(reason) => write!(f, "Failed to show a notification: {reason}"),
+        }
+    }
+}
+
+impl NariError {
+    /// Stable name of the error kind for scripts, unlike the message it never changes
+    pub fn code(&self) -> &'static str {
+        match self {
+            NariError::DeviceNotFound => "device_not_found",
+            NariError::PermissionDenied => "permission_denied",
+            NariError::DetachKernelDriver(_) => "detach_kernel_driver",
+            NariError::ClaimInterface(_) => "claim_interface",
+            NariError::Timeout => "timeout",
+            NariError::ShortWrite { .. } => "short_write",
+            NariError::MalformedResponse(_) => "malformed_response",
+            NariError::InvalidArgument(_) => "invalid_argument",
+            NariError::Unsupported { .. } => "unsupported",
+            NariError::Experimental(_) => "experimental",
+            NariError::Usb(_) => "usb",
+            NariError::Config(_) => "config",
+            NariError::Daemon(_) => "daemon",
+            NariError::Notification(_) => "notification",
+        }
+    }
+}
+
+impl std::error::Error for NariError {}
+
+impl From<rusb::Error> for NariError {
+    fn from(error: rusb::Error) -> Self {
+        match error {
+            rusb::Error::Timeout => NariError::Timeout,
+            rusb::Error::Access => NariError::PermissionDenied,
+            rusb::Error::NoDevice | rusb::Error::NotFound => NariError::DeviceNotFound,
+            e => NariError::Usb(e),
+        }
+    }
+}
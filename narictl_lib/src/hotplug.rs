@@ -0,0 +1,199 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::Result;
+use crate::model::{VENDOR_ID, is_supported};
+use rusb::{Context, HotplugBuilder, UsbContext};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// How often the dongle is looked for when libusb has no hotplug support,
+/// and how long the hotplug thread waits for events before checking if it should stop
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotplugEvent {
+    Connected,
+    Disconnected,
+}
+
+/// Reports the dongle being plugged in and out.
+///
+/// Uses libusb hotplug callbacks where the platform has them and polls the bus otherwise.
+/// Only changes are reported, a dongle already plugged in when the watcher starts is not.
+pub struct HotplugWatcher {
+    events: Receiver<HotplugEvent>,
+    stop: Arc<AtomicBool>,
+}
+
+impl HotplugWatcher {
+    pub fn new() -> Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        // Opening a context first turns a missing USB stack into an error rather than a panic in has_hotplug
+        let context = Context::new()?;
+        if rusb::has_hotplug() {
+            let registration = HotplugBuilder::new()
+                .vendor_id(VENDOR_ID)
+                .enumerate(false)
+                .register(&context, Box::new(Callback { sender }))?;
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let _registration = registration;
+                while !stop.load(Ordering::Relaxed) {
+                    if let Err(e) = context.handle_events(Some(POLL_INTERVAL)) {
+                        log::warn!("Stopped watching for the headset dongle: {e}");
+                        return;
+                    }
+                }
+            });
+        } else {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let found = || {
+                    context
+                        .devices()
+                        .is_ok_and(|devices| devices.iter().any(|device| is_headset(&device)))
+                };
+                poll(found, POLL_INTERVAL, &sender, &stop);
+            });
+        }
+        Ok(HotplugWatcher { events, stop })
+    }
+
+    /// Waits for the next event, `None` once the watcher thread is gone
+    pub fn recv(&self) -> Option<HotplugEvent> {
+        self.events.recv().ok()
+    }
+
+    /// Returns the next event if one is pending
+    pub fn try_recv(&self) -> Option<HotplugEvent> {
+        self.events.try_recv().ok()
+    }
+}
+
+impl Iterator for HotplugWatcher {
+    type Item = HotplugEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv()
+    }
+}
+
+impl Drop for HotplugWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+struct Callback {
+    sender: Sender<HotplugEvent>,
+}
+
+impl rusb::Hotplug<Context> for Callback {
+    fn device_arrived(&mut self, device: rusb::Device<Context>) {
+        if is_headset(&device) {
+            let _ = self.sender.send(HotplugEvent::Connected);
+        }
+    }
+
+    fn device_left(&mut self, device: rusb::Device<Context>) {
+        if is_headset(&device) {
+            let _ = self.sender.send(HotplugEvent::Disconnected);
+        }
+    }
+}
+
+// libusb keeps the device descriptor cached, so it is safe to read from a hotplug callback
+fn is_headset(device: &rusb::Device<Context>) -> bool {
+    device
+        .device_descriptor()
+        .is_ok_and(|desc| is_supported(desc.vendor_id(), desc.product_id()))
+}
+
+/// Calls `found` every `interval` and reports when its answer changes
+fn poll(
+    mut found: impl FnMut() -> bool,
+    interval: Duration,
+    sender: &Sender<HotplugEvent>,
+    stop: &AtomicBool,
+) {
+    let mut plugged_in = found();
+    loop {
+        thread::sleep(interval);
+        if stop.load(Ordering::Relaxed) {
+            return;
+        }
+        let found = found();
+        if found != plugged_in {
+            let event = if found {
+                HotplugEvent::Connected
+            } else {
+                HotplugEvent::Disconnected
+            };
+            if sender.send(event).is_err() {
+                return;
+            }
+            plugged_in = found;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Events sent while the dongle is found or not in turn, as told by `plugged_in`
+    fn events(plugged_in: &[bool]) -> Vec<HotplugEvent> {
+        let (sender, events) = mpsc::channel();
+        let stop = AtomicBool::new(false);
+        let mut plugged_in = VecDeque::from(plugged_in.to_vec());
+        let found = || {
+            let found = plugged_in.pop_front().unwrap();
+            if plugged_in.is_empty() {
+                stop.store(true, Ordering::Relaxed);
+            }
+            found
+        };
+        poll(found, Duration::ZERO, &sender, &stop);
+        drop(sender);
+        events.iter().collect()
+    }
+
+    #[test]
+    fn a_dongle_plugged_in_at_start_is_not_reported() {
+        assert_eq!(events(&[true, true]), []);
+        assert_eq!(events(&[false, false]), []);
+    }
+
+    #[test]
+    fn only_changes_are_reported() {
+        assert_eq!(
+            events(&[false, true, true, false, false, true]),
+            [
+                HotplugEvent::Connected,
+                HotplugEvent::Disconnected,
+                HotplugEvent::Connected
+            ]
+        );
+    }
+
+    #[test]
+    fn polling_stops_once_nobody_listens() {
+        let (sender, events) = mpsc::channel();
+        drop(events);
+        let mut calls = 0;
+        let found = || {
+            calls += 1;
+            calls > 1
+        };
+        poll(found, Duration::ZERO, &sender, &AtomicBool::new(false));
+        assert_eq!(calls, 2);
+    }
+}
@@ -3,157 +3,668 @@ Copyright d22592 and contributors
 SPDX-License-Identifier: GPL-3.0-or-later
 */
 
-use rusb::{Context, DeviceHandle, Error, UsbContext};
-use std::time::Duration;
+mod animation;
+mod battery;
+pub mod capture;
+mod config;
+#[cfg(unix)]
+pub mod daemon;
+mod effect;
+mod error;
+mod hotplug;
+mod model;
+mod monitor;
+mod profile;
+mod report;
+mod selector;
+mod state;
+mod status;
+mod transport;
 
-const HID: u8 = 5;
-const VID: u16 = 0x1532;
-const PID: u16 = 0x051a;
-const TIMEOUT: Duration = Duration::from_secs(4);
+pub use animation::{Animation, Easing, Keyframe, MIN_FRAME_INTERVAL};
+pub use battery::{Battery, ChargingStatus, battery_percentage};
+pub use effect::{Breathing, LightingEffect, LightingMode};
+pub use error::{NariError, Result};
+pub use hotplug::{HotplugEvent, HotplugWatcher};
+pub use model::{
+    Capabilities, MODELS, Model, NARI, NARI_ESSENTIAL, NARI_ULTIMATE, VENDOR_ID,
+};
+pub use monitor::{Alert, BatteryMonitor, DEFAULT_MONITOR_INTERVAL, DEFAULT_THRESHOLDS};
+pub use config::{config_dir, load_toml, save_toml};
+pub use profile::{DesiredState, Profile, Profiles};
+pub use report::{
+    ARGUMENTS_LEN, Command, Direction, HEADER_LEN, Report, decode_hex, encode_hex,
+};
+pub use selector::{DeviceInfo, DeviceSelector};
+pub use state::{
+    DEFAULT_MIC_MONITOR_LEVEL, DeviceState, MIC_MONITOR_END, MIC_MONITOR_MAX_LEVEL,
+    MIC_MONITOR_OFF, mic_monitor_from_raw, mic_monitor_to_raw,
+};
+pub use status::{Connection, Identity, Status, Target};
+pub use transport::{MockTransport, REPORT_LEN, Transport, UsbTransport};
 
-pub struct Device {
-    hid_handle: DeviceHandle<Context>,
+use model::{CHARGING_STATUS, EFFECTS, HEADSET_IDENTITY};
+use std::cell::OnceCell;
+use transport::check_written;
+
+pub struct Device<T: Transport = UsbTransport> {
+    transport: T,
+    model: OnceCell<&'static Model>,
+    /// Whether the commands not confirmed on a Nari yet may be sent
+    experimental: bool,
+}
+
+/// `NARICTL_EXPERIMENTAL=1` opts into the commands taken from other Razer devices and never
+/// seen in a capture of the Nari, each of them is marked as unverified in `Command`
+pub fn experimental_enabled() -> bool {
+    std::env::var_os("NARICTL_EXPERIMENTAL").is_some_and(|value| value == "1")
 }
 
 impl Device {
-    pub fn init() -> Result<Self, String> {
-        match Context::new() {
-            Ok(mut context) => match open_device(&mut context) {
-                Some(handle) => {
-                    if handle.kernel_driver_active(HID).is_ok_and(|yes| yes) {
-                        if let Err(e) = handle.detach_kernel_driver(HID) {
-                            if e != Error::NotSupported && e != Error::NotFound {
-                                return Err(String::from("Failed to detach kernel driver"));
-                            }
-                        }
-                    }
-                    if handle.claim_interface(HID).is_ok() {
-                        Ok(Device { hid_handle: handle })
-                    } else {
-                        Err(String::from("Failed to claim interface"))
-                    }
-                }
-                None => Err(format!("Failed to find device {VID:04x}:{PID:04x}")),
-            },
-            Err(_) => Err(String::from("Failed to open device")),
+    pub fn init() -> Result<Self> {
+        Self::open(DeviceSelector::First)
+    }
+
+    /// Opens the headset matching `selector` directly, without going through `narictld`
+    pub fn open(selector: DeviceSelector) -> Result<Self> {
+        Ok(Device::with_transport(UsbTransport::open_selected(
+            selector,
+        )?))
+    }
+
+    /// Lists every headset dongle plugged in
+    pub fn enumerate() -> Result<Vec<DeviceInfo>> {
+        selector::enumerate()
+    }
+}
+
+impl Device<Box<dyn Transport + Send>> {
+    /// Goes through `narictld` when it is running, otherwise opens the headset directly
+    pub fn connect() -> Result<Self> {
+        #[cfg(unix)]
+        if let Ok(client) = daemon::Client::connect() {
+            return Ok(Device::with_transport(Box::new(client)));
+        }
+        Ok(Device::with_transport(Box::new(UsbTransport::open()?)))
+    }
+
+    /// Like `connect`, but any other selector than `First` opens that headset directly,
+    /// since the daemon only shares the one it opened
+    pub fn connect_selected(selector: DeviceSelector) -> Result<Self> {
+        match selector {
+            DeviceSelector::First => Self::connect(),
+            selector => Ok(Device::with_transport(Box::new(
+                UsbTransport::open_selected(selector)?,
+            ))),
+        }
+    }
+
+    /// Like `connect`, but opens the headset on first use instead of failing when it is not there yet
+    pub fn connect_lazy() -> Self {
+        #[cfg(unix)]
+        if let Ok(client) = daemon::Client::connect() {
+            return Device::with_transport(Box::new(client));
+        }
+        Device::with_transport(Box::new(UsbTransport::new()))
+    }
+}
+
+impl<T: Transport> Device<T> {
+    pub fn with_transport(transport: T) -> Self {
+        Device {
+            transport,
+            model: OnceCell::new(),
+            experimental: experimental_enabled(),
+        }
+    }
+
+    /// Allows or refuses the unverified commands whatever `NARICTL_EXPERIMENTAL` says
+    pub fn with_experimental(self, experimental: bool) -> Self {
+        Device {
+            experimental,
+            ..self
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Model of the connected headset, asked once and remembered
+    pub fn model(&self) -> Result<&'static Model> {
+        if let Some(model) = self.model.get() {
+            return Ok(model);
+        }
+        let product_id = self.transport.product_id()?;
+        let model = Model::from_product_id(product_id).ok_or_else(|| {
+            NariError::MalformedResponse(format!("unknown product id {product_id:#06x}"))
+        })?;
+        Ok(self.model.get_or_init(|| model))
+    }
+
+    pub fn capabilities(&self) -> Result<Capabilities> {
+        Ok(self.model()?.capabilities)
+    }
+
+    /// Fails with `NariError::Unsupported` when the model lacks `feature`
+    fn require(&self, feature: &'static str, has: fn(&Capabilities) -> bool) -> Result<()> {
+        let model = self.model()?;
+        if !has(&model.capabilities) {
+            return Err(NariError::Unsupported {
+                model: model.name,
+                feature,
+            });
+        }
+        Ok(())
+    }
+
+    /// Fails with `NariError::Experimental` unless the unverified commands were opted into
+    fn require_experimental(&self, feature: &'static str) -> Result<()> {
+        match self.experimental {
+            true => Ok(()),
+            false => Err(NariError::Experimental(feature)),
         }
     }
 
     // GENERAL
-    pub fn set_mic_monitor(&self, mut value: u8) -> Result<usize, Error> {
-        if !(0xc0..0xec).contains(&value) {
-            value = 0xc0;
+    pub fn set_mic_monitor(&self, value: u8) -> Result<()> {
+        self.require("mic monitoring", |c| c.sidetone)?;
+        if !(MIC_MONITOR_OFF..MIC_MONITOR_END).contains(&value) {
+            return Err(NariError::InvalidArgument(format!(
+                "mic monitor value {value:#04x} is outside {MIC_MONITOR_OFF:#04x}..{MIC_MONITOR_END:#04x}"
+            )));
+        }
+        self.send(&Report::set(Command::MIC_MONITOR, &[0x04, 0x00, value])?)
+    }
+
+    pub fn set_haptic_intensity(&self, percentage: u8, enable: bool) -> Result<()> {
+        self.require("haptics", |c| c.haptics)?;
+        check_percentage("haptic intensity", percentage)?;
+        self.send(&Report::set(Command::HAPTICS, &[enable as u8, percentage])?)
+    }
+
+    // LIGHTING
+    pub fn set_brightness(&self, percentage: u8) -> Result<()> {
+        self.require("lighting", Capabilities::lighting)?;
+        check_percentage("brightness", percentage)?;
+        self.send(&Report::set(Command::BRIGHTNESS, &[percentage])?)
+    }
+
+    pub fn set_off(&self) -> Result<()> {
+        self.set_fixed(0, 0, 0)
+    }
+
+    pub fn set_fixed(&self, r: u8, g: u8, b: u8) -> Result<()> {
+        self.require("lighting", Capabilities::lighting)?;
+        self.send(&Report::set(Command::COLOR, &[r, g, b])?)
+    }
+
+    /// Only the animated effects use the unverified EFFECT command, they need the experimental
+    /// opt-in, see `effect.rs`
+    pub fn set_effect(&self, effect: LightingEffect) -> Result<()> {
+        match effect {
+            LightingEffect::Off => self.set_off(),
+            LightingEffect::Static((r, g, b)) => self.set_fixed(r, g, b),
+            _ => {
+                self.require("lighting", Capabilities::lighting)?;
+                self.require_experimental(EFFECTS)?;
+                self.send(&Report::set(Command::EFFECT, &effect.to_arguments())?)
+            }
         }
-        let result = decode(format!("ff0a00ff040ef105010400{value:02x}")).unwrap();
-        self.hid_handle
-            .write_control(0x21, 9, 0x03ff, 5, &result, TIMEOUT)
     }
 
-    pub fn set_haptic_intensity(&self, mut percentage: u8, enable: bool) -> Result<usize, Error> {
-        if percentage > 100 {
-            percentage = 0;
+    /// Sends every setting of `state` the model has to the headset
+    pub fn apply_state(&self, state: &DeviceState) -> Result<()> {
+        let capabilities = self.capabilities()?;
+        if capabilities.lighting() {
+            self.set_effect(state.effect)?;
+            self.set_brightness(state.brightness)?;
+        }
+        if capabilities.haptics {
+            self.set_haptic_intensity(state.haptic_intensity, state.haptics_enabled)?;
         }
-        let result = decode(format!(
-            "ff0a00ff0402f10620{:02x}{:02x}",
-            enable as u8, percentage
+        if capabilities.sidetone {
+            self.set_mic_monitor(state.mic_monitor)?;
+        }
+        Ok(())
+    }
+
+    // READING (Reading data from the headset is experimental, the headset is assumed to
+    // answer with the value at the same place the matching setter writes it)
+    pub fn get_mic_monitor(&self) -> Result<u8> {
+        self.require("mic monitoring", |c| c.sidetone)?;
+        let response = self.query(&Report::get(Command::MIC_MONITOR)?)?;
+        let value = response.arguments[2];
+        if !(MIC_MONITOR_OFF..MIC_MONITOR_END).contains(&value) {
+            return Err(NariError::MalformedResponse(format!(
+                "mic monitor value {value:#04x} is out of range"
+            )));
+        }
+        Ok(value)
+    }
+
+    /// Returns the haptic intensity and whether haptics are enabled
+    pub fn get_haptic_intensity(&self) -> Result<(u8, bool)> {
+        self.require("haptics", |c| c.haptics)?;
+        let response = self.query(&Report::get(Command::HAPTICS)?)?;
+        let percentage = check_response_percentage("haptic intensity", response.arguments[1])?;
+        Ok((percentage, response.arguments[0] != 0))
+    }
+
+    pub fn get_brightness(&self) -> Result<u8> {
+        self.require("lighting", Capabilities::lighting)?;
+        let response = self.query(&Report::get(Command::BRIGHTNESS)?)?;
+        check_response_percentage("brightness", response.arguments[0])
+    }
+
+    pub fn get_color(&self) -> Result<(u8, u8, u8)> {
+        self.require("lighting", Capabilities::lighting)?;
+        let response = self.query(&Report::get(Command::COLOR)?)?;
+        Ok((
+            response.arguments[0],
+            response.arguments[1],
+            response.arguments[2],
         ))
-        .unwrap();
-        self.hid_handle
-            .write_control(0x21, 9, 0x03ff, 5, &result, TIMEOUT)
     }
 
-    // LIGHTING
-    pub fn set_brightness(&self, mut percentage: u8) -> Result<usize, Error> {
-        if percentage > 100 {
-            percentage = 0;
+    /// Without the experimental opt-in only the color is read, so the lighting is static or off
+    pub fn get_effect(&self) -> Result<LightingEffect> {
+        let color = self.get_color()?;
+        if !self.experimental {
+            return Ok(LightingEffect::from_color(color));
         }
-        let result = decode(format!("ff0a00ff0412f10371{percentage:02x}")).unwrap();
-        self.hid_handle
-            .write_control(0x21, 9, 0x03ff, 5, &result, TIMEOUT)
+        let response = self.query(&Report::get(Command::EFFECT)?)?;
+        LightingEffect::from_arguments(&response.arguments, color)
     }
 
-    pub fn set_off(&self) -> Result<usize, Error> {
-        let result = decode("ff0a00ff0412f10572").unwrap();
-        self.hid_handle
-            .write_control(0x21, 9, 0x03ff, 5, &result, TIMEOUT)
+    pub fn get_lighting_mode(&self) -> Result<LightingMode> {
+        Ok(self.get_effect()?.mode())
     }
 
-    pub fn set_fixed(&self, r: u8, g: u8, b: u8) -> Result<usize, Error> {
-        let result = decode(format!("ff0a00ff0412f10572{r:02x}{g:02x}{b:02x}")).unwrap();
-        self.hid_handle
-            .write_control(0x21, 9, 0x03ff, 5, &result, TIMEOUT)
+    /// Settings the model lacks are reported as off
+    pub fn state(&self) -> Result<DeviceState> {
+        let capabilities = self.capabilities()?;
+        let (haptic_intensity, haptics_enabled) = match capabilities.haptics {
+            true => self.get_haptic_intensity()?,
+            false => (0, false),
+        };
+        let (effect, brightness) = match capabilities.lighting() {
+            true => (self.get_effect()?, self.get_brightness()?),
+            false => (LightingEffect::Off, 0),
+        };
+        Ok(DeviceState {
+            effect,
+            brightness,
+            haptics_enabled,
+            haptic_intensity,
+            mic_monitor: match capabilities.sidetone {
+                true => self.get_mic_monitor()?,
+                false => MIC_MONITOR_OFF,
+            },
+        })
     }
 
-    // EXTRAS (Reading data from the headset is experimental)
-    pub fn get_voltage(&self) -> Result<u16, Error> {
-        let result = decode("ff0a00fd0412f10205").unwrap();
-        self.hid_handle
-            .write_control(0x21, 9, 0x03ff, 5, &result, TIMEOUT)?;
+    // EXTRAS
+    pub fn get_voltage(&self) -> Result<u16> {
+        self.require("battery", |c| c.battery)?;
+        // The headset sometimes answers the first query with 0, so ask once more before giving up
+        for _ in 0..2 {
+            let response = self.query(&Report::get(Command::VOLTAGE)?)?;
+            let voltage = u16::from_be_bytes([response.arguments[3], response.arguments[4]]);
+            if voltage != 0 {
+                return Ok(voltage);
+            }
+            log::debug!("The headset reported a voltage of 0 mv");
+        }
+        Err(NariError::MalformedResponse(String::from(
+            "the headset reported a voltage of 0 mv",
+        )))
+    }
 
-        let mut voltage_res = [0; 64];
-        self.hid_handle
-            .read_control(0xa1, 1, 0x03ff, 5, &mut voltage_res, TIMEOUT)?;
+    /// Charging, full or discharging (EXPERIMENTAL). The command is unverified and needs the
+    /// experimental opt-in, the flag is answered like the voltage, right after the three echoed
+    /// argument bytes
+    pub fn get_charging_status(&self) -> Result<ChargingStatus> {
+        self.require("battery", |c| c.battery)?;
+        self.require_experimental(CHARGING_STATUS)?;
+        let response = self.query(&Report::get(Command::CHARGING)?)?;
+        match response.arguments[3] {
+            0x00 => Ok(ChargingStatus::Discharging),
+            0x01 => Ok(ChargingStatus::Charging),
+            0x02 => Ok(ChargingStatus::Full),
+            value => Err(NariError::MalformedResponse(format!(
+                "unknown charging status {value:#04x}"
+            ))),
+        }
+    }
 
-        let result = u16::from_be_bytes([voltage_res[12], voltage_res[13]]);
+    /// Voltage and charge, plus the charging status when the headset answers that query too
+    pub fn get_battery(&self) -> Result<Battery> {
+        let voltage = self.get_voltage()?;
+        let status = self
+            .get_charging_status()
+            .inspect_err(|e| log::debug!("The charging status is unknown: {e}"))
+            .ok();
+        Ok(Battery {
+            voltage,
+            percentage: battery_percentage(voltage),
+            status,
+        })
+    }
 
-        if result == 0 {
-            let trying_again = self.get_voltage()?;
-            if trying_again == 0 {
-                return Err(Error::Other)
+    /// The dongle's version comes from its USB descriptor, the headset is asked with an
+    /// unverified command that needs the experimental opt-in
+    pub fn get_firmware_version(&self, target: Target) -> Result<String> {
+        match target {
+            Target::Dongle => Ok(self.dongle_info()?.firmware),
+            Target::Headset => {
+                self.require_experimental(HEADSET_IDENTITY)?;
+                let response = self.query(&Report::get(Command::FIRMWARE_VERSION)?)?;
+                Ok(format!("{}.{}", response.arguments[0], response.arguments[1]))
             }
-            return Ok(trying_again)
         }
-        Ok(result)
     }
-}
 
-impl Drop for Device {
-    fn drop(&mut self) {
-        if self.hid_handle.release_interface(HID).is_ok() {
-            if let Err(e) = self.hid_handle.attach_kernel_driver(HID) {
-                if e != Error::NotFound {
-                    eprintln!("[WARN]: Failed to attach kernel driver");
+    /// The dongle's serial comes from its USB descriptor, the headset is asked with an
+    /// unverified command that needs the experimental opt-in
+    pub fn get_serial(&self, target: Target) -> Result<String> {
+        match target {
+            Target::Dongle => self.dongle_info()?.serial.ok_or_else(|| {
+                NariError::MalformedResponse(String::from("the dongle has no serial number"))
+            }),
+            Target::Headset => {
+                self.require_experimental(HEADSET_IDENTITY)?;
+                let response = self.query(&Report::get(Command::SERIAL)?)?;
+                let serial: String = response.arguments[..Command::SERIAL.data_size as usize]
+                    .iter()
+                    .take_while(|&&byte| byte != 0)
+                    .map(|&byte| byte as char)
+                    .collect();
+                if serial.is_empty() || !serial.chars().all(|c| c.is_ascii_graphic()) {
+                    return Err(NariError::MalformedResponse(format!(
+                        "{serial:?} is not a serial number"
+                    )));
                 }
+                Ok(serial)
             }
-        } else {
-            eprintln!("[WARN]: Failed to release interface");
         }
     }
-}
 
-fn decode<T: Into<String>>(data: T) -> Option<Vec<u8>> {
-    let mut vector = Vec::new();
-    let data = data.into();
+    fn dongle_info(&self) -> Result<DeviceInfo> {
+        self.transport.device_info()?.ok_or(NariError::DeviceNotFound)
+    }
 
-    for bytes in data.as_bytes().chunks(2) {
-        let chunk = std::str::from_utf8(bytes).expect("Failed to decode string, must be UTF-8");
-        let new_byte = u8::from_str_radix(chunk, 16).expect("Unable to convert chunk to int");
-        vector.push(new_byte);
+    fn send(&self, report: &Report) -> Result<()> {
+        log::debug!("Sending {report}");
+        let bytes = report.to_bytes();
+        check_written(bytes.len(), self.transport.send_report(&bytes)?)
     }
-    if vector.is_empty() || vector.len() < data.len() / 2 || data.len() % 2 != 0 {
-        None
-    } else {
-        while vector.len() != 64 {
-            vector.push(0);
+
+    fn query(&self, report: &Report) -> Result<Report> {
+        log::debug!("Querying {report}");
+        let mut buffer = [0; REPORT_LEN];
+        let read = self.transport.exchange(&report.to_bytes(), &mut buffer)?;
+        if read < buffer.len() {
+            return Err(NariError::MalformedResponse(format!(
+                "expected {} bytes but received {read}",
+                buffer.len()
+            )));
         }
-        Some(vector)
+        let response = Report::parse(&buffer)?;
+        let len = (response.command.data_size as usize).min(ARGUMENTS_LEN);
+        log::debug!(
+            "Answered {response}, arguments {}",
+            encode_hex(&response.arguments[..len])
+        );
+        Ok(response)
     }
 }
 
-fn open_device(context: &mut Context) -> Option<DeviceHandle<Context>> {
-    match context.devices() {
-        Ok(device_list) => for device in device_list.iter() {
-            if let Ok(device_desc) = device.device_descriptor() {
-                if device_desc.vendor_id() == VID && device_desc.product_id() == PID {
-                    println!("Found headset, attempting to open device");
-                    match device.open() {
-                        Ok(handle) => return Some(handle),
-                        Err(e) => eprintln!("Device found but failed to open: {e}"),
-                    }
-                }
-            }
-        }
-        Err(e) => eprintln!("Failed to get device list: {e}"),
+fn check_percentage(name: &str, percentage: u8) -> Result<()> {
+    if percentage > 100 {
+        return Err(NariError::InvalidArgument(format!(
+            "{name} must be between 0 and 100, got {percentage}"
+        )));
+    }
+    Ok(())
+}
+
+fn check_response_percentage(name: &str, percentage: u8) -> Result<u8> {
+    if percentage > 100 {
+        return Err(NariError::MalformedResponse(format!(
+            "{name} of {percentage}% is out of range"
+        )));
+    }
+    Ok(percentage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The headset's answer to `command`, its arguments follow the three echoed bytes
+    fn answer(command: Command, value: &[u8]) -> [u8; REPORT_LEN] {
+        let mut arguments = vec![0; 3];
+        arguments.extend_from_slice(value);
+        Report::new(Direction::Get, command, &arguments)
+            .unwrap()
+            .to_bytes()
+    }
+
+    /// The headset's answer to `command` with its arguments as they are
+    fn respond(command: Command, arguments: &[u8]) -> [u8; REPORT_LEN] {
+        Report::new(Direction::Get, command, arguments)
+            .unwrap()
+            .to_bytes()
+    }
+
+    fn mock() -> Device<MockTransport> {
+        Device::with_transport(MockTransport::new()).with_experimental(false)
+    }
+
+    #[test]
+    fn setters_send_one_report() {
+        let device = mock();
+        device.set_brightness(80).unwrap();
+        device.set_haptic_intensity(60, true).unwrap();
+        device.set_mic_monitor(mic_monitor_to_raw(20)).unwrap();
+        let expected = [
+            Report::set(Command::BRIGHTNESS, &[80]),
+            Report::set(Command::HAPTICS, &[1, 60]),
+            Report::set(Command::MIC_MONITOR, &[0x04, 0x00, 0xca]),
+        ];
+        let expected: Vec<_> = expected
+            .map(|report| report.unwrap().to_bytes().to_vec())
+            .into();
+        assert_eq!(device.transport().sent(), expected);
+    }
+
+    #[test]
+    fn setters_refuse_values_out_of_range() {
+        let device = mock();
+        assert!(matches!(
+            device.set_brightness(101),
+            Err(NariError::InvalidArgument(_))
+        ));
+        assert!(device.set_haptic_intensity(101, true).is_err());
+        assert!(device.set_mic_monitor(MIC_MONITOR_OFF - 1).is_err());
+        assert!(device.set_mic_monitor(MIC_MONITOR_END).is_err());
+        assert!(device.transport().sent().is_empty());
+    }
+
+    #[test]
+    fn getters_read_the_answer() {
+        let device = mock();
+        let transport = device.transport();
+        transport.push_response(respond(Command::BRIGHTNESS, &[80]));
+        assert_eq!(device.get_brightness().unwrap(), 80);
+        transport.push_response(respond(Command::HAPTICS, &[1, 60]));
+        assert_eq!(device.get_haptic_intensity().unwrap(), (60, true));
+        transport.push_response(respond(Command::MIC_MONITOR, &[0x04, 0x00, 0xca]));
+        assert_eq!(device.get_mic_monitor().unwrap(), 0xca);
+        transport.push_response(respond(Command::COLOR, &[255, 0, 128]));
+        assert_eq!(device.get_color().unwrap(), (255, 0, 128));
+        // Every getter asked once
+        assert_eq!(
+            transport.sent()[0],
+            Report::get(Command::BRIGHTNESS).unwrap().to_bytes()
+        );
+        assert_eq!(transport.sent().len(), 4);
+    }
+
+    #[test]
+    fn getters_refuse_answers_out_of_range() {
+        let device = mock();
+        device
+            .transport()
+            .push_response(respond(Command::BRIGHTNESS, &[101]));
+        assert!(matches!(
+            device.get_brightness(),
+            Err(NariError::MalformedResponse(_))
+        ));
+        device
+            .transport()
+            .push_response(respond(Command::MIC_MONITOR, &[0x04, 0x00, 0x10]));
+        assert!(matches!(
+            device.get_mic_monitor(),
+            Err(NariError::MalformedResponse(_))
+        ));
+        device.transport().push_response([0xff; 8]);
+        assert!(matches!(
+            device.get_brightness(),
+            Err(NariError::MalformedResponse(_))
+        ));
+        assert!(matches!(device.get_brightness(), Err(NariError::Timeout)));
+    }
+
+    #[test]
+    fn voltage_is_asked_again_after_a_zero() {
+        let device = mock();
+        device
+            .transport()
+            .push_response(answer(Command::VOLTAGE, &[0, 0]));
+        device
+            .transport()
+            .push_response(answer(Command::VOLTAGE, &[0x0f, 0x6e]));
+        assert_eq!(device.get_voltage().unwrap(), 3950);
+
+        device
+            .transport()
+            .push_response(answer(Command::VOLTAGE, &[0, 0]));
+        device
+            .transport()
+            .push_response(answer(Command::VOLTAGE, &[0, 0]));
+        assert!(matches!(
+            device.get_voltage(),
+            Err(NariError::MalformedResponse(_))
+        ));
+    }
+
+    #[test]
+    fn battery_is_read_with_the_charging_status() {
+        let device = mock().with_experimental(true);
+        device
+            .transport()
+            .push_response(answer(Command::VOLTAGE, &[0x0f, 0x6e]));
+        device
+            .transport()
+            .push_response(answer(Command::CHARGING, &[0x01]));
+        let battery = device.get_battery().unwrap();
+        assert_eq!(battery.voltage, 3950);
+        assert_eq!(battery.percentage, battery_percentage(3950));
+        assert_eq!(battery.status, Some(ChargingStatus::Charging));
+    }
+
+    #[test]
+    fn battery_is_read_without_the_charging_status() {
+        let device = mock().with_experimental(true);
+        device
+            .transport()
+            .push_response(answer(Command::VOLTAGE, &[0x0f, 0x6e]));
+        // The charging query gets no answer
+        let battery = device.get_battery().unwrap();
+        assert_eq!(battery.percentage, battery_percentage(3950));
+        assert_eq!(battery.status, None);
+        assert_eq!(battery.to_string(), format!("{}%", battery.percentage));
+
+        device
+            .transport()
+            .push_response(answer(Command::VOLTAGE, &[0x0f, 0x6e]));
+        device
+            .transport()
+            .push_response(answer(Command::CHARGING, &[0x07]));
+        assert_eq!(device.get_battery().unwrap().status, None);
+    }
+
+    #[test]
+    fn only_animated_effects_use_the_effect_command() {
+        let device = mock();
+        device
+            .set_effect(LightingEffect::Static((255, 0, 0)))
+            .unwrap();
+        device.set_effect(LightingEffect::Off).unwrap();
+        let expected = [
+            Report::set(Command::COLOR, &[255, 0, 0])
+                .unwrap()
+                .to_bytes(),
+            Report::set(Command::COLOR, &[0, 0, 0]).unwrap().to_bytes(),
+        ];
+        assert_eq!(device.transport().sent(), expected);
+
+        device.transport().clear();
+        let device = device.with_experimental(true);
+        device.set_effect(LightingEffect::Spectrum).unwrap();
+        let expected = Report::set(Command::EFFECT, &LightingEffect::Spectrum.to_arguments());
+        assert_eq!(device.transport().sent(), [expected.unwrap().to_bytes()]);
+    }
+
+    #[test]
+    fn the_effect_command_needs_the_experimental_opt_in() {
+        let device = mock();
+        assert_eq!(
+            device.set_effect(LightingEffect::Spectrum),
+            Err(NariError::Experimental(EFFECTS))
+        );
+        assert!(device.transport().sent().is_empty());
+
+        // Only the color is read back
+        device
+            .transport()
+            .push_response(respond(Command::COLOR, &[255, 0, 0]));
+        assert_eq!(
+            device.get_effect().unwrap(),
+            LightingEffect::Static((255, 0, 0))
+        );
+        assert_eq!(
+            device.transport().sent(),
+            [Report::get(Command::COLOR).unwrap().to_bytes()]
+        );
+    }
+
+    #[test]
+    fn the_headset_identity_needs_the_experimental_opt_in() {
+        let device = mock();
+        assert_eq!(
+            device.get_firmware_version(Target::Headset),
+            Err(NariError::Experimental(HEADSET_IDENTITY))
+        );
+        assert_eq!(
+            device.get_serial(Target::Headset),
+            Err(NariError::Experimental(HEADSET_IDENTITY))
+        );
+        assert!(device.transport().sent().is_empty());
+    }
+
+    #[test]
+    fn the_charging_status_needs_the_experimental_opt_in() {
+        let device = mock();
+        device
+            .transport()
+            .push_response(answer(Command::VOLTAGE, &[0x0f, 0x6e]));
+        assert_eq!(device.get_battery().unwrap().status, None);
+        assert_eq!(
+            device.transport().sent(),
+            [Report::get(Command::VOLTAGE).unwrap().to_bytes()]
+        );
     }
-    None
 }
@@ -0,0 +1,101 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::MIC_MONITOR_MAX_LEVEL;
+use serde::Serialize;
+use std::fmt;
+
+/// Razer's USB vendor id, shared by every model
+pub const VENDOR_ID: u16 = 0x1532;
+
+/// What a model can do, frontends show only the controls a headset has
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Capabilities {
+    pub haptics: bool,
+    /// Number of separately lit zones, 0 without RGB lighting
+    pub lighting_zones: u8,
+    pub sidetone: bool,
+    /// Loudest mic monitor level the model takes
+    pub sidetone_max_level: u8,
+    pub battery: bool,
+}
+
+impl Capabilities {
+    pub fn lighting(&self) -> bool {
+        self.lighting_zones > 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Model {
+    /// USB product id of the model's dongle
+    pub product_id: u16,
+    pub name: &'static str,
+    pub capabilities: Capabilities,
+}
+
+impl Model {
+    pub fn from_product_id(product_id: u16) -> Option<&'static Model> {
+        MODELS.iter().find(|model| model.product_id == product_id)
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// The Nari Ultimate, also assumed for transports that can not tell which model they talk to
+pub const NARI_ULTIMATE: Model = Model {
+    product_id: 0x051a,
+    name: "Razer Nari Ultimate",
+    capabilities: Capabilities {
+        haptics: true,
+        lighting_zones: 1,
+        sidetone: true,
+        sidetone_max_level: MIC_MONITOR_MAX_LEVEL,
+        battery: true,
+    },
+};
+
+// UNVERIFIED: the product ids of the plain Nari and the Nari Essential come from community
+// device lists and have not been tested with the real headsets.
+
+/// The Ultimate without the haptic drivers
+pub const NARI: Model = Model {
+    product_id: 0x051c,
+    name: "Razer Nari",
+    capabilities: Capabilities {
+        haptics: false,
+        ..NARI_ULTIMATE.capabilities
+    },
+};
+
+/// The Nari without its RGB lighting
+pub const NARI_ESSENTIAL: Model = Model {
+    product_id: 0x051e,
+    name: "Razer Nari Essential",
+    capabilities: Capabilities {
+        lighting_zones: 0,
+        ..NARI.capabilities
+    },
+};
+
+/// Names of the features a model may lack, as given in `NariError::Unsupported`
+pub(crate) const FEATURES: [&str; 4] = ["lighting", "haptics", "mic monitoring", "battery"];
+
+pub(crate) const EFFECTS: &str = "Breathing and spectrum lighting";
+pub(crate) const CHARGING_STATUS: &str = "Reading the charging status";
+pub(crate) const HEADSET_IDENTITY: &str = "Reading the headset firmware version and serial number";
+/// What `NariError::Experimental` names
+pub(crate) const EXPERIMENTAL_FEATURES: [&str; 3] = [EFFECTS, CHARGING_STATUS, HEADSET_IDENTITY];
+
+/// Every supported model
+pub const MODELS: [Model; 3] = [NARI_ULTIMATE, NARI, NARI_ESSENTIAL];
+
+pub(crate) fn is_supported(vendor_id: u16, product_id: u16) -> bool {
+    vendor_id == VENDOR_ID && Model::from_product_id(product_id).is_some()
+}
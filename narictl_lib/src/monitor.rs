@@ -0,0 +1,291 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::{Battery, ChargingStatus};
+use std::fmt;
+use std::time::Duration;
+
+/// Charges (%) warned about when no other thresholds are configured
+pub const DEFAULT_THRESHOLDS: [u8; 3] = [20, 10, 5];
+/// How often the battery is read when no other interval is configured
+pub const DEFAULT_MONITOR_INTERVAL: Duration = Duration::from_secs(60);
+/// How far (%) the charge has to climb back above a threshold before that threshold is warned
+/// about again, the estimate moves by a few percent between readings as the load changes
+const HYSTERESIS: u8 = 3;
+
+/// Something about the headset worth telling the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alert {
+    /// The charge dropped to `threshold` or below while discharging
+    LowBattery {
+        battery: Battery,
+        threshold: u8,
+    },
+    Charging(Battery),
+    Charged(Battery),
+    /// The headset stopped answering, it was turned off, went out of range or the dongle was unplugged
+    Disconnected,
+    Reconnected(Battery),
+}
+
+impl Alert {
+    /// Stable name of the alert for scripts
+    pub fn code(&self) -> &'static str {
+        match self {
+            Alert::LowBattery { .. } => "low_battery",
+            Alert::Charging(_) => "charging",
+            Alert::Charged(_) => "charged",
+            Alert::Disconnected => "disconnected",
+            Alert::Reconnected(_) => "reconnected",
+        }
+    }
+
+    pub fn battery(&self) -> Option<Battery> {
+        match *self {
+            Alert::LowBattery { battery, .. }
+            | Alert::Charging(battery)
+            | Alert::Charged(battery)
+            | Alert::Reconnected(battery) => Some(battery),
+            Alert::Disconnected => None,
+        }
+    }
+
+    pub fn summary(&self) -> &'static str {
+        match self {
+            Alert::LowBattery { .. } => "Headset battery low",
+            Alert::Charging(_) => "Headset charging",
+            Alert::Charged(_) => "Headset charged",
+            Alert::Disconnected => "Headset disconnected",
+            Alert::Reconnected(_) => "Headset connected",
+        }
+    }
+
+    pub fn body(&self) -> String {
+        match self {
+            Alert::LowBattery { battery, .. } => {
+                format!("{}% left, plug in the charger soon", battery.percentage)
+            }
+            Alert::Charging(battery) => format!("Charging from {}%", battery.percentage),
+            Alert::Charged(_) => String::from("The battery is full"),
+            Alert::Disconnected => String::from("The headset is turned off or out of range"),
+            Alert::Reconnected(battery) => format!("Battery {battery}"),
+        }
+    }
+
+    /// Freedesktop icon name matching the alert
+    pub fn icon(&self) -> &'static str {
+        match self {
+            Alert::LowBattery { battery, .. } if battery.percentage <= 10 => "battery-empty",
+            Alert::LowBattery { .. } => "battery-caution",
+            Alert::Charging(_) => "battery-good-charging",
+            Alert::Charged(_) => "battery-full-charged",
+            Alert::Disconnected => "battery-missing",
+            Alert::Reconnected(_) => "audio-headset",
+        }
+    }
+
+    /// Shows the alert as a desktop notification, low battery warnings stay until dismissed
+    #[cfg(feature = "notifications")]
+    pub fn notify(&self) -> crate::Result<()> {
+        let mut notification = notify_rust::Notification::new();
+        notification
+            .appname("NariCTL")
+            .summary(self.summary())
+            .body(&self.body())
+            .icon(self.icon());
+        #[cfg(all(unix, not(target_os = "macos")))]
+        if let Alert::LowBattery { .. } = self {
+            notification.urgency(notify_rust::Urgency::Critical);
+        }
+        match notification.show() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(crate::NariError::Notification(e.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.summary(), self.body())
+    }
+}
+
+/// Turns battery readings taken at a regular interval into alerts.
+///
+/// Each threshold is warned about once while discharging, and only again after the charge
+/// climbed `HYSTERESIS` percent above it. The first reading only sets the starting point,
+/// apart from warning about a battery that is already low.
+#[derive(Debug, Clone)]
+pub struct BatteryMonitor {
+    // Sorted from the lowest charge
+    thresholds: Vec<u8>,
+    // Lowest threshold warned about since the charge was last above it
+    warned: Option<u8>,
+    // Last charging status the headset told
+    status: Option<ChargingStatus>,
+    connected: Option<bool>,
+}
+
+impl BatteryMonitor {
+    pub fn new(thresholds: &[u8]) -> Self {
+        let mut thresholds = thresholds.to_vec();
+        thresholds.sort_unstable();
+        thresholds.dedup();
+        BatteryMonitor {
+            thresholds,
+            warned: None,
+            status: None,
+            connected: None,
+        }
+    }
+
+    /// Takes the latest reading, `None` when the headset did not answer
+    pub fn update(&mut self, battery: Option<Battery>) -> Vec<Alert> {
+        let Some(battery) = battery else {
+            let was_connected = self.connected == Some(true);
+            self.connected = Some(false);
+            return match was_connected {
+                true => vec![Alert::Disconnected],
+                false => Vec::new(),
+            };
+        };
+
+        let mut alerts = Vec::new();
+        match (self.connected, self.status, battery.status) {
+            (Some(false), ..) => alerts.push(Alert::Reconnected(battery)),
+            (Some(true), Some(last), Some(status)) if status != last => match status {
+                ChargingStatus::Charging => alerts.push(Alert::Charging(battery)),
+                ChargingStatus::Full => alerts.push(Alert::Charged(battery)),
+                ChargingStatus::Discharging => {}
+            },
+            _ => {}
+        }
+        self.connected = Some(true);
+        // An unknown status keeps the last one, so the next answer is not taken for a change
+        self.status = battery.status.or(self.status);
+
+        if battery.status == Some(ChargingStatus::Full) {
+            self.warned = None;
+        }
+        while let Some(warned) = self.warned
+            && battery.percentage > warned.saturating_add(HYSTERESIS)
+        {
+            self.warned = self.thresholds.iter().copied().find(|t| *t > warned);
+        }
+        // While charging the voltage reads high, an unknown status is taken for discharging
+        if !matches!(
+            battery.status,
+            Some(ChargingStatus::Charging | ChargingStatus::Full)
+        ) && let Some(threshold) = self
+            .thresholds
+            .iter()
+            .copied()
+            .find(|t| battery.percentage <= *t)
+            && self.warned.is_none_or(|warned| threshold < warned)
+        {
+            self.warned = Some(threshold);
+            alerts.push(Alert::LowBattery { battery, threshold });
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(percentage: u8, status: Option<ChargingStatus>) -> Option<Battery> {
+        Some(Battery {
+            voltage: 3800,
+            percentage,
+            status,
+        })
+    }
+
+    fn discharging(percentage: u8) -> Option<Battery> {
+        reading(percentage, Some(ChargingStatus::Discharging))
+    }
+
+    fn codes(alerts: Vec<Alert>) -> Vec<&'static str> {
+        alerts.iter().map(Alert::code).collect()
+    }
+
+    fn thresholds(alerts: Vec<Alert>) -> Vec<u8> {
+        alerts
+            .iter()
+            .filter_map(|alert| match alert {
+                Alert::LowBattery { threshold, .. } => Some(*threshold),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn thresholds_are_warned_about_once() {
+        let mut monitor = BatteryMonitor::new(&DEFAULT_THRESHOLDS);
+        assert!(monitor.update(discharging(50)).is_empty());
+        assert_eq!(thresholds(monitor.update(discharging(20))), [20]);
+        assert!(monitor.update(discharging(19)).is_empty());
+        // Climbing back by less than the hysteresis does not warn again
+        assert!(monitor.update(discharging(22)).is_empty());
+        assert!(monitor.update(discharging(19)).is_empty());
+        assert_eq!(thresholds(monitor.update(discharging(10))), [10]);
+        // Skipping a threshold warns about the lowest one reached
+        assert_eq!(thresholds(monitor.update(discharging(3))), [5]);
+        assert!(monitor.update(discharging(1)).is_empty());
+    }
+
+    #[test]
+    fn thresholds_are_warned_about_again_past_the_hysteresis() {
+        let mut monitor = BatteryMonitor::new(&[20, 10]);
+        assert_eq!(thresholds(monitor.update(discharging(9))), [10]);
+        assert!(monitor.update(discharging(13)).is_empty());
+        assert!(monitor.update(discharging(10)).is_empty());
+        // Past 10 + HYSTERESIS only 20 stays warned
+        assert!(monitor.update(discharging(14)).is_empty());
+        assert_eq!(thresholds(monitor.update(discharging(10))), [10]);
+        assert!(monitor.update(discharging(24)).is_empty());
+        assert_eq!(thresholds(monitor.update(discharging(20))), [20]);
+    }
+
+    #[test]
+    fn charging_is_announced_and_silences_low_warnings() {
+        let mut monitor = BatteryMonitor::new(&DEFAULT_THRESHOLDS);
+        assert!(monitor.update(discharging(30)).is_empty());
+        let charging = monitor.update(reading(15, Some(ChargingStatus::Charging)));
+        assert_eq!(codes(charging), ["charging"]);
+        let charged = monitor.update(reading(100, Some(ChargingStatus::Full)));
+        assert_eq!(codes(charged), ["charged"]);
+        assert!(monitor.update(discharging(99)).is_empty());
+    }
+
+    #[test]
+    fn an_unknown_status_is_not_a_change() {
+        let mut monitor = BatteryMonitor::new(&DEFAULT_THRESHOLDS);
+        assert!(
+            monitor
+                .update(reading(50, Some(ChargingStatus::Charging)))
+                .is_empty()
+        );
+        assert!(monitor.update(reading(51, None)).is_empty());
+        assert!(
+            monitor
+                .update(reading(52, Some(ChargingStatus::Charging)))
+                .is_empty()
+        );
+        // Without a status a low charge is still warned about
+        assert_eq!(thresholds(monitor.update(reading(20, None))), [20]);
+    }
+
+    #[test]
+    fn disconnects_are_announced_once() {
+        let mut monitor = BatteryMonitor::new(&DEFAULT_THRESHOLDS);
+        assert!(monitor.update(None).is_empty());
+        assert_eq!(thresholds(monitor.update(discharging(8))), [10]);
+        assert_eq!(codes(monitor.update(None)), ["disconnected"]);
+        assert!(monitor.update(None).is_empty());
+        assert_eq!(codes(monitor.update(discharging(8))), ["reconnected"]);
+    }
+}
@@ -0,0 +1,327 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::config::FileError;
+use crate::{
+    DEFAULT_MIC_MONITOR_LEVEL, DeviceState, LightingEffect, MIC_MONITOR_MAX_LEVEL, MIC_MONITOR_OFF,
+    Model, NariError, Result, config_dir, load_toml, mic_monitor_from_raw, mic_monitor_to_raw,
+    save_toml,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Named set of settings that can be applied in one step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    pub effect: LightingEffect,
+    pub brightness: u8,
+    pub haptics_enabled: bool,
+    pub haptic_intensity: u8,
+    pub sidetone_enabled: bool,
+    /// Mic monitor level from 0 to `MIC_MONITOR_MAX_LEVEL`
+    pub sidetone_level: u8,
+}
+
+impl Profile {
+    pub fn state(&self) -> DeviceState {
+        DeviceState {
+            effect: self.effect,
+            brightness: self.brightness,
+            haptics_enabled: self.haptics_enabled,
+            haptic_intensity: self.haptic_intensity,
+            mic_monitor: match self.sidetone_enabled {
+                true => mic_monitor_to_raw(self.sidetone_level),
+                false => MIC_MONITOR_OFF,
+            },
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.brightness > 100 || self.haptic_intensity > 100 {
+            return Err(NariError::InvalidArgument(String::from(
+                "brightness and haptic intensity must be between 0 and 100",
+            )));
+        }
+        if self.sidetone_level > MIC_MONITOR_MAX_LEVEL {
+            return Err(NariError::InvalidArgument(format!(
+                "sidetone level must be between 0 and {MIC_MONITOR_MAX_LEVEL}, got {}",
+                self.sidetone_level
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl From<DeviceState> for Profile {
+    fn from(state: DeviceState) -> Self {
+        Profile {
+            effect: state.effect,
+            brightness: state.brightness,
+            haptics_enabled: state.haptics_enabled,
+            haptic_intensity: state.haptic_intensity,
+            sidetone_enabled: state.mic_monitor != MIC_MONITOR_OFF,
+            sidetone_level: mic_monitor_from_raw(state.mic_monitor),
+        }
+    }
+}
+
+/// Settings a headset should end up with, as read by `narictl apply`.
+///
+/// Uses the fields of `Profile`, the ones left out keep their current value. Giving an
+/// intensity or a level alone also turns haptics or mic monitoring on, turning mic monitoring
+/// on without a level uses `DEFAULT_MIC_MONITOR_LEVEL` when it was off.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DesiredState {
+    pub effect: Option<LightingEffect>,
+    pub brightness: Option<u8>,
+    pub haptics_enabled: Option<bool>,
+    pub haptic_intensity: Option<u8>,
+    pub sidetone_enabled: Option<bool>,
+    pub sidetone_level: Option<u8>,
+}
+
+impl DesiredState {
+    /// Reads a JSON (`.json`), YAML (`.yaml` or `.yml`) or TOML file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = FileError::config(path);
+        let data = std::fs::read_to_string(path).map_err(|e| file.error(e))?;
+        let desired: DesiredState = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&data).map_err(|e| file.error(e))?,
+            // serde_norway wants enums as `!tags`, going through JSON keeps the shape of the others
+            Some("yaml" | "yml") => serde_norway::from_str::<serde_json::Value>(&data)
+                .map_err(|e| file.error(e))
+                .and_then(|value| serde_json::from_value(value).map_err(|e| file.error(e)))?,
+            _ => toml::from_str(&data).map_err(|e| file.error(e))?,
+        };
+        desired.validate().map_err(|e| file.wrap(e))?;
+        Ok(desired)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let percentage = |value: Option<u8>| value.is_none_or(|value| value <= 100);
+        if !percentage(self.brightness) || !percentage(self.haptic_intensity) {
+            return Err(NariError::InvalidArgument(String::from(
+                "brightness and haptic intensity must be between 0 and 100",
+            )));
+        }
+        if let Some(level) = self.sidetone_level
+            && level > MIC_MONITOR_MAX_LEVEL
+        {
+            return Err(NariError::InvalidArgument(format!(
+                "sidetone level must be between 0 and {MIC_MONITOR_MAX_LEVEL}, got {level}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// The state `current` turns into, fails when a setting is given that `model` lacks
+    pub fn target(&self, current: &DeviceState, model: &Model) -> Result<DeviceState> {
+        let capabilities = model.capabilities;
+        let unsupported = |feature| NariError::Unsupported {
+            model: model.name,
+            feature,
+        };
+        if (self.effect.is_some() || self.brightness.is_some()) && !capabilities.lighting() {
+            return Err(unsupported("lighting"));
+        }
+        if (self.haptics_enabled.is_some() || self.haptic_intensity.is_some())
+            && !capabilities.haptics
+        {
+            return Err(unsupported("haptics"));
+        }
+        if (self.sidetone_enabled.is_some() || self.sidetone_level.is_some())
+            && !capabilities.sidetone
+        {
+            return Err(unsupported("mic monitoring"));
+        }
+        // Turning mic monitoring on from off needs a level, the current one would keep it off
+        let sidetone_level = match current.mic_monitor {
+            MIC_MONITOR_OFF => self.sidetone_level.unwrap_or(DEFAULT_MIC_MONITOR_LEVEL),
+            raw => self.sidetone_level.unwrap_or(mic_monitor_from_raw(raw)),
+        };
+        Ok(DeviceState {
+            effect: self.effect.unwrap_or(current.effect),
+            brightness: self.brightness.unwrap_or(current.brightness),
+            haptics_enabled: self
+                .haptics_enabled
+                .unwrap_or(self.haptic_intensity.is_some() || current.haptics_enabled),
+            haptic_intensity: self.haptic_intensity.unwrap_or(current.haptic_intensity),
+            mic_monitor: match self.sidetone_enabled {
+                None if self.sidetone_level.is_none() => current.mic_monitor,
+                Some(false) => MIC_MONITOR_OFF,
+                _ => mic_monitor_to_raw(sidetone_level),
+            },
+        })
+    }
+}
+
+/// Every saved profile, stored in `profiles.toml` inside `config_dir()`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profiles {
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Profiles {
+    pub fn path() -> Result<PathBuf> {
+        Ok(config_dir()?.join("profiles.toml"))
+    }
+
+    /// Reads the saved profiles, a missing file is the same as having none
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        let profiles: Profiles = load_toml(&path)?;
+        for (name, profile) in &profiles.profiles {
+            profile
+                .validate()
+                .map_err(|e| FileError::config(&path).error(format!("profile {name}: {e}")))?;
+        }
+        Ok(profiles)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        save_toml(&Self::path()?, self)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Profile> {
+        self.profiles
+            .get(name)
+            .copied()
+            .ok_or_else(|| NariError::InvalidArgument(format!("no profile named \"{name}\"")))
+    }
+
+    pub fn insert(&mut self, name: &str, profile: Profile) -> Result<()> {
+        if name.trim().is_empty() {
+            return Err(NariError::InvalidArgument(String::from(
+                "a profile name can not be empty",
+            )));
+        }
+        profile.validate()?;
+        self.profiles.insert(name.to_string(), profile);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<Profile> {
+        self.profiles
+            .remove(name)
+            .ok_or_else(|| NariError::InvalidArgument(format!("no profile named \"{name}\"")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NARI_ULTIMATE;
+
+    const CURRENT: DeviceState = DeviceState {
+        effect: LightingEffect::Off,
+        brightness: 50,
+        haptics_enabled: false,
+        haptic_intensity: 30,
+        mic_monitor: MIC_MONITOR_OFF,
+    };
+
+    #[test]
+    fn enabling_sidetone_without_a_level_uses_the_default_level() {
+        let desired = DesiredState {
+            sidetone_enabled: Some(true),
+            ..DesiredState::default()
+        };
+        let target = desired.target(&CURRENT, &NARI_ULTIMATE).unwrap();
+        assert_eq!(
+            target.mic_monitor,
+            mic_monitor_to_raw(DEFAULT_MIC_MONITOR_LEVEL)
+        );
+        assert_ne!(target.mic_monitor, MIC_MONITOR_OFF);
+
+        // A headset already monitoring keeps its level
+        let current = DeviceState {
+            mic_monitor: mic_monitor_to_raw(40),
+            ..CURRENT
+        };
+        let target = desired.target(&current, &NARI_ULTIMATE).unwrap();
+        assert_eq!(target.mic_monitor, mic_monitor_to_raw(40));
+    }
+
+    #[test]
+    fn yaml_files_keep_the_shape_of_the_other_formats() {
+        let path = std::env::temp_dir().join(format!("narictl-{}.yaml", std::process::id()));
+        std::fs::write(&path, "effect:\n  breathing: random\nsidetone_level: 40\n").unwrap();
+        let desired = DesiredState::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let desired = desired.unwrap();
+        assert_eq!(
+            desired.effect,
+            Some(LightingEffect::Breathing(crate::Breathing::Random))
+        );
+        assert_eq!(desired.sidetone_level, Some(40));
+    }
+
+    #[test]
+    fn settings_left_out_keep_their_value() {
+        let desired = DesiredState {
+            brightness: Some(80),
+            haptic_intensity: Some(60),
+            sidetone_level: Some(40),
+            ..DesiredState::default()
+        };
+        let target = desired.target(&CURRENT, &NARI_ULTIMATE).unwrap();
+        assert_eq!(
+            target,
+            DeviceState {
+                brightness: 80,
+                // An intensity or a level alone turns the feature on
+                haptics_enabled: true,
+                haptic_intensity: 60,
+                mic_monitor: mic_monitor_to_raw(40),
+                ..CURRENT
+            }
+        );
+        assert_eq!(
+            DesiredState::default()
+                .target(&target, &NARI_ULTIMATE)
+                .unwrap(),
+            target
+        );
+
+        let desired = DesiredState {
+            sidetone_enabled: Some(false),
+            sidetone_level: Some(40),
+            ..DesiredState::default()
+        };
+        let target = desired.target(&target, &NARI_ULTIMATE).unwrap();
+        assert_eq!(target.mic_monitor, MIC_MONITOR_OFF);
+    }
+
+    #[test]
+    fn settings_the_model_lacks_are_refused() {
+        let desired = DesiredState {
+            haptics_enabled: Some(true),
+            ..DesiredState::default()
+        };
+        assert!(matches!(
+            desired.target(&CURRENT, &crate::NARI),
+            Err(NariError::Unsupported {
+                feature: "haptics",
+                ..
+            })
+        ));
+        let desired = DesiredState {
+            brightness: Some(80),
+            ..DesiredState::default()
+        };
+        assert!(desired.target(&CURRENT, &crate::NARI).is_ok());
+        assert!(matches!(
+            desired.target(&CURRENT, &crate::NARI_ESSENTIAL),
+            Err(NariError::Unsupported {
+                feature: "lighting",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,289 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::{NariError, REPORT_LEN, Result};
+use std::fmt;
+
+/// Number of bytes in front of the arguments of every report
+pub const HEADER_LEN: usize = 9;
+/// Largest number of argument bytes a report can carry
+pub const ARGUMENTS_LEN: usize = REPORT_LEN - HEADER_LEN;
+
+const REPORT_ID: u8 = 0xff;
+const TRANSACTION_ID: u8 = 0x0a;
+// Bytes 2, 4 and 6 hold the same values in every packet seen so far, their meaning is unknown
+const RESERVED: u8 = 0x00;
+const PROTOCOL: u8 = 0x04;
+const COMMAND_GROUP: u8 = 0xf1;
+
+/// Whether a report changes a setting or asks the headset for one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Set,
+    Get,
+    Other(u8),
+}
+
+impl Direction {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Direction::Set => 0xff,
+            Direction::Get => 0xfd,
+            Direction::Other(byte) => byte,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Self {
+        match byte {
+            0xff => Direction::Set,
+            0xfd => Direction::Get,
+            byte => Direction::Other(byte),
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Set => write!(f, "set"),
+            Direction::Get => write!(f, "get"),
+            Direction::Other(byte) => write!(f, "{byte:#04x}"),
+        }
+    }
+}
+
+/// Identifies what a report does on the headset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Command {
+    pub class: u8,
+    pub id: u8,
+    pub data_size: u8,
+}
+
+impl Command {
+    pub const MIC_MONITOR: Command = Command::new(0x0e, 0x01, 0x05);
+    pub const HAPTICS: Command = Command::new(0x02, 0x20, 0x06);
+    pub const BRIGHTNESS: Command = Command::new(0x12, 0x71, 0x03);
+    pub const COLOR: Command = Command::new(0x12, 0x72, 0x05);
+    /// Unverified: the Chroma effect command, never seen in a capture of the Nari. Only sent
+    /// with the experimental opt-in, see `experimental_enabled`
+    pub const EFFECT: Command = Command::new(0x12, 0x73, 0x09);
+    pub const VOLTAGE: Command = Command::new(0x12, 0x05, 0x02);
+    /// Unverified: guessed next to `VOLTAGE`, never seen in a capture of the Nari. Only sent
+    /// with the experimental opt-in, see `experimental_enabled`
+    pub const CHARGING: Command = Command::new(0x12, 0x06, 0x02);
+    /// Unverified: device information class of Razer's Chroma protocol, never seen in a capture
+    /// of the Nari. Only sent with the experimental opt-in, see `experimental_enabled`
+    pub const FIRMWARE_VERSION: Command = Command::new(0x00, 0x81, 0x02);
+    /// Unverified, like `FIRMWARE_VERSION`
+    pub const SERIAL: Command = Command::new(0x00, 0x82, 0x16);
+
+    /// Every command the library sends, with a short name
+    pub const KNOWN: [(Command, &'static str); 9] = [
+        (Command::MIC_MONITOR, "mic monitor"),
+        (Command::HAPTICS, "haptics"),
+        (Command::BRIGHTNESS, "brightness"),
+        (Command::COLOR, "color"),
+        (Command::EFFECT, "effect"),
+        (Command::VOLTAGE, "voltage"),
+        (Command::CHARGING, "charging status"),
+        (Command::FIRMWARE_VERSION, "firmware version"),
+        (Command::SERIAL, "serial"),
+    ];
+
+    pub const fn new(class: u8, id: u8, data_size: u8) -> Self {
+        Command {
+            class,
+            id,
+            data_size,
+        }
+    }
+
+    /// Whether both are the same command, the data size is not compared
+    pub fn is(&self, other: Command) -> bool {
+        self.class == other.class && self.id == other.id
+    }
+
+    /// Name of the command, `None` when the library does not know it
+    pub fn name(&self) -> Option<&'static str> {
+        Command::KNOWN
+            .iter()
+            .find(|(command, _)| self.is(*command))
+            .map(|(_, name)| *name)
+    }
+}
+
+/// A feature report sent to or received from the headset.
+///
+/// Serialized layout:
+/// `report id | transaction id | 0x00 | direction | 0x04 | class | 0xf1 | data size | command id | arguments..`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub report_id: u8,
+    pub transaction_id: u8,
+    pub direction: Direction,
+    pub command: Command,
+    pub arguments: [u8; ARGUMENTS_LEN],
+}
+
+impl Report {
+    pub fn new(direction: Direction, command: Command, arguments: &[u8]) -> Result<Self> {
+        if arguments.len() > ARGUMENTS_LEN {
+            return Err(NariError::InvalidArgument(format!(
+                "a report can carry at most {ARGUMENTS_LEN} argument bytes, got {}",
+                arguments.len()
+            )));
+        }
+        let mut report = Report {
+            report_id: REPORT_ID,
+            transaction_id: TRANSACTION_ID,
+            direction,
+            command,
+            arguments: [0; ARGUMENTS_LEN],
+        };
+        report.arguments[..arguments.len()].copy_from_slice(arguments);
+        Ok(report)
+    }
+
+    pub fn set(command: Command, arguments: &[u8]) -> Result<Self> {
+        Report::new(Direction::Set, command, arguments)
+    }
+
+    pub fn get(command: Command) -> Result<Self> {
+        Report::new(Direction::Get, command, &[])
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < HEADER_LEN {
+            return Err(NariError::MalformedResponse(format!(
+                "a report needs at least {HEADER_LEN} bytes, got {}",
+                bytes.len()
+            )));
+        }
+        let mut arguments = [0; ARGUMENTS_LEN];
+        let len = (bytes.len() - HEADER_LEN).min(ARGUMENTS_LEN);
+        arguments[..len].copy_from_slice(&bytes[HEADER_LEN..HEADER_LEN + len]);
+        Ok(Report {
+            report_id: bytes[0],
+            transaction_id: bytes[1],
+            direction: Direction::from_byte(bytes[3]),
+            command: Command::new(bytes[5], bytes[8], bytes[7]),
+            arguments,
+        })
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        Report::parse(&decode_hex(hex)?)
+    }
+
+    pub fn to_bytes(&self) -> [u8; REPORT_LEN] {
+        let mut bytes = [0; REPORT_LEN];
+        bytes[..HEADER_LEN].copy_from_slice(&[
+            self.report_id,
+            self.transaction_id,
+            RESERVED,
+            self.direction.to_byte(),
+            PROTOCOL,
+            self.command.class,
+            COMMAND_GROUP,
+            self.command.data_size,
+            self.command.id,
+        ]);
+        bytes[HEADER_LEN..].copy_from_slice(&self.arguments);
+        bytes
+    }
+}
+
+/// Shows the header fields, the arguments are left out
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "report id {:#04x}, transaction {:#04x}, direction {}, class {:#04x}, command {:#04x}, data size {}",
+            self.report_id,
+            self.transaction_id,
+            self.direction,
+            self.command.class,
+            self.command.id,
+            self.command.data_size
+        )
+    }
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Parses a hex string such as `ff0a00ff`, spaces and `:` between bytes are ignored
+pub fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    let digits = hex
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':')
+        .collect::<Vec<char>>();
+    if digits.len() % 2 != 0 {
+        return Err(NariError::InvalidArgument(format!(
+            "hex string has an odd number of digits: {hex}"
+        )));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair = pair.iter().collect::<String>();
+            u8::from_str_radix(&pair, 16)
+                .map_err(|_| NariError::InvalidArgument(format!("invalid hex byte {pair}")))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_are_laid_out_like_the_headset_expects() {
+        let report = Report::set(Command::BRIGHTNESS, &[80]).unwrap();
+        let bytes = report.to_bytes();
+        assert_eq!(
+            bytes[..HEADER_LEN + 1],
+            [0xff, 0x0a, 0x00, 0xff, 0x04, 0x12, 0xf1, 0x03, 0x71, 80]
+        );
+        assert!(bytes[HEADER_LEN + 1..].iter().all(|&byte| byte == 0));
+        assert_eq!(Report::parse(&bytes).unwrap(), report);
+    }
+
+    #[test]
+    fn reports_are_parsed_back() {
+        let report = Report::new(Direction::Get, Command::VOLTAGE, &[0, 0, 0, 0x0f, 0x6e]).unwrap();
+        let parsed = Report::from_hex(&encode_hex(&report.to_bytes())).unwrap();
+        assert_eq!(parsed, report);
+        assert_eq!(parsed.command.name(), Some("voltage"));
+
+        // Truncated captures keep their header and leave the missing arguments at 0
+        let parsed = Report::parse(&report.to_bytes()[..HEADER_LEN + 4]).unwrap();
+        assert_eq!(parsed.arguments[..5], [0, 0, 0, 0x0f, 0]);
+        assert_eq!(Direction::from_byte(0x42), Direction::Other(0x42));
+    }
+
+    #[test]
+    fn invalid_reports_are_refused() {
+        assert!(matches!(
+            Report::set(Command::COLOR, &[0; ARGUMENTS_LEN + 1]),
+            Err(NariError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            Report::parse(&[0xff; HEADER_LEN - 1]),
+            Err(NariError::MalformedResponse(_))
+        ));
+        assert_eq!(Command::new(0x12, 0x42, 0x01).name(), None);
+    }
+
+    #[test]
+    fn hex_strings_are_decoded() {
+        assert_eq!(decode_hex("ff0a 00:FF").unwrap(), [0xff, 0x0a, 0x00, 0xff]);
+        assert_eq!(encode_hex(&[0xff, 0x0a]), "ff0a");
+        assert!(decode_hex("ff0").is_err());
+        assert!(decode_hex("zz").is_err());
+    }
+}
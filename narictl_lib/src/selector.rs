@@ -0,0 +1,236 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::model::is_supported;
+use crate::{Model, NariError, Result};
+use rusb::{Context, DeviceHandle, UsbContext};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Where a headset dongle sits on the USB bus
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub product_id: u16,
+    pub bus: u8,
+    /// Changes every time the dongle is plugged in
+    pub address: u8,
+    /// Ports from the root hub down to the dongle, stays the same across replugs
+    pub port_path: Vec<u8>,
+    /// `None` when the dongle could not be opened to read it, usually missing permissions
+    pub serial: Option<String>,
+    /// Release number the dongle reports in its device descriptor
+    pub firmware: String,
+}
+
+impl DeviceInfo {
+    /// `None` for a product id this version does not know, ex: one listed by a newer `narictld`
+    pub fn model(&self) -> Option<&'static Model> {
+        Model::from_product_id(self.product_id)
+    }
+
+    /// Name of the model, or the product id when the model is unknown
+    pub fn model_name(&self) -> String {
+        match self.model() {
+            Some(model) => model.name.to_string(),
+            None => format!("Unknown headset {:#06x}", self.product_id),
+        }
+    }
+
+    /// Port path in the `bus-port.port` form used by sysfs, ex: `3-1.2`
+    pub fn port(&self) -> String {
+        let ports: Vec<String> = self.port_path.iter().map(u8::to_string).collect();
+        format!("{}-{}", self.bus, ports.join("."))
+    }
+
+    /// Selector that keeps finding this dongle after a replug
+    pub fn selector(&self) -> DeviceSelector {
+        match &self.serial {
+            Some(serial) => DeviceSelector::Serial(serial.clone()),
+            None => DeviceSelector::Port(self.bus, self.port_path.clone()),
+        }
+    }
+
+    fn matches(&self, selector: &DeviceSelector) -> bool {
+        match selector {
+            DeviceSelector::First => true,
+            DeviceSelector::BusAddress(bus, address) => {
+                self.bus == *bus && self.address == *address
+            }
+            DeviceSelector::Port(bus, ports) => self.bus == *bus && self.port_path == *ports,
+            DeviceSelector::Serial(serial) => self.serial.as_ref() == Some(serial),
+        }
+    }
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: Bus {:03} Device {:03} port {} serial {} firmware {}",
+            self.model_name(),
+            self.bus,
+            self.address,
+            self.port(),
+            self.serial.as_deref().unwrap_or("unknown"),
+            self.firmware
+        )
+    }
+}
+
+/// Picks one headset when several dongles are plugged in.
+///
+/// Parsed from `bus:address` (ex: `3:7`, as shown by lsusb), a port path (ex: `3-1.2`)
+/// or anything else as a serial number.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// The first dongle found
+    #[default]
+    First,
+    BusAddress(u8, u8),
+    Port(u8, Vec<u8>),
+    Serial(String),
+}
+
+impl FromStr for DeviceSelector {
+    type Err = NariError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(NariError::InvalidArgument(String::from(
+                "a device selector can not be empty",
+            )));
+        }
+        if let Some((bus, address)) = value.split_once(':')
+            && let (Ok(bus), Ok(address)) = (bus.parse(), address.parse())
+        {
+            return Ok(DeviceSelector::BusAddress(bus, address));
+        }
+        if let Some((bus, ports)) = value.split_once('-')
+            && let Ok(bus) = bus.parse()
+            && let Ok(ports) = ports.split('.').map(str::parse).collect()
+        {
+            return Ok(DeviceSelector::Port(bus, ports));
+        }
+        Ok(DeviceSelector::Serial(value.to_string()))
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSelector::First => write!(f, "first"),
+            DeviceSelector::BusAddress(bus, address) => write!(f, "{bus}:{address}"),
+            DeviceSelector::Port(bus, ports) => {
+                let ports: Vec<String> = ports.iter().map(u8::to_string).collect();
+                write!(f, "{bus}-{}", ports.join("."))
+            }
+            DeviceSelector::Serial(serial) => write!(f, "{serial}"),
+        }
+    }
+}
+
+/// Lists every headset dongle plugged in
+pub fn enumerate() -> Result<Vec<DeviceInfo>> {
+    let context = Context::new()?;
+    Ok(find(&context)?.into_iter().map(|(_, info)| info).collect())
+}
+
+/// Returns the first dongle matching `selector`
+pub(crate) fn select(
+    context: &Context,
+    selector: &DeviceSelector,
+) -> Result<(rusb::Device<Context>, DeviceInfo)> {
+    find(context)?
+        .into_iter()
+        .find(|(_, info)| info.matches(selector))
+        .ok_or(NariError::DeviceNotFound)
+}
+
+fn find(context: &Context) -> Result<Vec<(rusb::Device<Context>, DeviceInfo)>> {
+    let mut found = Vec::new();
+    for device in context.devices()?.iter() {
+        let Ok(desc) = device.device_descriptor() else {
+            continue;
+        };
+        if !is_supported(desc.vendor_id(), desc.product_id()) {
+            continue;
+        }
+        let info = describe(&device, device.open().ok().as_ref())?;
+        found.push((device, info));
+    }
+    Ok(found)
+}
+
+/// Reads where `device` sits and what it reports about itself, the serial needs an open `handle`
+pub(crate) fn describe(
+    device: &rusb::Device<Context>,
+    handle: Option<&DeviceHandle<Context>>,
+) -> Result<DeviceInfo> {
+    let desc = device.device_descriptor()?;
+    Ok(DeviceInfo {
+        product_id: desc.product_id(),
+        bus: device.bus_number(),
+        address: device.address(),
+        port_path: device.port_numbers().unwrap_or_default(),
+        serial: handle.and_then(|handle| handle.read_serial_number_string_ascii(&desc).ok()),
+        firmware: desc.device_version().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selectors_are_parsed() {
+        let parse = |value: &str| value.parse::<DeviceSelector>().unwrap();
+        assert_eq!(parse("3:7"), DeviceSelector::BusAddress(3, 7));
+        assert_eq!(parse(" 3-1.2 "), DeviceSelector::Port(3, vec![1, 2]));
+        assert_eq!(parse("3-4"), DeviceSelector::Port(3, vec![4]));
+        assert_eq!(
+            parse("PM1234"),
+            DeviceSelector::Serial(String::from("PM1234"))
+        );
+        // Numbers that do not fit are taken for serials
+        assert_eq!(
+            parse("3:700"),
+            DeviceSelector::Serial(String::from("3:700"))
+        );
+        assert_eq!(
+            parse("AB-12"),
+            DeviceSelector::Serial(String::from("AB-12"))
+        );
+        assert!(matches!(
+            "  ".parse::<DeviceSelector>(),
+            Err(NariError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_product_ids_are_listed_without_a_model() {
+        let info = DeviceInfo {
+            product_id: 0xffff,
+            bus: 3,
+            address: 7,
+            port_path: vec![1, 2],
+            serial: None,
+            firmware: String::from("1.0"),
+        };
+        assert_eq!(info.model(), None);
+        assert_eq!(
+            info.to_string(),
+            "Unknown headset 0xffff: Bus 003 Device 007 port 3-1.2 serial unknown firmware 1.0"
+        );
+    }
+
+    #[test]
+    fn selectors_are_printed_the_way_they_are_parsed() {
+        for value in ["3:7", "3-1.2", "PM1234"] {
+            assert_eq!(value.parse::<DeviceSelector>().unwrap().to_string(), value);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::LightingEffect;
+use serde::{Deserialize, Serialize};
+
+/// Raw mic monitor value that turns mic monitoring off
+pub const MIC_MONITOR_OFF: u8 = 0xc0;
+/// First raw mic monitor value past the loudest level
+pub const MIC_MONITOR_END: u8 = 0xec;
+/// Loudest mic monitor level accepted by `mic_monitor_to_raw`
+pub const MIC_MONITOR_MAX_LEVEL: u8 = 86;
+/// Level mic monitoring is turned on with when no level was chosen yet
+pub const DEFAULT_MIC_MONITOR_LEVEL: u8 = 20;
+
+/// Snapshot of every setting that can be read back from the headset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceState {
+    pub effect: LightingEffect,
+    pub brightness: u8,
+    pub haptics_enabled: bool,
+    pub haptic_intensity: u8,
+    /// Raw mic monitor value, `MIC_MONITOR_OFF` when disabled
+    pub mic_monitor: u8,
+}
+
+/// Converts a mic monitor level (0 to `MIC_MONITOR_MAX_LEVEL`) to the raw value the headset expects
+pub fn mic_monitor_to_raw(level: u8) -> u8 {
+    // The headset only takes even steps
+    MIC_MONITOR_OFF + ((level.min(MIC_MONITOR_MAX_LEVEL) / 2) & !1)
+}
+
+/// Converts a raw mic monitor value back to its level
+pub fn mic_monitor_from_raw(value: u8) -> u8 {
+    value.saturating_sub(MIC_MONITOR_OFF) * 2
+}
@@ -0,0 +1,179 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::{
+    Battery, Device, DeviceInfo, LightingEffect, MIC_MONITOR_OFF, Model, NariError, Result,
+    Transport, mic_monitor_from_raw,
+};
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Connection {
+    /// The headset is turned on and paired with the dongle
+    Connected,
+    /// Only the dongle answers, the headset is turned off or out of range
+    HeadsetOff,
+}
+
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Connection::Connected => write!(f, "connected"),
+            Connection::HeadsetOff => write!(f, "headset off"),
+        }
+    }
+}
+
+/// Which end of the wireless link a query is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// The USB dongle
+    Dongle,
+    /// The wireless headset paired with it
+    Headset,
+}
+
+/// Firmware versions and serial numbers, `None` when they could not be read
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Identity {
+    pub dongle_firmware: Option<String>,
+    pub dongle_serial: Option<String>,
+    pub headset_firmware: Option<String>,
+    pub headset_serial: Option<String>,
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unknown = |value: &Option<String>| value.clone().unwrap_or_else(|| "unknown".into());
+        writeln!(f, "Dongle firmware: {}", unknown(&self.dongle_firmware))?;
+        writeln!(f, "Dongle serial: {}", unknown(&self.dongle_serial))?;
+        writeln!(f, "Headset firmware: {}", unknown(&self.headset_firmware))?;
+        write!(f, "Headset serial: {}", unknown(&self.headset_serial))
+    }
+}
+
+/// Everything that can be read from the dongle and the headset at once.
+///
+/// Settings are `None` when the model lacks them or the headset is off. The auto-off
+/// timer is not part of the protocol as far as it is known, so it is not reported.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Status {
+    pub model: &'static Model,
+    /// `None` when the transport can not tell, ex: an older `narictld`
+    pub dongle: Option<DeviceInfo>,
+    pub identity: Identity,
+    pub connection: Connection,
+    pub battery: Option<Battery>,
+    pub effect: Option<LightingEffect>,
+    pub brightness: Option<u8>,
+    pub haptics_enabled: Option<bool>,
+    pub haptic_intensity: Option<u8>,
+    /// Raw mic monitor value, `MIC_MONITOR_OFF` when disabled
+    pub mic_monitor: Option<u8>,
+}
+
+impl<T: Transport> Device<T> {
+    /// Reads whatever identifies the dongle and the headset, the headset only answers when it is on
+    pub fn identity(&self) -> Identity {
+        Identity {
+            dongle_firmware: self.get_firmware_version(Target::Dongle).ok(),
+            dongle_serial: self.get_serial(Target::Dongle).ok(),
+            headset_firmware: self.get_firmware_version(Target::Headset).ok(),
+            headset_serial: self.get_serial(Target::Headset).ok(),
+        }
+    }
+
+    /// Fails only when the dongle can not be reached, a silent headset is reported as off
+    pub fn status(&self) -> Result<Status> {
+        let model = self.model()?;
+        let capabilities = model.capabilities;
+        let dongle = self.transport().device_info().unwrap_or(None);
+        let mut status = Status {
+            model,
+            identity: Identity {
+                dongle_firmware: dongle.as_ref().map(|dongle| dongle.firmware.clone()),
+                dongle_serial: dongle.as_ref().and_then(|dongle| dongle.serial.clone()),
+                ..Identity::default()
+            },
+            dongle,
+            connection: Connection::Connected,
+            battery: None,
+            effect: None,
+            brightness: None,
+            haptics_enabled: None,
+            haptic_intensity: None,
+            mic_monitor: None,
+        };
+        // The first query tells whether the headset is there at all
+        let first = match capabilities.battery {
+            true => self
+                .get_battery()
+                .map(|battery| status.battery = Some(battery)),
+            false => self
+                .get_mic_monitor()
+                .map(|value| status.mic_monitor = Some(value)),
+        };
+        match first {
+            Ok(()) => {}
+            Err(NariError::Timeout | NariError::MalformedResponse(_)) => {
+                status.connection = Connection::HeadsetOff;
+                return Ok(status);
+            }
+            Err(e) => return Err(e),
+        }
+        status.identity.headset_firmware = self.get_firmware_version(Target::Headset).ok();
+        status.identity.headset_serial = self.get_serial(Target::Headset).ok();
+        if capabilities.lighting() {
+            status.effect = Some(self.get_effect()?);
+            status.brightness = Some(self.get_brightness()?);
+        }
+        if capabilities.haptics {
+            let (intensity, enabled) = self.get_haptic_intensity()?;
+            status.haptic_intensity = Some(intensity);
+            status.haptics_enabled = Some(enabled);
+        }
+        if capabilities.sidetone && status.mic_monitor.is_none() {
+            status.mic_monitor = Some(self.get_mic_monitor()?);
+        }
+        Ok(status)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Model: {}", self.model)?;
+        if let Some(dongle) = &self.dongle {
+            writeln!(f, "Port: {}", dongle.port())?;
+        }
+        writeln!(f, "{}", self.identity)?;
+        write!(f, "Connection: {}", self.connection)?;
+        if let Some(battery) = self.battery {
+            write!(f, "\nBattery: {battery}")?;
+            write!(f, "\nVoltage: {} mv", battery.voltage)?;
+        }
+        if let Some(effect) = self.effect {
+            write!(f, "\nEffect: {}", effect.mode())?;
+            if let Some((r, g, b)) = effect.color() {
+                write!(f, "\nColor: {r},{g},{b}")?;
+            }
+        }
+        if let Some(brightness) = self.brightness {
+            write!(f, "\nBrightness: {brightness}")?;
+        }
+        match (self.haptics_enabled, self.haptic_intensity) {
+            (Some(true), Some(intensity)) => write!(f, "\nHaptics: {intensity}")?,
+            (Some(false), _) => write!(f, "\nHaptics: off")?,
+            _ => {}
+        }
+        match self.mic_monitor {
+            Some(MIC_MONITOR_OFF) => write!(f, "\nMic monitor: off")?,
+            Some(value) => write!(f, "\nMic monitor: {}", mic_monitor_from_raw(value))?,
+            None => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,339 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::selector::{describe, select};
+use crate::{DeviceInfo, DeviceSelector, NARI_ULTIMATE, NariError, Result, encode_hex};
+use log::{debug, log_enabled, trace, warn};
+use rusb::{Context, DeviceHandle, Error};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+pub(crate) const HID: u8 = 5;
+const TIMEOUT: Duration = Duration::from_secs(4);
+// Class specific HID requests to the interface, host to device and device to host
+pub(crate) const REQUEST_TYPE_OUT: u8 = 0x21;
+pub(crate) const REQUEST_TYPE_IN: u8 = 0xa1;
+pub(crate) const SET_REPORT: u8 = 0x09;
+pub(crate) const GET_REPORT: u8 = 0x01;
+// Feature report (0x03) with report id 0xff
+pub(crate) const REPORT_VALUE: u16 = 0x03ff;
+
+/// Size of every feature report exchanged with the headset
+pub const REPORT_LEN: usize = 64;
+
+/// Moves feature reports between a `Device` and the headset
+pub trait Transport {
+    /// Sends a feature report (SET_REPORT) to the headset
+    fn send_report(&self, report: &[u8]) -> Result<usize>;
+
+    /// Reads the pending feature report (GET_REPORT) from the headset into `buffer`
+    fn receive_report(&self, buffer: &mut [u8]) -> Result<usize>;
+
+    /// USB product id of the headset's dongle, see `Model`.
+    /// Transports that can not tell assume the Nari Ultimate.
+    fn product_id(&self) -> Result<u16> {
+        Ok(NARI_ULTIMATE.product_id)
+    }
+
+    /// Where the dongle sits on the USB bus, `None` for transports without one
+    fn device_info(&self) -> Result<Option<DeviceInfo>> {
+        Ok(None)
+    }
+
+    /// Sends `report` and reads the answer into `buffer` with nothing else in between.
+    /// Transports shared by several programs override this to make the pair atomic.
+    fn exchange(&self, report: &[u8], buffer: &mut [u8]) -> Result<usize> {
+        check_written(report.len(), self.send_report(report)?)?;
+        self.receive_report(buffer)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send_report(&self, report: &[u8]) -> Result<usize> {
+        (**self).send_report(report)
+    }
+
+    fn receive_report(&self, buffer: &mut [u8]) -> Result<usize> {
+        (**self).receive_report(buffer)
+    }
+
+    fn product_id(&self) -> Result<u16> {
+        (**self).product_id()
+    }
+
+    fn device_info(&self) -> Result<Option<DeviceInfo>> {
+        (**self).device_info()
+    }
+
+    fn exchange(&self, report: &[u8], buffer: &mut [u8]) -> Result<usize> {
+        (**self).exchange(report, buffer)
+    }
+}
+
+/// Talks to the headset over HID interface 5 of the USB dongle.
+///
+/// The interface is claimed again on the next report after the dongle was replugged,
+/// so the same transport keeps working across disconnects. Select dongles by serial
+/// or port rather than by address to keep finding the same one after a replug.
+pub struct UsbTransport {
+    selector: DeviceSelector,
+    interface: RefCell<Option<Interface>>,
+}
+
+impl UsbTransport {
+    /// Opens the first headset right away, failing when no dongle is plugged in
+    pub fn open() -> Result<Self> {
+        Self::open_selected(DeviceSelector::First)
+    }
+
+    /// Opens the headset matching `selector` right away
+    pub fn open_selected(selector: DeviceSelector) -> Result<Self> {
+        let interface = Interface::claim(&selector)?;
+        Ok(UsbTransport {
+            selector,
+            interface: RefCell::new(Some(interface)),
+        })
+    }
+
+    /// Creates a transport that only opens the first headset when the first report is sent
+    pub fn new() -> Self {
+        Self::with_selector(DeviceSelector::First)
+    }
+
+    /// Like `new`, for the headset matching `selector`
+    pub fn with_selector(selector: DeviceSelector) -> Self {
+        UsbTransport {
+            selector,
+            interface: RefCell::new(None),
+        }
+    }
+
+    pub fn selector(&self) -> &DeviceSelector {
+        &self.selector
+    }
+
+    /// Whether the interface is currently claimed
+    pub fn is_open(&self) -> bool {
+        self.interface.borrow().is_some()
+    }
+
+    fn with_handle<R>(
+        &self,
+        mut operation: impl FnMut(&DeviceHandle<Context>) -> rusb::Result<R>,
+    ) -> Result<R> {
+        let mut interface = self.interface.borrow_mut();
+        // A replugged dongle is a new device, so a stale handle is dropped and opened once more
+        for retry in [true, false] {
+            if interface.is_none() {
+                *interface = Some(Interface::claim(&self.selector)?);
+            }
+            let handle = &interface
+                .as_ref()
+                .expect("the interface was just claimed")
+                .handle;
+            match operation(handle) {
+                Err(e @ (Error::NoDevice | Error::Io)) => {
+                    debug!("Lost the dongle ({e}), opening it again");
+                    *interface = None;
+                    if !retry {
+                        return Err(NariError::DeviceNotFound);
+                    }
+                }
+                result => return Ok(result?),
+            }
+        }
+        Err(NariError::DeviceNotFound)
+    }
+}
+
+impl Default for UsbTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for UsbTransport {
+    fn send_report(&self, report: &[u8]) -> Result<usize> {
+        let start = Instant::now();
+        let result = self.with_handle(|handle| {
+            handle.write_control(
+                REQUEST_TYPE_OUT,
+                SET_REPORT,
+                REPORT_VALUE,
+                HID as u16,
+                report,
+                TIMEOUT,
+            )
+        });
+        trace_transfer(REQUEST_TYPE_OUT, SET_REPORT, report, start, &result);
+        result
+    }
+
+    fn receive_report(&self, buffer: &mut [u8]) -> Result<usize> {
+        let start = Instant::now();
+        let result = self.with_handle(|handle| {
+            handle.read_control(
+                REQUEST_TYPE_IN,
+                GET_REPORT,
+                REPORT_VALUE,
+                HID as u16,
+                buffer,
+                TIMEOUT,
+            )
+        });
+        let read = *result.as_ref().unwrap_or(&0);
+        trace_transfer(REQUEST_TYPE_IN, GET_REPORT, &buffer[..read], start, &result);
+        result
+    }
+
+    fn product_id(&self) -> Result<u16> {
+        self.with_handle(|handle| Ok(handle.device().device_descriptor()?.product_id()))
+    }
+
+    fn device_info(&self) -> Result<Option<DeviceInfo>> {
+        let device = self.with_handle(|handle| Ok(handle.device()))?;
+        let interface = self.interface.borrow();
+        Ok(Some(describe(
+            &device,
+            interface.as_ref().map(|interface| &interface.handle),
+        )?))
+    }
+}
+
+/// Claimed HID interface, given back to the kernel driver when dropped
+struct Interface {
+    handle: DeviceHandle<Context>,
+}
+
+impl Interface {
+    fn claim(selector: &DeviceSelector) -> Result<Self> {
+        let context = Context::new()?;
+        let handle = open_device(&context, selector)?;
+        if handle.kernel_driver_active(HID).is_ok_and(|yes| yes)
+            && let Err(e) = handle.detach_kernel_driver(HID)
+            && e != Error::NotSupported
+            && e != Error::NotFound
+        {
+            return Err(NariError::DetachKernelDriver(e));
+        }
+        match handle.claim_interface(HID) {
+            Ok(()) => Ok(Interface { handle }),
+            Err(Error::Access) => Err(NariError::PermissionDenied),
+            Err(e) => Err(NariError::ClaimInterface(e)),
+        }
+    }
+}
+
+impl Drop for Interface {
+    fn drop(&mut self) {
+        match self.handle.release_interface(HID) {
+            Ok(()) => {
+                if let Err(e) = self.handle.attach_kernel_driver(HID)
+                    && e != Error::NotFound
+                {
+                    warn!("Failed to attach kernel driver ({e})");
+                }
+            }
+            // Nothing to give back once the dongle is gone
+            Err(Error::NoDevice) => {}
+            Err(e) => warn!("Failed to release interface ({e})"),
+        }
+    }
+}
+
+/// In-memory stand-in for the headset.
+///
+/// Every report sent is recorded and can be inspected with `sent()`; reads are
+/// answered from the responses queued with `push_response()`, in order. A read
+/// with nothing queued fails with `NariError::Timeout`, like an unanswered request would.
+#[derive(Default)]
+pub struct MockTransport {
+    sent: RefCell<Vec<Vec<u8>>>,
+    responses: RefCell<VecDeque<Vec<u8>>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a response for the next `receive_report()` call
+    pub fn push_response<T: Into<Vec<u8>>>(&self, response: T) {
+        self.responses.borrow_mut().push_back(response.into());
+    }
+
+    /// Returns every report sent so far, oldest first
+    pub fn sent(&self) -> Vec<Vec<u8>> {
+        self.sent.borrow().clone()
+    }
+
+    pub fn clear(&self) {
+        self.sent.borrow_mut().clear();
+        self.responses.borrow_mut().clear();
+    }
+}
+
+impl Transport for MockTransport {
+    fn send_report(&self, report: &[u8]) -> Result<usize> {
+        self.sent.borrow_mut().push(report.to_vec());
+        Ok(report.len())
+    }
+
+    fn receive_report(&self, buffer: &mut [u8]) -> Result<usize> {
+        let response = self
+            .responses
+            .borrow_mut()
+            .pop_front()
+            .ok_or(NariError::Timeout)?;
+        let len = response.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&response[..len]);
+        Ok(len)
+    }
+}
+
+pub(crate) fn check_written(expected: usize, written: usize) -> Result<()> {
+    if written < expected {
+        return Err(NariError::ShortWrite { expected, written });
+    }
+    Ok(())
+}
+
+fn open_device(context: &Context, selector: &DeviceSelector) -> Result<DeviceHandle<Context>> {
+    let (device, _) = select(context, selector)?;
+    debug!("Found headset, attempting to open device");
+    device.open().map_err(|e| {
+        warn!("Device found but failed to open: {e}");
+        e.into()
+    })
+}
+
+/// Logs a control transfer on the `trace` level: request, payload and how it went
+fn trace_transfer(
+    request_type: u8,
+    request: u8,
+    data: &[u8],
+    start: Instant,
+    result: &Result<usize>,
+) {
+    if !log_enabled!(log::Level::Trace) {
+        return;
+    }
+    let outcome = match result {
+        Ok(len) => format!("{len} bytes"),
+        Err(e) => format!("failed: {e}"),
+    };
+    trace!(
+        "{} request_type={request_type:#04x} request={request:#04x} value={REPORT_VALUE:#06x} \
+        index={HID} data={} -> {outcome} in {:?}",
+        if request_type == REQUEST_TYPE_OUT {
+            "write_control"
+        } else {
+            "read_control"
+        },
+        encode_hex(data),
+        start.elapsed()
+    );
+}
@@ -0,0 +1,672 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::color::{ColorPicker, ColorSpace};
+use crate::worker::{Answer, Request};
+use narictl_lib::*;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+// Gives udev a moment to set the permissions of a freshly plugged dongle
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const DEFAULT_COLOR: (u8, u8, u8) = (0, 255, 0);
+const DEFAULT_SECOND_COLOR: (u8, u8, u8) = (0, 0, 255);
+/// Lowest haptic intensity the slider goes to, the same as in NariCTL_UI
+pub const HAPTICS_MIN: u8 = 20;
+pub const BREATHING_OPTIONS: [&str; 3] = ["Single Color", "Two Colors", "Random"];
+// Used when the headset state cannot be read back
+const DEFAULT_STATE: DeviceState = DeviceState {
+    effect: LightingEffect::Off,
+    brightness: 60,
+    haptics_enabled: false,
+    haptic_intensity: 60,
+    mic_monitor: MIC_MONITOR_OFF,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Page {
+    General,
+    Lighting,
+    About,
+}
+
+impl Page {
+    pub const ALL: [Page; 3] = [Page::General, Page::Lighting, Page::About];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Page::General => "General",
+            Page::Lighting => "Lighting",
+            Page::About => "About",
+        }
+    }
+}
+
+/// A row of a page that can be selected and changed with the keyboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    MicMonitor,
+    Haptics,
+    Profiles,
+    Effect,
+    Breathing,
+    Brightness,
+    /// A channel of the first color
+    Color(usize),
+    /// A channel of the second breathing color
+    SecondColor(usize),
+}
+
+pub struct App {
+    /// Requests for the thread owning the headset, see `worker.rs`
+    requests: Sender<Request>,
+    pub connected: bool,
+    // Decides which controls are shown, the Nari Ultimate until a headset answers
+    pub model: &'static Model,
+    pub page: Page,
+    /// Index of the selected row in `controls()`
+    pub selected: usize,
+    pub brightness: u8,
+    pub haptics_enabled: bool,
+    pub haptics: u8,
+    pub mic_monitor_enabled: bool,
+    /// Raw mic monitor value, kept while mic monitoring is off
+    pub mic_monitor: u8,
+    pub mode: LightingMode,
+    pub breathing: usize,
+    pub color: ColorPicker,
+    pub second_color: ColorPicker,
+    pub color_space: ColorSpace,
+    pub battery: Option<Battery>,
+    pub identity: Option<Identity>,
+    pub profiles: Profiles,
+    pub profile: usize,
+    /// Name of the profile being typed, `None` when not saving one
+    pub profile_name: Option<String>,
+    /// Last error or confirmation, shown at the bottom
+    pub message: Option<String>,
+    reconnect_at: Option<Instant>,
+    pub quit: bool,
+}
+
+impl App {
+    /// Starts with the default settings and asks the headset for its own
+    pub fn new(requests: Sender<Request>, profiles: Result<Profiles>) -> Self {
+        let mut message = None;
+        let profiles = profiles.unwrap_or_else(|e| {
+            message = Some(format!("Unable to load the profiles. Error: {e}"));
+            Profiles::default()
+        });
+        let mut app = App {
+            requests,
+            connected: false,
+            model: &NARI_ULTIMATE,
+            page: Page::General,
+            selected: 0,
+            brightness: 0,
+            haptics_enabled: false,
+            haptics: 0,
+            mic_monitor_enabled: false,
+            mic_monitor: mic_monitor_to_raw(DEFAULT_MIC_MONITOR_LEVEL),
+            mode: LightingMode::Off,
+            breathing: 0,
+            color: ColorPicker::new(DEFAULT_COLOR),
+            second_color: ColorPicker::new(DEFAULT_SECOND_COLOR),
+            color_space: ColorSpace::Rgb,
+            battery: None,
+            identity: None,
+            profiles,
+            profile: 0,
+            profile_name: None,
+            message,
+            reconnect_at: None,
+            quit: false,
+        };
+        app.set_state(&DEFAULT_STATE);
+        app.reconnect();
+        app
+    }
+
+    /// Rows of the current page, the ones the model lacks are left out
+    pub fn controls(&self) -> Vec<Control> {
+        let capabilities = self.model.capabilities;
+        let mut controls = Vec::new();
+        match self.page {
+            Page::General => {
+                if capabilities.sidetone {
+                    controls.push(Control::MicMonitor);
+                }
+                if capabilities.haptics {
+                    controls.push(Control::Haptics);
+                }
+                controls.push(Control::Profiles);
+            }
+            Page::Lighting if capabilities.lighting() => {
+                controls.push(Control::Effect);
+                if self.mode == LightingMode::Breathing {
+                    controls.push(Control::Breathing);
+                }
+                controls.push(Control::Brightness);
+                if self.uses_color() {
+                    controls.extend((0..3).map(Control::Color));
+                }
+                if self.uses_second_color() {
+                    controls.extend((0..3).map(Control::SecondColor));
+                }
+            }
+            Page::Lighting | Page::About => {}
+        }
+        controls
+    }
+
+    pub fn selected_control(&self) -> Option<Control> {
+        self.controls().get(self.selected).copied()
+    }
+
+    pub fn uses_color(&self) -> bool {
+        self.mode == LightingMode::Static
+            || (self.mode == LightingMode::Breathing && self.breathing < 2)
+    }
+
+    pub fn uses_second_color(&self) -> bool {
+        self.mode == LightingMode::Breathing && self.breathing == 1
+    }
+
+    pub fn key(&mut self, key: KeyEvent) {
+        if self.profile_name.is_some() {
+            self.profile_name_key(key);
+            return;
+        }
+        let step = if key.modifiers.contains(KeyModifiers::SHIFT) {
+            10
+        } else {
+            1
+        };
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+            KeyCode::Tab => self.switch_page(1),
+            KeyCode::BackTab => self.switch_page(Page::ALL.len() - 1),
+            KeyCode::Char(digit @ '1'..='3') => {
+                self.page = Page::ALL[digit as usize - '1' as usize];
+                self.selected = 0;
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(self.controls().len().saturating_sub(1))
+            }
+            KeyCode::Left | KeyCode::Char('h') => self.adjust(-step),
+            KeyCode::Right | KeyCode::Char('l') => self.adjust(step),
+            KeyCode::PageDown => self.adjust(-10),
+            KeyCode::PageUp => self.adjust(10),
+            KeyCode::Char('H') => self.adjust(-10),
+            KeyCode::Char('L') => self.adjust(10),
+            KeyCode::Char(' ') | KeyCode::Enter => self.activate(),
+            KeyCode::Char('m') => self.color_space = self.color_space.toggle(),
+            KeyCode::Char('s') if self.selected_control() == Some(Control::Profiles) => {
+                self.profile_name = Some(String::new())
+            }
+            KeyCode::Char('d') if self.selected_control() == Some(Control::Profiles) => {
+                self.delete_profile()
+            }
+            KeyCode::Char('r') => self.reconnect(),
+            _ => {}
+        }
+    }
+
+    fn profile_name_key(&mut self, key: KeyEvent) {
+        let Some(name) = &mut self.profile_name else {
+            return;
+        };
+        match key.code {
+            KeyCode::Esc => self.profile_name = None,
+            KeyCode::Enter => {
+                let name = name.trim().to_string();
+                self.profile_name = None;
+                self.save_profile(&name);
+            }
+            KeyCode::Backspace => {
+                name.pop();
+            }
+            KeyCode::Char(c) => name.push(c),
+            _ => {}
+        }
+    }
+
+    fn switch_page(&mut self, offset: usize) {
+        let index = Page::ALL
+            .iter()
+            .position(|page| *page == self.page)
+            .unwrap_or(0);
+        self.page = Page::ALL[(index + offset) % Page::ALL.len()];
+        self.selected = 0;
+    }
+
+    /// Moves the selected slider or choice by `delta`
+    fn adjust(&mut self, delta: i32) {
+        let Some(control) = self.selected_control() else {
+            return;
+        };
+        let percentage = |value: u8, min: u8| (value as i32 + delta).clamp(min as i32, 100) as u8;
+        match control {
+            Control::MicMonitor => {
+                // The headset only takes even raw values, which are 4 levels apart
+                let level = mic_monitor_from_raw(self.mic_monitor) as i32 + delta * 4;
+                let level = level.clamp(0, MIC_MONITOR_MAX_LEVEL as i32) as u8;
+                let value = mic_monitor_to_raw(level);
+                self.mic_monitor = value;
+                if self.mic_monitor_enabled {
+                    self.send(Request::SetMicMonitor(value));
+                }
+            }
+            Control::Haptics => {
+                self.haptics = percentage(self.haptics, HAPTICS_MIN);
+                if self.haptics_enabled {
+                    self.send(Request::SetHaptics(self.haptics, true));
+                }
+            }
+            Control::Profiles => {
+                let count = self.profiles.profiles.len().max(1) as i32;
+                self.profile = (self.profile as i32 + delta.signum()).rem_euclid(count) as usize;
+            }
+            Control::Effect => {
+                let count = LightingMode::ALL.len() as i32;
+                let index = LightingMode::ALL
+                    .iter()
+                    .position(|mode| *mode == self.mode)
+                    .unwrap_or(0) as i32;
+                self.mode = LightingMode::ALL[(index + delta.signum()).rem_euclid(count) as usize];
+                self.apply_effect();
+            }
+            Control::Breathing => {
+                let count = BREATHING_OPTIONS.len() as i32;
+                self.breathing =
+                    (self.breathing as i32 + delta.signum()).rem_euclid(count) as usize;
+                self.apply_effect();
+            }
+            Control::Brightness => {
+                self.brightness = percentage(self.brightness, 0);
+                self.send(Request::SetBrightness(self.brightness));
+            }
+            Control::Color(channel) => {
+                self.color.adjust(self.color_space, channel, delta);
+                self.apply_effect();
+            }
+            Control::SecondColor(channel) => {
+                self.second_color.adjust(self.color_space, channel, delta);
+                self.apply_effect();
+            }
+        }
+    }
+
+    /// Turns the selected switch on or off, or loads the selected profile
+    fn activate(&mut self) {
+        match self.selected_control() {
+            Some(Control::MicMonitor) => {
+                self.mic_monitor_enabled = !self.mic_monitor_enabled;
+                self.send(Request::SetMicMonitor(self.state().mic_monitor));
+            }
+            Some(Control::Haptics) => {
+                self.haptics_enabled = !self.haptics_enabled;
+                self.send(Request::SetHaptics(self.haptics, self.haptics_enabled));
+            }
+            Some(Control::Profiles) => self.load_profile(),
+            _ => {}
+        }
+    }
+
+    pub fn profile_names(&self) -> Vec<&str> {
+        self.profiles.profiles.keys().map(String::as_str).collect()
+    }
+
+    fn selected_profile(&self) -> Option<String> {
+        self.profile_names()
+            .get(self.profile)
+            .map(|name| name.to_string())
+    }
+
+    fn load_profile(&mut self) {
+        let Some(name) = self.selected_profile() else {
+            return;
+        };
+        let state = match self.profiles.get(&name) {
+            Ok(profile) => profile.state(),
+            Err(e) => {
+                return self.message = Some(format!("Unable to load the profile, Error: {e}"));
+            }
+        };
+        self.send(Request::ApplyState(state));
+        self.message = Some(format!("Loaded profile {name}"));
+        self.set_state(&state);
+    }
+
+    fn save_profile(&mut self, name: &str) {
+        let profile = Profile::from(self.state());
+        match self
+            .profiles
+            .insert(name, profile)
+            .and_then(|_| self.profiles.save())
+        {
+            Ok(()) => self.message = Some(format!("Saved profile {name}")),
+            Err(e) => self.error("save the profile", e),
+        }
+    }
+
+    fn delete_profile(&mut self) {
+        let Some(name) = self.selected_profile() else {
+            return;
+        };
+        match self
+            .profiles
+            .remove(&name)
+            .and_then(|_| self.profiles.save())
+        {
+            Ok(_) => {
+                self.message = Some(format!("Deleted profile {name}"));
+                self.profile = self.profile.saturating_sub(1);
+            }
+            Err(e) => self.error("delete the profile", e),
+        }
+    }
+
+    pub fn hotplug(&mut self, event: HotplugEvent) {
+        match event {
+            HotplugEvent::Connected => self.reconnect_at = Some(Instant::now() + RECONNECT_DELAY),
+            HotplugEvent::Disconnected => {
+                self.connected = false;
+                self.battery = None;
+                self.identity = None;
+                // Another dongle leaving does not concern this one, it answers again right away
+                self.reconnect_at = Some(Instant::now() + RECONNECT_DELAY);
+            }
+        }
+    }
+
+    /// Runs a reconnect scheduled by `hotplug` once its delay is over
+    pub fn tick(&mut self) {
+        if self.reconnect_at.is_some_and(|at| at <= Instant::now()) {
+            self.reconnect_at = None;
+            self.reconnect();
+        }
+    }
+
+    fn reconnect(&mut self) {
+        self.send(Request::Connect);
+    }
+
+    pub fn refresh_battery(&mut self) {
+        if self.model.capabilities.battery {
+            self.send(Request::ReadBattery);
+        }
+    }
+
+    /// Takes in what the headset answered to an earlier request
+    pub fn answer(&mut self, answer: Answer) {
+        match answer {
+            Answer::Connected {
+                model,
+                state,
+                identity,
+            } => {
+                self.model = model;
+                self.identity = Some(identity);
+                self.connected = true;
+                self.message = None;
+                match state {
+                    Ok(state) => self.set_state(&state),
+                    Err(e) => {
+                        self.message = Some(format!(
+                            "Unable to read the headset state, using defaults. Error: {e}"
+                        ))
+                    }
+                }
+                self.selected = self.selected.min(self.controls().len().saturating_sub(1));
+                self.refresh_battery();
+            }
+            Answer::Waiting(e) => {
+                self.message = Some(format!("Waiting for the headset, Error: {e}"))
+            }
+            Answer::Battery(Ok(battery)) => {
+                self.battery = Some(battery);
+                // The headset answering again after a while means it woke up
+                if !self.connected {
+                    self.reconnect();
+                }
+            }
+            Answer::Battery(Err(e)) => {
+                if self.connected {
+                    self.message = Some(format!("Unable to get battery data, Error: {e}"));
+                }
+                self.battery = None;
+            }
+            Answer::Failed { action, error } => self.error(action, error),
+        }
+    }
+
+    fn send(&mut self, request: Request) {
+        // Only fails once the thread owning the headset is gone
+        if self.requests.send(request).is_err() {
+            self.message = Some(String::from("Lost the connection to the headset thread"));
+        }
+    }
+
+    fn apply_effect(&mut self) {
+        self.send(Request::SetEffect(self.effect()));
+    }
+
+    fn error(&mut self, action: &str, e: NariError) {
+        self.message = Some(format!(
+            "An error occurred while trying to {action}, Error: {e}"
+        ));
+    }
+
+    /// Takes over every setting of `state`, keeping the unused colors and levels as they are
+    fn set_state(&mut self, state: &DeviceState) {
+        self.brightness = state.brightness;
+        self.haptics_enabled = state.haptics_enabled;
+        self.haptics = state.haptic_intensity;
+        self.mic_monitor_enabled = state.mic_monitor != MIC_MONITOR_OFF;
+        if self.mic_monitor_enabled {
+            self.mic_monitor = state.mic_monitor;
+        }
+        self.mode = state.effect.mode();
+        if let Some(color) = state.effect.color() {
+            self.color.set_rgb(color);
+        }
+        if let LightingEffect::Breathing(breathing) = state.effect {
+            self.breathing = match breathing {
+                Breathing::Single(_) => 0,
+                Breathing::Dual(_, color) => {
+                    self.second_color.set_rgb(color);
+                    1
+                }
+                Breathing::Random => 2,
+            };
+        }
+    }
+
+    fn state(&self) -> DeviceState {
+        DeviceState {
+            effect: self.effect(),
+            brightness: self.brightness,
+            haptics_enabled: self.haptics_enabled,
+            haptic_intensity: self.haptics,
+            mic_monitor: if self.mic_monitor_enabled {
+                self.mic_monitor
+            } else {
+                MIC_MONITOR_OFF
+            },
+        }
+    }
+
+    pub fn effect(&self) -> LightingEffect {
+        match self.mode {
+            LightingMode::Off => LightingEffect::Off,
+            LightingMode::Static => LightingEffect::Static(self.color.rgb()),
+            LightingMode::Breathing => LightingEffect::Breathing(match self.breathing {
+                0 => Breathing::Single(self.color.rgb()),
+                1 => Breathing::Dual(self.color.rgb(), self.second_color.rgb()),
+                _ => Breathing::Random,
+            }),
+            LightingMode::Spectrum => LightingEffect::Spectrum,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{self, Receiver};
+
+    fn app() -> (App, Receiver<Request>) {
+        let (requests, sent) = mpsc::channel();
+        let app = App::new(requests, Ok(Profiles::default()));
+        assert_eq!(sent.try_recv(), Ok(Request::Connect));
+        (app, sent)
+    }
+
+    fn press(app: &mut App, code: KeyCode) {
+        app.key(KeyEvent::new(code, KeyModifiers::NONE));
+    }
+
+    fn requests(sent: &Receiver<Request>) -> Vec<Request> {
+        sent.try_iter().collect()
+    }
+
+    #[test]
+    fn pages_are_switched_with_tab_and_digits() {
+        let (mut app, _sent) = app();
+        press(&mut app, KeyCode::Down);
+        assert_eq!(app.selected, 1);
+        press(&mut app, KeyCode::Tab);
+        assert_eq!((app.page, app.selected), (Page::Lighting, 0));
+        press(&mut app, KeyCode::BackTab);
+        press(&mut app, KeyCode::BackTab);
+        assert_eq!(app.page, Page::About);
+        press(&mut app, KeyCode::Char('1'));
+        assert_eq!(app.page, Page::General);
+    }
+
+    #[test]
+    fn the_selection_stays_on_the_page() {
+        let (mut app, _sent) = app();
+        press(&mut app, KeyCode::Up);
+        assert_eq!(app.selected, 0);
+        for _ in 0..5 {
+            press(&mut app, KeyCode::Char('j'));
+        }
+        assert_eq!(app.selected_control(), Some(Control::Profiles));
+    }
+
+    #[test]
+    fn sliders_send_the_new_value() {
+        let (mut app, sent) = app();
+        press(&mut app, KeyCode::Char('2'));
+        press(&mut app, KeyCode::Down);
+        assert_eq!(app.selected_control(), Some(Control::Brightness));
+        press(&mut app, KeyCode::Right);
+        app.key(KeyEvent::new(KeyCode::Right, KeyModifiers::SHIFT));
+        press(&mut app, KeyCode::PageUp);
+        press(&mut app, KeyCode::PageUp);
+        press(&mut app, KeyCode::PageUp);
+        assert_eq!(
+            requests(&sent),
+            [61, 71, 81, 91, 100].map(Request::SetBrightness)
+        );
+        assert_eq!(app.brightness, 100);
+    }
+
+    #[test]
+    fn switches_toggle_and_sliders_wait_for_them() {
+        let (mut app, sent) = app();
+        assert_eq!(app.selected_control(), Some(Control::MicMonitor));
+        // Mic monitoring is off, the level is only kept for later
+        press(&mut app, KeyCode::Right);
+        assert!(requests(&sent).is_empty());
+        press(&mut app, KeyCode::Enter);
+        assert!(app.mic_monitor_enabled);
+        press(&mut app, KeyCode::Char(' '));
+        assert_eq!(
+            requests(&sent),
+            [
+                Request::SetMicMonitor(app.mic_monitor),
+                Request::SetMicMonitor(MIC_MONITOR_OFF),
+            ]
+        );
+
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Enter);
+        press(&mut app, KeyCode::Left);
+        assert_eq!(
+            requests(&sent),
+            [Request::SetHaptics(60, true), Request::SetHaptics(59, true)]
+        );
+    }
+
+    #[test]
+    fn the_effect_is_sent_when_it_changes() {
+        let (mut app, sent) = app();
+        press(&mut app, KeyCode::Tab);
+        press(&mut app, KeyCode::Right);
+        assert_eq!(app.mode, LightingMode::Static);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Down);
+        assert_eq!(app.selected_control(), Some(Control::Color(0)));
+        press(&mut app, KeyCode::Right);
+        assert_eq!(
+            requests(&sent),
+            [
+                Request::SetEffect(LightingEffect::Static(DEFAULT_COLOR)),
+                Request::SetEffect(LightingEffect::Static((1, 255, 0))),
+            ]
+        );
+    }
+
+    #[test]
+    fn keys_go_to_the_profile_name_while_typing_it() {
+        let (mut app, sent) = app();
+        press(&mut app, KeyCode::Char('s'));
+        assert_eq!(app.profile_name, None);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Char('s'));
+        for c in "quiet".chars() {
+            press(&mut app, KeyCode::Char(c));
+        }
+        press(&mut app, KeyCode::Backspace);
+        assert_eq!(app.profile_name.as_deref(), Some("quie"));
+        press(&mut app, KeyCode::Esc);
+        assert_eq!(app.profile_name, None);
+        assert!(!app.quit);
+        assert!(requests(&sent).is_empty());
+
+        press(&mut app, KeyCode::Char('q'));
+        assert!(app.quit);
+    }
+
+    #[test]
+    fn answers_update_the_state() {
+        let (mut app, sent) = app();
+        let state = DeviceState {
+            brightness: 30,
+            ..DEFAULT_STATE
+        };
+        app.answer(Answer::Connected {
+            model: &NARI_ULTIMATE,
+            state: Ok(state),
+            identity: Identity::default(),
+        });
+        assert!(app.connected);
+        assert_eq!(app.brightness, 30);
+        assert_eq!(requests(&sent), [Request::ReadBattery]);
+
+        app.answer(Answer::Failed {
+            action: "change the brightness",
+            error: NariError::Timeout,
+        });
+        assert!(app.message.is_some());
+    }
+}
@@ -0,0 +1,190 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    Rgb,
+    Hsv,
+}
+
+impl ColorSpace {
+    pub fn toggle(self) -> Self {
+        match self {
+            ColorSpace::Rgb => ColorSpace::Hsv,
+            ColorSpace::Hsv => ColorSpace::Rgb,
+        }
+    }
+}
+
+/// A color edited one channel at a time, as red, green and blue or as hue, saturation and value.
+///
+/// The HSV channels are kept on their own so the hue survives going through gray or black.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorPicker {
+    rgb: (u8, u8, u8),
+    /// Hue from 0 to 359, saturation and value from 0 to 100
+    hsv: (u16, u8, u8),
+}
+
+impl ColorPicker {
+    pub fn new(rgb: (u8, u8, u8)) -> Self {
+        ColorPicker {
+            rgb,
+            hsv: rgb_to_hsv(rgb),
+        }
+    }
+
+    pub fn rgb(&self) -> (u8, u8, u8) {
+        self.rgb
+    }
+
+    pub fn set_rgb(&mut self, rgb: (u8, u8, u8)) {
+        if rgb != self.rgb {
+            *self = ColorPicker::new(rgb);
+        }
+    }
+
+    /// Name, value and largest value of the three channels
+    pub fn channels(&self, space: ColorSpace) -> [(&'static str, u16, u16); 3] {
+        let (r, g, b) = self.rgb;
+        let (h, s, v) = self.hsv;
+        match space {
+            ColorSpace::Rgb => [
+                ("Red", r.into(), 255),
+                ("Green", g.into(), 255),
+                ("Blue", b.into(), 255),
+            ],
+            ColorSpace::Hsv => [
+                ("Hue", h, 359),
+                ("Saturation", s.into(), 100),
+                ("Value", v.into(), 100),
+            ],
+        }
+    }
+
+    /// Moves `channel` by `delta`, the hue wraps around while the others stop at their ends
+    pub fn adjust(&mut self, space: ColorSpace, channel: usize, delta: i32) {
+        let step = |value: u8, max: i32| (value as i32 + delta).clamp(0, max) as u8;
+        match space {
+            ColorSpace::Rgb => {
+                let (mut r, mut g, mut b) = self.rgb;
+                match channel {
+                    0 => r = step(r, 255),
+                    1 => g = step(g, 255),
+                    _ => b = step(b, 255),
+                }
+                self.set_rgb((r, g, b));
+            }
+            ColorSpace::Hsv => {
+                let (mut h, mut s, mut v) = self.hsv;
+                match channel {
+                    0 => h = (h as i32 + delta).rem_euclid(360) as u16,
+                    1 => s = step(s, 100),
+                    _ => v = step(v, 100),
+                }
+                self.hsv = (h, s, v);
+                self.rgb = hsv_to_rgb(self.hsv);
+            }
+        }
+    }
+}
+
+fn rgb_to_hsv((r, g, b): (u8, u8, u8)) -> (u16, u8, u8) {
+    let (r, g, b) = (r as f32 / 255., g as f32 / 255., b as f32 / 255.);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    let hue = if delta == 0. {
+        0.
+    } else if max == r {
+        60. * ((g - b) / delta).rem_euclid(6.)
+    } else if max == g {
+        60. * ((b - r) / delta + 2.)
+    } else {
+        60. * ((r - g) / delta + 4.)
+    };
+    let saturation = if max == 0. { 0. } else { delta / max };
+    (
+        hue.round() as u16 % 360,
+        (saturation * 100.).round() as u8,
+        (max * 100.).round() as u8,
+    )
+}
+
+fn hsv_to_rgb((h, s, v): (u16, u8, u8)) -> (u8, u8, u8) {
+    let (s, v) = (s as f32 / 100., v as f32 / 100.);
+    let chroma = v * s;
+    let sector = h as f32 / 60.;
+    let x = chroma * (1. - (sector.rem_euclid(2.) - 1.).abs());
+    let (r, g, b) = match sector as u8 {
+        0 => (chroma, x, 0.),
+        1 => (x, chroma, 0.),
+        2 => (0., chroma, x),
+        3 => (0., x, chroma),
+        4 => (x, 0., chroma),
+        _ => (chroma, 0., x),
+    };
+    let m = v - chroma;
+    let channel = |value: f32| ((value + m) * 255.).round() as u8;
+    (channel(r), channel(g), channel(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primary_and_secondary_colors_convert_exactly() {
+        let colors = [
+            ((0, 0, 0), (0, 0, 0)),
+            ((255, 255, 255), (0, 0, 100)),
+            ((255, 0, 0), (0, 100, 100)),
+            ((255, 255, 0), (60, 100, 100)),
+            ((0, 255, 0), (120, 100, 100)),
+            ((0, 255, 255), (180, 100, 100)),
+            ((0, 0, 255), (240, 100, 100)),
+            ((255, 0, 255), (300, 100, 100)),
+        ];
+        for (rgb, hsv) in colors {
+            assert_eq!(rgb_to_hsv(rgb), hsv);
+            assert_eq!(hsv_to_rgb(hsv), rgb);
+        }
+    }
+
+    #[test]
+    fn rgb_survives_a_round_trip_through_hsv() {
+        // Saturation and value are whole percents, about 2.5 steps of a channel each
+        for r in (0..=255).step_by(5) {
+            for g in (0..=255).step_by(5) {
+                for b in (0..=255).step_by(5) {
+                    let (r2, g2, b2) = hsv_to_rgb(rgb_to_hsv((r, g, b)));
+                    assert!(
+                        r.abs_diff(r2) <= 3 && g.abs_diff(g2) <= 3 && b.abs_diff(b2) <= 3,
+                        "({r}, {g}, {b}) came back as ({r2}, {g2}, {b2})"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hue_survives_black() {
+        let mut color = ColorPicker::new((0, 0, 255));
+        color.adjust(ColorSpace::Hsv, 2, -100);
+        assert_eq!(color.rgb(), (0, 0, 0));
+        color.adjust(ColorSpace::Hsv, 2, 100);
+        assert_eq!(color.rgb(), (0, 0, 255));
+    }
+
+    #[test]
+    fn hue_wraps_and_the_others_stop() {
+        let mut color = ColorPicker::new((255, 0, 0));
+        color.adjust(ColorSpace::Hsv, 0, -120);
+        assert_eq!(color.rgb(), (0, 0, 255));
+        color.adjust(ColorSpace::Hsv, 1, 10);
+        assert_eq!(color.channels(ColorSpace::Hsv)[1].1, 100);
+        color.adjust(ColorSpace::Rgb, 0, 300);
+        assert_eq!(color.rgb(), (255, 0, 255));
+    }
+}
@@ -0,0 +1,67 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+mod app;
+mod color;
+mod ui;
+mod worker;
+
+use app::App;
+use narictl_lib::{HotplugWatcher, Profiles};
+use ratatui::DefaultTerminal;
+use ratatui::crossterm::event::{self, Event, KeyEventKind};
+use std::io;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+use worker::Answer;
+
+const BATTERY_INTERVAL: Duration = Duration::from_secs(30);
+// How long to wait for a key before looking for answers and hotplug events again
+const TICK: Duration = Duration::from_millis(250);
+
+fn main() -> io::Result<()> {
+    // Log messages would be drawn over the interface, so nothing is logged unless asked for,
+    // ex: `NARICTL_LOG=trace narictl_tui 2> trace.log`
+    env_logger::Builder::from_env(env_logger::Env::new().filter_or("NARICTL_LOG", "off")).init();
+    let (requests, answers) = worker::spawn();
+    let mut app = App::new(requests, Profiles::load());
+    let hotplug = HotplugWatcher::new()
+        .map_err(|e| app.message = Some(format!("Unable to watch for the headset, Error: {e}")))
+        .ok();
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, &mut app, &answers, hotplug.as_ref());
+    ratatui::restore();
+    result
+}
+
+fn run(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    answers: &Receiver<Answer>,
+    hotplug: Option<&HotplugWatcher>,
+) -> io::Result<()> {
+    let mut battery_read = Instant::now();
+    while !app.quit {
+        terminal.draw(|frame| ui::draw(frame, app))?;
+        if event::poll(TICK)?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            app.key(key);
+        }
+        while let Ok(answer) = answers.try_recv() {
+            app.answer(answer);
+        }
+        while let Some(event) = hotplug.and_then(HotplugWatcher::try_recv) {
+            app.hotplug(event);
+        }
+        app.tick();
+        if battery_read.elapsed() >= BATTERY_INTERVAL {
+            app.refresh_battery();
+            battery_read = Instant::now();
+        }
+    }
+    Ok(())
+}
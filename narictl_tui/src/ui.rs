@@ -0,0 +1,247 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::app::{App, BREATHING_OPTIONS, Control, HAPTICS_MIN, Page};
+use crate::color::{ColorPicker, ColorSpace};
+use narictl_lib::*;
+use ratatui::Frame;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Gauge, Paragraph, Tabs, Wrap};
+
+const LABEL_WIDTH: usize = 18;
+const SLIDER_WIDTH: usize = 32;
+const HELP: &str =
+    "Tab page  ↑↓ select  ←→ change (Shift: ×10)  Space toggle  m RGB/HSV  r reconnect  q quit";
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let [header, battery, body, message, help] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Length(1),
+        Constraint::Min(0),
+        Constraint::Length(1),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    draw_header(frame, app, header);
+    draw_battery(frame, app, battery);
+    let block = Block::bordered().title(format!(" {} ", app.page.title()));
+    let inner = block.inner(body);
+    frame.render_widget(block, body);
+    match app.page {
+        Page::General | Page::Lighting => draw_controls(frame, app, inner),
+        Page::About => draw_about(frame, app, inner),
+    }
+    if let Some(text) = &app.message {
+        frame.render_widget(Paragraph::new(text.as_str()).yellow(), message);
+    }
+    frame.render_widget(Paragraph::new(HELP).dark_gray(), help);
+}
+
+fn draw_header(frame: &mut Frame, app: &App, area: Rect) {
+    let [tabs, device] =
+        Layout::horizontal([Constraint::Min(0), Constraint::Length(32)]).areas(area);
+    let selected = Page::ALL.iter().position(|page| *page == app.page);
+    let titles = Page::ALL
+        .iter()
+        .enumerate()
+        .map(|(i, page)| format!("{} {}", i + 1, page.title()));
+    frame.render_widget(
+        Tabs::new(titles)
+            .select(selected)
+            .highlight_style(Style::new().bold().reversed())
+            .block(Block::bordered().title(" NariCTL ")),
+        tabs,
+    );
+    let status = match app.connected {
+        true => Line::from(app.model.name).green(),
+        false => Line::from("Waiting for headset...").red(),
+    };
+    frame.render_widget(
+        Paragraph::new(status.centered()).block(Block::bordered().title(" Headset ")),
+        device,
+    );
+}
+
+fn draw_battery(frame: &mut Frame, app: &App, area: Rect) {
+    match app.battery {
+        Some(battery) => {
+            let color = match battery.percentage {
+                0..=15 => Color::Red,
+                16..=40 => Color::Yellow,
+                _ => Color::Green,
+            };
+            let label = format!("Battery {battery}, {} mv", battery.voltage);
+            frame.render_widget(
+                Gauge::default()
+                    .gauge_style(Style::new().fg(color).bg(Color::DarkGray))
+                    .ratio(battery.percentage.min(100) as f64 / 100.)
+                    .label(label),
+                area,
+            );
+        }
+        None => frame.render_widget(Paragraph::new("Battery: unknown").dark_gray(), area),
+    }
+}
+
+fn draw_controls(frame: &mut Frame, app: &App, area: Rect) {
+    let controls = app.controls();
+    let mut lines = Vec::new();
+    if app.page == Page::Lighting && !app.model.capabilities.lighting() {
+        lines.push(Line::from(format!(
+            "The {} has no lighting",
+            app.model.name
+        )));
+    }
+    for (i, control) in controls.iter().enumerate() {
+        match control {
+            Control::Color(0) => {
+                lines.push(Line::default());
+                lines.push(color_header("Color", &app.color, app.color_space));
+            }
+            Control::SecondColor(0) => {
+                lines.push(Line::default());
+                lines.push(color_header(
+                    "Second Color",
+                    &app.second_color,
+                    app.color_space,
+                ));
+            }
+            Control::Profiles => lines.push(Line::default()),
+            _ => {}
+        }
+        let mut line = control_line(app, *control);
+        if i == app.selected {
+            line = line.patch_style(Style::new().add_modifier(Modifier::REVERSED));
+            line.spans.insert(0, Span::raw("> "));
+        } else {
+            line.spans.insert(0, Span::raw("  "));
+        }
+        lines.push(line);
+    }
+    frame.render_widget(Paragraph::new(lines), area);
+}
+
+fn control_line(app: &App, control: Control) -> Line<'static> {
+    match control {
+        Control::MicMonitor => switch_slider(
+            "Mic Monitoring",
+            app.mic_monitor_enabled,
+            mic_monitor_from_raw(app.mic_monitor) as u16,
+            0,
+            MIC_MONITOR_MAX_LEVEL as u16,
+        ),
+        Control::Haptics => switch_slider(
+            "Haptic Intensity",
+            app.haptics_enabled,
+            app.haptics as u16,
+            HAPTICS_MIN as u16,
+            100,
+        ),
+        Control::Profiles => match &app.profile_name {
+            Some(name) => row("Save As", format!("{name}_  (Enter save, Esc cancel)")),
+            None => match app.profile_names().get(app.profile) {
+                Some(name) => row(
+                    "Profiles",
+                    format!("< {name} >  (Enter load, s save current, d delete)"),
+                ),
+                None => row("Profiles", String::from("none  (s save current)")),
+            },
+        },
+        Control::Effect => row(
+            "Effects",
+            format!("< {} >", capitalize(&app.mode.to_string())),
+        ),
+        Control::Breathing => row(
+            "Breathing",
+            format!("< {} >", BREATHING_OPTIONS[app.breathing]),
+        ),
+        Control::Brightness => slider("Brightness", app.brightness as u16, 0, 100),
+        Control::Color(channel) => channel_line(&app.color, app.color_space, channel),
+        Control::SecondColor(channel) => channel_line(&app.second_color, app.color_space, channel),
+    }
+}
+
+fn row(label: &str, value: String) -> Line<'static> {
+    Line::from(vec![
+        Span::raw(format!("{label:<LABEL_WIDTH$}")).bold(),
+        Span::raw(value),
+    ])
+}
+
+fn slider(label: &str, value: u16, min: u16, max: u16) -> Line<'static> {
+    let filled = (value.saturating_sub(min) as usize * SLIDER_WIDTH) / (max - min).max(1) as usize;
+    Line::from(vec![
+        Span::raw(format!("{label:<LABEL_WIDTH$}")).bold(),
+        Span::raw("█".repeat(filled)).cyan(),
+        Span::raw("░".repeat(SLIDER_WIDTH - filled.min(SLIDER_WIDTH))).dark_gray(),
+        Span::raw(format!(" {value:>3} / {max}")),
+    ])
+}
+
+/// A slider that is grayed out while its switch is off
+fn switch_slider(label: &str, enabled: bool, value: u16, min: u16, max: u16) -> Line<'static> {
+    let mut line = slider(label, value, min, max);
+    if !enabled {
+        line = line.patch_style(Style::new().dark_gray());
+    }
+    line.spans
+        .insert(1, Span::raw(if enabled { "[on]  " } else { "[off] " }));
+    line
+}
+
+fn color_header(label: &str, color: &ColorPicker, space: ColorSpace) -> Line<'static> {
+    let (r, g, b) = color.rgb();
+    let space = match space {
+        ColorSpace::Rgb => "RGB",
+        ColorSpace::Hsv => "HSV",
+    };
+    Line::from(vec![
+        Span::raw(format!("  {label} ({space})  ")).bold(),
+        Span::raw("      ").bg(Color::Rgb(r, g, b)),
+        Span::raw(format!("  #{r:02x}{g:02x}{b:02x}")),
+    ])
+}
+
+fn channel_line(color: &ColorPicker, space: ColorSpace, channel: usize) -> Line<'static> {
+    let (name, value, max) = color.channels(space)[channel];
+    slider(&format!("  {name}"), value, 0, max)
+}
+
+fn draw_about(frame: &mut Frame, app: &App, area: Rect) {
+    let mut lines = vec![
+        Line::from("NariCTL").bold(),
+        Line::default(),
+        Line::from(format!("Version: {}", env!("CARGO_PKG_VERSION"))),
+        Line::from(format!("Model: {}", app.model.name)),
+    ];
+    if let Some(battery) = app.battery {
+        lines.push(Line::from(format!(
+            "Battery: {battery}, {} mv",
+            battery.voltage
+        )));
+    }
+    if let Some(identity) = &app.identity {
+        lines.extend(
+            identity
+                .to_string()
+                .lines()
+                .map(|line| Line::from(line.to_string())),
+        );
+    }
+    lines.push(Line::default());
+    lines.push(Line::from("Built and optimized for use with the Razer Nari family").italic());
+    frame.render_widget(Paragraph::new(lines).wrap(Wrap { trim: false }), area);
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
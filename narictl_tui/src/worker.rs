@@ -0,0 +1,88 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+//! Owns the headset and talks to it on a thread of its own, so a slow or silent headset never
+//! freezes the interface. Requests are handled in the order they were sent.
+
+use narictl_lib::*;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+/// What the interface asks the headset to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    /// Reads the model, state and identity, answered with `Connected` or `Waiting`
+    Connect,
+    ReadBattery,
+    SetMicMonitor(u8),
+    SetHaptics(u8, bool),
+    SetBrightness(u8),
+    SetEffect(LightingEffect),
+    ApplyState(DeviceState),
+}
+
+/// What the headset answered, settings only get an answer when they failed
+#[derive(Debug)]
+pub enum Answer {
+    /// `state` is an error when the headset answered but its settings could not be read
+    Connected {
+        model: &'static Model,
+        state: Result<DeviceState>,
+        identity: Identity,
+    },
+    /// Neither the headset nor its dongle answered
+    Waiting(NariError),
+    Battery(Result<Battery>),
+    Failed {
+        action: &'static str,
+        error: NariError,
+    },
+}
+
+/// Starts the thread talking to the headset, it stops once the request sender is dropped
+pub fn spawn() -> (Sender<Request>, Receiver<Answer>) {
+    let (requests, inbox) = mpsc::channel();
+    let (outbox, answers) = mpsc::channel();
+    thread::spawn(move || {
+        let device = Device::connect_lazy();
+        for request in inbox {
+            if let Some(answer) = handle(&device, request)
+                && outbox.send(answer).is_err()
+            {
+                return;
+            }
+        }
+    });
+    (requests, answers)
+}
+
+fn handle<T: Transport>(device: &Device<T>, request: Request) -> Option<Answer> {
+    let failed =
+        |action, result: Result<()>| result.err().map(|error| Answer::Failed { action, error });
+    match request {
+        Request::Connect => Some(match device.state() {
+            Err(e @ (NariError::DeviceNotFound | NariError::Timeout)) => Answer::Waiting(e),
+            state => Answer::Connected {
+                model: device.model().unwrap_or(&NARI_ULTIMATE),
+                state,
+                identity: device.identity(),
+            },
+        }),
+        Request::ReadBattery => Some(Answer::Battery(device.get_battery())),
+        Request::SetMicMonitor(value) => failed(
+            "change the mic monitor state",
+            device.set_mic_monitor(value),
+        ),
+        Request::SetHaptics(intensity, enabled) => failed(
+            "change the haptics",
+            device.set_haptic_intensity(intensity, enabled),
+        ),
+        Request::SetBrightness(brightness) => {
+            failed("change the brightness", device.set_brightness(brightness))
+        }
+        Request::SetEffect(effect) => failed("change the lighting", device.set_effect(effect)),
+        Request::ApplyState(state) => failed("apply the profile", device.apply_state(&state)),
+    }
+}
@@ -5,31 +5,131 @@ SPDX-License-Identifier: GPL-3.0-or-later
 
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows
 
+mod settings;
+#[cfg(unix)]
+mod tray;
+mod worker;
+
 use std::env;
-use gtk::glib::clone;
-use gtk::prelude::{BoxExt, ColorChooserExt, GtkWindowExt, RangeExt, WidgetExt};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Instant;
+use gtk::glib::{self, clone};
+use gtk::prelude::{
+    BoxExt, ButtonExt, Cast, ColorChooserExt, EditableExt, GtkWindowExt, IsA, ListModelExt,
+    ObjectExt, RangeExt, WidgetExt,
+};
 use narictl_lib::*;
 use relm4::{
-    gtk, gtk::gdk::RGBA, gtk::Adjustment, ComponentParts, ComponentSender, RelmApp, SimpleComponent,
+    gtk, gtk::gdk::RGBA, gtk::Adjustment, Component, ComponentController, ComponentParts,
+    ComponentSender, Controller, RelmApp, SimpleComponent, WorkerController,
 };
+use relm4_components::open_dialog::{
+    OpenDialog, OpenDialogMsg, OpenDialogResponse, OpenDialogSettings,
+};
+use settings::Settings;
+#[cfg(unix)]
+use tray::{Tray, TrayAction, TrayState};
+use worker::{DeviceInput, DeviceOutput, DeviceWorker};
 
 const CSS: &str = include_str!("application.css");
 const BOX_SPACING: u32 = 22;
+const DEFAULT_COLOR: (u8, u8, u8) = (0, 255, 0);
+const DEFAULT_SECOND_COLOR: (u8, u8, u8) = (0, 0, 255);
+const BREATHING_OPTIONS: [&str; 3] = ["Single Color", "Two Colors", "Random"];
+// Shown until the headset state is read back
+const DEFAULT_STATE: DeviceState = DeviceState {
+    effect: LightingEffect::Off,
+    brightness: 60,
+    haptics_enabled: false,
+    haptic_intensity: 60,
+    mic_monitor: MIC_MONITOR_OFF,
+};
 
 struct Application {
-    device: Device,
+    // Does every read and write of the headset, off the GTK thread
+    worker: WorkerController<DeviceWorker>,
+    connected: bool,
+    // Decides which controls are shown, the Nari Ultimate until a headset answers
+    model: &'static Model,
+    devices: Vec<DeviceInfo>,
+    selector: DeviceSelector,
+    brightness: u8,
+    haptics_enabled: bool,
     haptics: u8,
+    mic_monitor_enabled: bool,
     mic_monitor: u8,
+    mode: LightingMode,
+    breathing: u32,
     color: (u8, u8, u8),
+    second_color: (u8, u8, u8),
+    battery: Option<Battery>,
+    // Firmware versions and serial numbers for the About page, read once the headset answers
+    identity: Option<Identity>,
+    animation: Option<PlayingAnimation>,
+    open_dialog: Controller<OpenDialog>,
+    profiles: Profiles,
+    settings: Settings,
+    // Turns the battery readings into notifications
+    battery_monitor: BatteryMonitor,
+    #[cfg(unix)]
+    window: gtk::Window,
+    // `None` when no StatusNotifierItem host is running, closing the window then quits
+    #[cfg(unix)]
+    tray: Option<ksni::blocking::Handle<Tray>>,
+    // Lighting mode the tray restores when it turns the lighting back on
+    #[cfg(unix)]
+    lighting_mode: LightingMode,
+    // Bumped every time the settings change outside the widgets (profile loaded, headset
+    // reconnected, tray used) so the widgets know to catch up
+    syncs: u32,
+}
+
+struct PlayingAnimation {
+    animation: Animation,
+    start: Instant,
+    timer: glib::SourceId,
 }
 
 struct Widgets {
+    main_stack: gtk::Stack,
+    device_label: gtk::Label,
+    f1_box: gtk::Box,
+    f2_box: gtk::Box,
+    lighting_box: gtk::FlowBox,
+    about_label: gtk::Label,
+    battery_label: gtk::Label,
+    d_dropdown: gtk::DropDown,
+    d_list: gtk::StringList,
     ht_switch: gtk::Switch,
     hi_scale: gtk::Scale,
     mm_switch: gtk::Switch,
     mm_scale: gtk::Scale,
-    c_effects_dropdown: gtk::DropDown,
     c_box: gtk::Box,
+    c2_box: gtk::Box,
+    cb_box: gtk::Box,
+    a_button: gtk::Button,
+    b_scale: gtk::Scale,
+    c_effects_dropdown: gtk::DropDown,
+    cb_dropdown: gtk::DropDown,
+    c_colorpicker: gtk::ColorButton,
+    c2_colorpicker: gtk::ColorButton,
+    p_list: gtk::StringList,
+    // Blocked while the widgets are synced, so setting them does not write the values back
+    d_dropdown_handler: glib::SignalHandlerId,
+    b_scale_handler: glib::SignalHandlerId,
+    mm_scale_handler: glib::SignalHandlerId,
+    hi_scale_handler: glib::SignalHandlerId,
+    ht_switch_handler: glib::SignalHandlerId,
+    mm_switch_handler: glib::SignalHandlerId,
+    c_colorpicker_handler: glib::SignalHandlerId,
+    c2_colorpicker_handler: glib::SignalHandlerId,
+    c_effects_dropdown_handler: glib::SignalHandlerId,
+    cb_dropdown_handler: glib::SignalHandlerId,
+    syncs: u32,
+    // Last state sent to the tray, it is only updated when this changes
+    #[cfg(unix)]
+    tray_state: Option<TrayState>,
 }
 
 #[derive(Debug)]
@@ -40,7 +140,21 @@ enum AppInput {
     ToggleHaptics(bool),
     SetHapticIntensity(u8),
     SetColor(u8, u8, u8),
+    SetSecondColor(u8, u8, u8),
     SetState(u8),
+    SetBreathing(u32),
+    ToggleAnimation,
+    PlayAnimation(PathBuf),
+    AnimationFrame,
+    LoadProfile(String),
+    SaveProfile(String),
+    DeleteProfile(String),
+    SelectDevice(u32),
+    RefreshBattery,
+    Device(DeviceOutput),
+    #[cfg(unix)]
+    Tray(TrayAction),
+    Ignore,
 }
 
 impl SimpleComponent for Application {
@@ -63,19 +177,74 @@ impl SimpleComponent for Application {
         window: Self::Root,
         sender: ComponentSender<Self>,
     ) -> relm4::ComponentParts<Self> {
-        let device = Device::init();
-        if let Err(e) = device {
-            eprintln!("Failed to initialize the headset. Error: {e}");
-            std::process::exit(1);
-        }
+        let worker = DeviceWorker::builder()
+            .detach_worker(())
+            .forward(sender.input_sender(), AppInput::Device);
+        worker.emit(DeviceInput::ListDevices);
+        worker.emit(DeviceInput::Connect);
+
+        let state = DEFAULT_STATE;
+        let mic_monitor_enabled = state.mic_monitor != MIC_MONITOR_OFF;
+
+        let profiles = Profiles::load().unwrap_or_else(|e| {
+            eprintln!("Unable to load the profiles. Error: {e}");
+            Profiles::default()
+        });
+        let settings = Settings::load().unwrap_or_else(|e| {
+            eprintln!("Unable to load the settings. Error: {e}");
+            Settings::default()
+        });
 
         let main = Application {
-            device: device.unwrap(),
-            haptics: 60,
-            mic_monitor: 20,
-            color: (0, 255, 0),
+            worker,
+            connected: false,
+            model: &NARI_ULTIMATE,
+            devices: Vec::new(),
+            selector: DeviceSelector::First,
+            brightness: state.brightness,
+            haptics_enabled: state.haptics_enabled,
+            haptics: state.haptic_intensity,
+            mic_monitor_enabled,
+            mic_monitor: if mic_monitor_enabled {
+                state.mic_monitor
+            } else {
+                mic_monitor_to_raw(DEFAULT_MIC_MONITOR_LEVEL)
+            },
+            mode: state.effect.mode(),
+            breathing: match state.effect {
+                LightingEffect::Breathing(Breathing::Dual(..)) => 1,
+                LightingEffect::Breathing(Breathing::Random) => 2,
+                _ => 0,
+            },
+            color: state.effect.color().unwrap_or(DEFAULT_COLOR),
+            second_color: match state.effect {
+                LightingEffect::Breathing(Breathing::Dual(_, color)) => color,
+                _ => DEFAULT_SECOND_COLOR,
+            },
+            battery: None,
+            identity: None,
+            animation: None,
+            open_dialog: OpenDialog::builder()
+                .transient_for_native(&window)
+                .launch(OpenDialogSettings::default())
+                .forward(sender.input_sender(), |response| match response {
+                    OpenDialogResponse::Accept(path) => AppInput::PlayAnimation(path),
+                    OpenDialogResponse::Cancel => AppInput::Ignore,
+                }),
+            profiles,
+            battery_monitor: BatteryMonitor::new(&settings.battery_thresholds),
+            settings,
+            #[cfg(unix)]
+            window: window.clone(),
+            #[cfg(unix)]
+            tray: None,
+            #[cfg(unix)]
+            lighting_mode: match state.effect.mode() {
+                LightingMode::Off => LightingMode::Static,
+                mode => mode,
+            },
+            syncs: 0,
         };
-        main.device.set_off();
 
         let main_box = gtk::Box::builder()
             .orientation(gtk::Orientation::Vertical)
@@ -86,39 +255,23 @@ impl SimpleComponent for Application {
             .build();
 
         let main_stack = gtk::Stack::builder()
+            .sensitive(main.connected)
             .transition_duration(200)
             .hhomogeneous(false)
             .transition_type(gtk::StackTransitionType::SlideLeftRight)
             .css_name("main_stack")
             .build();
 
-        let mv = main
-            .device
-            .get_voltage()
-            .expect("Failed to get data from the headset");
-        let info_text = if mv != 0 {
-            format!(
-                "<b>NariCTL</b>\n\nVersion: {}\nVoltage: <i>{mv} mv</i>\n\n\n<small>Built and optimized for use with the Razer Nari Ultimate only</small>",
-                env!("CARGO_PKG_VERSION")
-            )
-        } else {
-            eprintln!("Unable to get voltage data");
-            format!(
-                "<b>NariCTL</b>\n\nVersion: {}\n\n\n<small>Built and optimized for use with the Razer Nari Ultimate only</small>",
-                env!("CARGO_PKG_VERSION")
-            )
-        };
-
         let about_label = gtk::Label::builder()
             .use_markup(true)
             .halign(gtk::Align::Start)
             .hexpand(true)
-            .label(info_text)
+            .label(about_text(None, None))
             .build();
 
         let device_label = gtk::Label::builder()
             .css_name("device_label")
-            .label("Razer Nari Ultimate")
+            .label(device_text(main.connected, main.model))
             .single_line_mode(true)
             .hexpand(true)
             .build();
@@ -150,8 +303,26 @@ impl SimpleComponent for Application {
             .hexpand(false)
             .build();
 
+        let battery_label = gtk::Label::builder()
+            .css_name("battery_label")
+            .halign(gtk::Align::End)
+            .single_line_mode(true)
+            .build();
+
+        // Only shown with more than one dongle plugged in
+        let names = device_names(&main.devices);
+        let d_list = gtk::StringList::new(&names.iter().map(String::as_str).collect::<Vec<_>>());
+        let d_dropdown = gtk::DropDown::builder()
+            .model(&d_list)
+            .halign(gtk::Align::End)
+            .valign(gtk::Align::Center)
+            .visible(main.devices.len() > 1)
+            .build();
+
         header.append(&header_label);
         switcher_box.append(&switcher);
+        switcher_box.append(&d_dropdown);
+        switcher_box.append(&battery_label);
 
         // ------------------------------------
         let f_box = gtk::FlowBox::builder()
@@ -193,6 +364,8 @@ impl SimpleComponent for Application {
         let mm_switch = gtk::Switch::builder()
             .valign(gtk::Align::Start)
             .halign(gtk::Align::Start)
+            .active(mic_monitor_enabled)
+            .state(mic_monitor_enabled)
             .build();
 
         mm_box.append(&mm_label);
@@ -202,13 +375,13 @@ impl SimpleComponent for Application {
         let mm_scale = gtk::Scale::builder()
             .draw_value(true)
             .digits(0)
-            .opacity(0.4)
-            .sensitive(false)
+            .opacity(if mic_monitor_enabled { 1. } else { 0.4 })
+            .sensitive(mic_monitor_enabled)
             .show_fill_level(true)
             .adjustment(&Adjustment::new(
-                main.mic_monitor as f64,
+                mic_monitor_from_raw(main.mic_monitor) as f64,
                 0.,
-                86.,
+                MIC_MONITOR_MAX_LEVEL as f64,
                 1.,
                 0.,
                 0.,
@@ -250,6 +423,8 @@ impl SimpleComponent for Application {
         let ht_switch = gtk::Switch::builder()
             .valign(gtk::Align::Start)
             .halign(gtk::Align::Start)
+            .active(state.haptics_enabled)
+            .state(state.haptics_enabled)
             .build();
 
         ht_box.append(&ht_label);
@@ -261,8 +436,8 @@ impl SimpleComponent for Application {
             .draw_value(true)
             .adjustment(&Adjustment::new(main.haptics as f64, 20., 100., 1., 0., 0.))
             .show_fill_level(true)
-            .opacity(0.4)
-            .sensitive(false)
+            .opacity(if state.haptics_enabled { 1. } else { 0.4 })
+            .sensitive(state.haptics_enabled)
             .build();
         let hi_scale_label = hi_scale.first_child().expect("Couldn't find the scale");
         hi_scale_label.set_margin_bottom(10);
@@ -286,6 +461,50 @@ impl SimpleComponent for Application {
         f2_box.append(&hi_scale);
         f2_box.append(&hi_box);
 
+        // Profiles Init
+        let f3_box = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .spacing(6)
+            .width_request(460)
+            .css_name("properties")
+            .build();
+        let p_label = gtk::Label::builder()
+            .css_name("title")
+            .halign(gtk::Align::Start)
+            .label("Profiles")
+            .build();
+        let p_list = gtk::StringList::new(&profile_names(&main.profiles));
+        let p_dropdown = gtk::DropDown::builder()
+            .model(&p_list)
+            .width_request(170)
+            .build();
+        let p_load_button = gtk::Button::builder().label("Load").build();
+        let p_delete_button = gtk::Button::builder().label("Delete").build();
+        let p_box = gtk::Box::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .spacing(6)
+            .build();
+        p_box.append(&p_dropdown);
+        p_box.append(&p_load_button);
+        p_box.append(&p_delete_button);
+
+        let p_entry = gtk::Entry::builder()
+            .placeholder_text("Profile name")
+            .width_request(170)
+            .build();
+        let p_save_button = gtk::Button::builder().label("Save Current").build();
+        let ps_box = gtk::Box::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .spacing(6)
+            .build();
+        ps_box.append(&p_entry);
+        ps_box.append(&p_save_button);
+
+        f3_box.append(&p_label);
+        f3_box.append(&p_box);
+        f3_box.append(&ps_box);
+        f_box.append(&f3_box);
+
         // ------------------------------------
         let lighting_box = gtk::FlowBox::builder()
             .orientation(gtk::Orientation::Horizontal)
@@ -323,7 +542,7 @@ impl SimpleComponent for Application {
             .digits(0)
             .draw_value(true)
             .show_fill_level(true)
-            .adjustment(&Adjustment::new(60., 0., 100., 2., 0., 0.))
+            .adjustment(&Adjustment::new(state.brightness as f64, 0., 100., 2., 0., 0.))
             .build();
         let b_scale_label = b_scale.first_child().expect("Couldn't find the scale");
         b_scale_label.set_margin_bottom(10);
@@ -348,9 +567,24 @@ impl SimpleComponent for Application {
         b_box.append(&b_label_low);
         b_box.append(&b_label_high);
 
+        // Animation Init
+        let a_label = gtk::Label::builder()
+            .css_name("title")
+            .halign(gtk::Align::Start)
+            .margin_top(BOX_SPACING as i32)
+            .label("Animation")
+            .build();
+        let a_button = gtk::Button::builder()
+            .label("Play Animation")
+            .halign(gtk::Align::Start)
+            .width_request(170)
+            .build();
+
         l1_box.append(&b_label);
         l1_box.append(&b_scale);
         l1_box.append(&b_box);
+        l1_box.append(&a_label);
+        l1_box.append(&a_button);
 
         let c_label = gtk::Label::builder()
             .label("Effects")
@@ -363,36 +597,69 @@ impl SimpleComponent for Application {
             .spacing(4)
             .build();
 
-        let c_effects_dropdown = gtk::DropDown::from_strings(&["Off", "Static"]);
+        let modes = LightingMode::ALL.map(|mode| capitalize(&mode.to_string()));
+        let c_effects_dropdown =
+            gtk::DropDown::from_strings(&modes.iter().map(String::as_str).collect::<Vec<_>>());
         c_effects_dropdown.set_halign(gtk::Align::Start);
         c_effects_dropdown.set_width_request(170);
+        c_effects_dropdown.set_selected(
+            LightingMode::ALL
+                .iter()
+                .position(|mode| *mode == main.mode)
+                .unwrap_or_default() as u32,
+        );
+
+        // Breathing Init
+        let cb_box = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .spacing(4)
+            .build();
+        let cb_label = gtk::Label::builder()
+            .label("Breathing")
+            .halign(gtk::Align::Start)
+            .css_name("c0")
+            .build();
+        let cb_dropdown = gtk::DropDown::from_strings(&BREATHING_OPTIONS);
+        cb_dropdown.set_halign(gtk::Align::Start);
+        cb_dropdown.set_width_request(170);
+        cb_dropdown.set_selected(main.breathing);
+        cb_box.append(&cb_label);
+        cb_box.append(&cb_dropdown);
 
         let c_colorpicker_label = gtk::Label::builder()
             .label("Color")
             .halign(gtk::Align::Start)
             .css_name("c0")
             .build();
-        let c_colorpicker = gtk::ColorButton::builder()
-            .valign(gtk::Align::Center)
-            .halign(gtk::Align::Start)
-            .title("Set Color")
-            .build();
-        c_colorpicker.set_size_request(1, 1);
-        let swatch = c_colorpicker
-            .first_child()
-            .expect("Couldn't find the colorbutton")
-            .first_child()
-            .expect("Couldn't find the color swatch");
-        swatch.set_width_request(20);
-        c_colorpicker.set_rgba(&RGBA::new(0., 1., 0., 1.));
+        let c_colorpicker = color_button("Set Color", main.color);
         c_box.append(&c_colorpicker_label);
         c_box.append(&c_colorpicker);
-        c_box.set_sensitive(false);
-        c_box.set_opacity(0.);
+
+        let c2_box = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .spacing(4)
+            .build();
+        let c2_colorpicker_label = gtk::Label::builder()
+            .label("Second Color")
+            .halign(gtk::Align::Start)
+            .css_name("c0")
+            .build();
+        let c2_colorpicker = color_button("Set Second Color", main.second_color);
+        c2_box.append(&c2_colorpicker_label);
+        c2_box.append(&c2_colorpicker);
+
+        let breathing = main.mode == LightingMode::Breathing;
+        let shows_color = main.mode == LightingMode::Static || (breathing && main.breathing != 2);
+        c_box.set_sensitive(shows_color);
+        c_box.set_opacity(if shows_color { 1. } else { 0. });
+        cb_box.set_visible(breathing);
+        c2_box.set_visible(breathing && main.breathing == 1);
 
         l2_box.append(&c_label);
         l2_box.append(&c_effects_dropdown);
+        l2_box.append(&cb_box);
         l2_box.append(&c_box);
+        l2_box.append(&c2_box);
         lighting_box.append(&l1_box);
         lighting_box.append(&l2_box);
 
@@ -400,6 +667,12 @@ impl SimpleComponent for Application {
         main_stack.add_titled(&lighting_box, Some("lighting"), "Lighting");
         main_stack.add_titled(&about_label, Some("about"), "About");
 
+        // Only show what the model can do
+        let capabilities = main.model.capabilities;
+        f1_box.set_visible(capabilities.sidetone);
+        f2_box.set_visible(capabilities.haptics);
+        lighting_box.set_visible(capabilities.lighting());
+
         main_box.append(&switcher_box);
         main_box.append(&main_stack);
         main_box.append(&device_label);
@@ -407,122 +680,533 @@ impl SimpleComponent for Application {
         window.set_child(Some(&main_box));
 
         // Listeners
-        b_scale.connect_value_changed(clone!(@strong sender => move |v| {
-            sender.input(AppInput::SetBrightness(v.value() as u8));
-        }));
+        a_button.connect_clicked(clone!(
+            #[strong]
+            sender,
+            move |_| {
+                sender.input(AppInput::ToggleAnimation);
+            }
+        ));
 
-        mm_scale.connect_value_changed(clone!(@strong sender => move |v| {
-            sender.input(AppInput::SetMicMonitor(v.value() as u8));
-        }));
+        p_load_button.connect_clicked(clone!(
+            #[strong]
+            sender,
+            #[weak]
+            p_dropdown,
+            move |_| {
+                if let Some(name) = selected_string(&p_dropdown) {
+                    sender.input(AppInput::LoadProfile(name));
+                }
+            }
+        ));
 
-        hi_scale.connect_value_changed(clone!(@strong sender => move |v| {
-            sender.input(AppInput::SetHapticIntensity(v.value() as u8));
-        }));
+        p_delete_button.connect_clicked(clone!(
+            #[strong]
+            sender,
+            #[weak]
+            p_dropdown,
+            move |_| {
+                if let Some(name) = selected_string(&p_dropdown) {
+                    sender.input(AppInput::DeleteProfile(name));
+                }
+            }
+        ));
 
-        ht_switch.connect_state_notify(clone!(@strong sender => move |t| {
-            sender.input(AppInput::ToggleHaptics(t.state()));
-        }));
+        let d_dropdown_handler = d_dropdown.connect_selected_item_notify(clone!(
+            #[strong]
+            sender,
+            move |d| {
+                sender.input(AppInput::SelectDevice(d.selected()));
+            }
+        ));
 
-        mm_switch.connect_state_notify(clone!(@strong sender => move |t| {
-            sender.input(AppInput::ToggleMicMonitor(t.state()));
-        }));
+        p_save_button.connect_clicked(clone!(
+            #[strong]
+            sender,
+            #[weak]
+            p_entry,
+            move |_| {
+                sender.input(AppInput::SaveProfile(p_entry.text().to_string()));
+            }
+        ));
 
-        c_colorpicker.connect_rgba_notify(clone!(@strong sender => move |c| {
-            let c = c.rgba();
-            let (r, g, b) = ((c.red() * 255.99) as u8, (c.green() * 255.99) as u8, (c.blue() * 255.99) as u8);
-            sender.input(AppInput::SetColor(r, g, b));
-        }));
+        let b_scale_handler = b_scale.connect_value_changed(clone!(
+            #[strong]
+            sender,
+            move |v| {
+                sender.input(AppInput::SetBrightness(v.value() as u8));
+            }
+        ));
 
-        c_effects_dropdown.connect_selected_item_notify(clone!(@strong sender => move |d| {
-            sender.input(AppInput::SetState(d.selected() as u8));
-        }));
+        let mm_scale_handler = mm_scale.connect_value_changed(clone!(
+            #[strong]
+            sender,
+            move |v| {
+                sender.input(AppInput::SetMicMonitor(v.value() as u8));
+            }
+        ));
+
+        let hi_scale_handler = hi_scale.connect_value_changed(clone!(
+            #[strong]
+            sender,
+            move |v| {
+                sender.input(AppInput::SetHapticIntensity(v.value() as u8));
+            }
+        ));
+
+        let ht_switch_handler = ht_switch.connect_state_notify(clone!(
+            #[strong]
+            sender,
+            move |t| {
+                sender.input(AppInput::ToggleHaptics(t.state()));
+            }
+        ));
+
+        let mm_switch_handler = mm_switch.connect_state_notify(clone!(
+            #[strong]
+            sender,
+            move |t| {
+                sender.input(AppInput::ToggleMicMonitor(t.state()));
+            }
+        ));
+
+        let c_colorpicker_handler = c_colorpicker.connect_rgba_notify(clone!(
+            #[strong]
+            sender,
+            move |c| {
+                let (r, g, b) = rgb(&c.rgba());
+                sender.input(AppInput::SetColor(r, g, b));
+            }
+        ));
+
+        let c2_colorpicker_handler = c2_colorpicker.connect_rgba_notify(clone!(
+            #[strong]
+            sender,
+            move |c| {
+                let (r, g, b) = rgb(&c.rgba());
+                sender.input(AppInput::SetSecondColor(r, g, b));
+            }
+        ));
+
+        let c_effects_dropdown_handler = c_effects_dropdown.connect_selected_item_notify(clone!(
+            #[strong]
+            sender,
+            move |d| {
+                sender.input(AppInput::SetState(d.selected() as u8));
+            }
+        ));
+
+        let cb_dropdown_handler = cb_dropdown.connect_selected_item_notify(clone!(
+            #[strong]
+            sender,
+            move |d| {
+                sender.input(AppInput::SetBreathing(d.selected()));
+            }
+        ));
+
+        match HotplugWatcher::new() {
+            Ok(watcher) => {
+                let worker = main.worker.sender().clone();
+                thread::spawn(move || {
+                    for event in watcher {
+                        worker.emit(DeviceInput::Hotplug(event));
+                    }
+                });
+            }
+            Err(e) => eprintln!("Unable to watch for the headset being plugged in, Error: {e}"),
+        }
+
+        #[cfg(unix)]
+        let main = Application {
+            tray: spawn_tray(main.tray_state(), &sender),
+            ..main
+        };
+        #[cfg(unix)]
+        if main.tray.is_some() {
+            // Keeps running in the tray once the window is closed
+            window.connect_close_request(|window| {
+                window.set_visible(false);
+                glib::Propagation::Stop
+            });
+            if main.settings.start_minimized {
+                // Runs once relm4 has shown the window
+                glib::idle_add_local_once(clone!(
+                    #[weak]
+                    window,
+                    move || {
+                        window.set_visible(false);
+                    }
+                ));
+            }
+        }
+
+        sender.input(AppInput::RefreshBattery);
+        let interval = main.settings.battery_interval.max(1);
+        glib::timeout_add_seconds_local(interval, clone!(
+            #[strong]
+            sender,
+            move || {
+                sender.input(AppInput::RefreshBattery);
+                glib::ControlFlow::Continue
+            }
+        ));
 
         ComponentParts {
             model: main,
             widgets: Widgets {
+                main_stack,
+                device_label,
+                f1_box,
+                f2_box,
+                lighting_box,
+                about_label,
+                battery_label,
+                d_dropdown,
+                d_list,
                 ht_switch,
                 hi_scale,
                 mm_switch,
                 mm_scale,
-                c_effects_dropdown,
                 c_box,
+                c2_box,
+                cb_box,
+                a_button,
+                b_scale,
+                c_effects_dropdown,
+                cb_dropdown,
+                c_colorpicker,
+                c2_colorpicker,
+                p_list,
+                d_dropdown_handler,
+                b_scale_handler,
+                mm_scale_handler,
+                hi_scale_handler,
+                ht_switch_handler,
+                mm_switch_handler,
+                c_colorpicker_handler,
+                c2_colorpicker_handler,
+                c_effects_dropdown_handler,
+                cb_dropdown_handler,
+                syncs: 0,
+                #[cfg(unix)]
+                tray_state: None,
             },
         }
     }
 
-    fn update(&mut self, message: Self::Input, _sender: ComponentSender<Self>) {
+    fn update(&mut self, message: Self::Input, sender: ComponentSender<Self>) {
         match message {
+            // The worker reports failed writes, the controls keep what was asked for
             AppInput::ToggleMicMonitor(state) => {
-                let value = if state { self.mic_monitor } else { 0 };
-                if let Err(e) = self.device.set_mic_monitor(value) {
-                    eprintln!("An error occurred while trying to change the mic monitor state, Error: {e}");
-                }
+                let value = if state { self.mic_monitor } else { MIC_MONITOR_OFF };
+                self.worker.emit(DeviceInput::SetMicMonitor(value));
+                self.mic_monitor_enabled = state;
             }
 
-            AppInput::SetMicMonitor(mut value) => {
-                value /= 2;
-                if value % 2 != 0 {
-                    value -= 1;
-                }
-                if let Err(e) = self.device.set_mic_monitor(value + 192) {
-                    eprintln!("An error occurred while trying to change the mic monitor state, Error: {e}");
-                } else {
-                    self.mic_monitor = value + 192;
-                }
+            AppInput::SetMicMonitor(level) => {
+                let value = mic_monitor_to_raw(level);
+                self.worker.emit(DeviceInput::SetMicMonitor(value));
+                self.mic_monitor = value;
             }
 
             AppInput::SetBrightness(brightness) => {
-                if let Err(e) = self.device.set_brightness(brightness) {
-                    eprintln!(
-                        "An error occurred while trying to change the brightness, Error: {e}"
-                    );
-                }
+                self.worker.emit(DeviceInput::SetBrightness(brightness));
+                self.brightness = brightness;
             }
 
-            AppInput::SetHapticIntensity(mut intensity) => {
-                if intensity > 100 {
-                    intensity = 100;
-                }
-                if let Err(e) = self.device.set_haptic_intensity(intensity, true) {
-                    eprintln!("An error occurred while trying to set haptic intensity, Error: {e}");
-                } else {
-                    self.haptics = intensity;
-                }
+            AppInput::SetHapticIntensity(intensity) => {
+                let intensity = intensity.min(100);
+                self.worker.emit(DeviceInput::SetHaptics(intensity, true));
+                self.haptics = intensity;
             }
 
             AppInput::ToggleHaptics(state) => {
-                if let Err(e) = self.device.set_haptic_intensity(self.haptics, state) {
-                    eprintln!("An error occurred while trying to toggle haptics, Error: {e}");
-                }
+                self.worker.emit(DeviceInput::SetHaptics(self.haptics, state));
+                self.haptics_enabled = state;
             }
 
             AppInput::SetColor(r, g, b) => {
-                if let Err(e) = self.device.set_fixed(r, g, b) {
-                    eprintln!("An error occurred while trying to change the color, Error: {e}");
+                self.color = (r, g, b);
+                self.apply_effect();
+            }
+
+            AppInput::SetSecondColor(r, g, b) => {
+                self.second_color = (r, g, b);
+                self.apply_effect();
+            }
+
+            AppInput::SetState(state) => {
+                let Some(mode) = LightingMode::ALL.get(state as usize) else {
+                    eprintln!("Invalid state, ignoring");
+                    return;
+                };
+                self.mode = *mode;
+                self.apply_effect();
+            }
+
+            AppInput::SetBreathing(breathing) => {
+                self.breathing = breathing;
+                self.apply_effect();
+            }
+
+            AppInput::ToggleAnimation => {
+                if self.animation.is_some() {
+                    self.apply_effect();
                 } else {
-                    self.color = (r, g, b);
+                    self.open_dialog.emit(OpenDialogMsg::Open);
                 }
             }
 
-            AppInput::SetState(state) => {
-                let res = match state {
-                    0 => self.device.set_off(),
-                    1 => self
-                        .device
-                        .set_fixed(self.color.0, self.color.1, self.color.2),
-                    _ => {
-                        eprintln!("Invalid state, ignoring");
-                        Ok(0)
+            AppInput::PlayAnimation(path) => {
+                let animation = match Animation::load(&path) {
+                    Ok(animation) => animation,
+                    Err(e) => {
+                        eprintln!("Unable to load the animation, Error: {e}");
+                        return;
+                    }
+                };
+                self.stop_animation();
+                let timer = glib::timeout_add_local(
+                    animation.frame_interval(),
+                    clone!(
+                        #[strong]
+                        sender,
+                        move || {
+                            sender.input(AppInput::AnimationFrame);
+                            glib::ControlFlow::Continue
+                        }
+                    ),
+                );
+                self.animation = Some(PlayingAnimation {
+                    animation,
+                    start: Instant::now(),
+                    timer,
+                });
+            }
+
+            AppInput::AnimationFrame => {
+                let Some(playing) = &self.animation else {
+                    return;
+                };
+                let elapsed = playing.start.elapsed();
+                let (r, g, b) = playing.animation.color_at(elapsed);
+                self.worker.emit(DeviceInput::SetFrame(r, g, b));
+                if playing.animation.is_finished(elapsed) {
+                    self.stop_animation();
+                }
+            }
+
+            AppInput::LoadProfile(name) => {
+                let profile = match self.profiles.get(&name) {
+                    Ok(profile) => profile,
+                    Err(e) => {
+                        eprintln!("Unable to load the profile, Error: {e}");
+                        return;
                     }
                 };
-                if let Err(e) = res {
-                    eprintln!("An error occurred while trying to change the state, Error: {e}");
+                self.stop_animation();
+                let state = profile.state();
+                self.worker.emit(DeviceInput::ApplyState(state));
+                self.set_state(&state);
+                self.syncs += 1;
+            }
+
+            AppInput::SaveProfile(name) => {
+                let profile = Profile::from(self.state());
+                if let Err(e) = self
+                    .profiles
+                    .insert(name.trim(), profile)
+                    .and_then(|_| self.profiles.save())
+                {
+                    eprintln!("An error occurred while trying to save the profile, Error: {e}");
                 }
             }
+
+            AppInput::DeleteProfile(name) => {
+                if let Err(e) = self
+                    .profiles
+                    .remove(&name)
+                    .and_then(|_| self.profiles.save())
+                {
+                    eprintln!("An error occurred while trying to delete the profile, Error: {e}");
+                }
+            }
+
+            AppInput::SelectDevice(index) => {
+                let Some(info) = self.devices.get(index as usize) else {
+                    return;
+                };
+                let selector = info.selector();
+                if selector == self.selector {
+                    return;
+                }
+                self.stop_animation();
+                self.worker.emit(DeviceInput::Select(selector.clone()));
+                self.selector = selector;
+                self.connected = false;
+                self.battery = None;
+                self.identity = None;
+                // The other headset starts over, without a disconnect notification
+                self.battery_monitor = BatteryMonitor::new(&self.settings.battery_thresholds);
+            }
+
+            AppInput::Device(DeviceOutput::Connected {
+                model,
+                state,
+                identity,
+            }) => {
+                self.model = model;
+                self.identity = Some(identity);
+                if let Some(state) = state {
+                    self.set_state(&state);
+                }
+                self.connected = true;
+                self.syncs += 1;
+                self.worker.emit(DeviceInput::ReadBattery);
+            }
+
+            AppInput::Device(DeviceOutput::Disconnected) => {
+                self.stop_animation();
+                self.connected = false;
+                self.battery = None;
+                self.identity = None;
+                self.monitor_battery();
+            }
+
+            AppInput::Device(DeviceOutput::Devices(devices)) => self.devices = devices,
+
+            AppInput::Device(DeviceOutput::Battery(battery)) => {
+                self.battery = battery;
+                self.monitor_battery();
+                // The headset answering again after a while means it woke up
+                if self.battery.is_some() && !self.connected {
+                    self.worker.emit(DeviceInput::Connect);
+                }
+            }
+
+            AppInput::Device(DeviceOutput::FrameFailed) => self.stop_animation(),
+
+            #[cfg(unix)]
+            AppInput::Tray(action) => match action {
+                TrayAction::ToggleWindow => {
+                    if self.window.is_visible() {
+                        self.window.set_visible(false);
+                    } else {
+                        self.window.present();
+                    }
+                }
+                TrayAction::ToggleLighting(enabled) => {
+                    if enabled {
+                        self.mode = self.lighting_mode;
+                    } else if self.mode != LightingMode::Off {
+                        self.lighting_mode = self.mode;
+                        self.mode = LightingMode::Off;
+                    }
+                    self.apply_effect();
+                    self.syncs += 1;
+                }
+                TrayAction::ToggleMicMonitor(enabled) => {
+                    SimpleComponent::update(self, AppInput::ToggleMicMonitor(enabled), sender);
+                    self.syncs += 1;
+                }
+                TrayAction::ToggleHaptics(enabled) => {
+                    SimpleComponent::update(self, AppInput::ToggleHaptics(enabled), sender);
+                    self.syncs += 1;
+                }
+                TrayAction::StartMinimized(enabled) => {
+                    self.settings.start_minimized = enabled;
+                    if let Err(e) = self.settings.save() {
+                        eprintln!("An error occurred while trying to save the settings, Error: {e}");
+                    }
+                }
+                TrayAction::Quit => {
+                    use gtk::prelude::ApplicationExt;
+                    relm4::main_application().quit();
+                }
+            },
+
+            AppInput::Ignore => {}
+
+            AppInput::RefreshBattery => self.worker.emit(DeviceInput::ReadBattery),
         }
     }
 
     fn update_view(&self, widgets: &mut Self::Widgets, _sender: ComponentSender<Self>) {
+        widgets.main_stack.set_sensitive(self.connected);
+        let devices = device_names(&self.devices);
+        if (0..widgets.d_list.n_items())
+            .map(|i| widgets.d_list.string(i).map(String::from).unwrap_or_default())
+            .ne(devices.iter().cloned())
+        {
+            let names: Vec<&str> = devices.iter().map(String::as_str).collect();
+            widgets.d_list.splice(0, widgets.d_list.n_items(), &names);
+            // Refilling the list resets the selection, point it back at the headset in use
+            if let Some(index) = self
+                .devices
+                .iter()
+                .position(|info| info.selector() == self.selector)
+            {
+                quietly(&widgets.d_dropdown, &widgets.d_dropdown_handler, |w| {
+                    w.set_selected(index as u32)
+                });
+            }
+        }
+        widgets.d_dropdown.set_visible(self.devices.len() > 1);
+        widgets.device_label.set_label(device_text(self.connected, self.model));
+        let capabilities = self.model.capabilities;
+        widgets.f1_box.set_visible(capabilities.sidetone);
+        widgets.f2_box.set_visible(capabilities.haptics);
+        widgets.lighting_box.set_visible(capabilities.lighting());
+        if widgets.syncs != self.syncs {
+            widgets.syncs = self.syncs;
+            quietly(&widgets.b_scale, &widgets.b_scale_handler, |w| {
+                w.set_value(self.brightness as f64)
+            });
+            quietly(&widgets.ht_switch, &widgets.ht_switch_handler, |w| {
+                w.set_active(self.haptics_enabled)
+            });
+            quietly(&widgets.hi_scale, &widgets.hi_scale_handler, |w| {
+                w.set_value(self.haptics as f64)
+            });
+            quietly(&widgets.mm_switch, &widgets.mm_switch_handler, |w| {
+                w.set_active(self.mic_monitor_enabled)
+            });
+            quietly(&widgets.mm_scale, &widgets.mm_scale_handler, |w| {
+                w.set_value(mic_monitor_from_raw(self.mic_monitor) as f64)
+            });
+            quietly(&widgets.c_effects_dropdown, &widgets.c_effects_dropdown_handler, |w| {
+                w.set_selected(
+                    LightingMode::ALL
+                        .iter()
+                        .position(|mode| *mode == self.mode)
+                        .unwrap_or_default() as u32,
+                )
+            });
+            quietly(&widgets.cb_dropdown, &widgets.cb_dropdown_handler, |w| {
+                w.set_selected(self.breathing)
+            });
+            quietly(&widgets.c_colorpicker, &widgets.c_colorpicker_handler, |w| {
+                w.set_rgba(&rgba(self.color))
+            });
+            quietly(&widgets.c2_colorpicker, &widgets.c2_colorpicker_handler, |w| {
+                w.set_rgba(&rgba(self.second_color))
+            });
+        }
+        let names = profile_names(&self.profiles);
+        if (0..widgets.p_list.n_items())
+            .map(|i| widgets.p_list.string(i).map(String::from).unwrap_or_default())
+            .ne(names.iter().copied())
+        {
+            widgets.p_list.splice(0, widgets.p_list.n_items(), &names);
+        }
+        widgets.about_label.set_label(&about_text(self.battery, self.identity.as_ref()));
+        widgets.battery_label.set_label(&match self.battery {
+            Some(battery) if battery.status == Some(ChargingStatus::Discharging) => {
+                format!("{}%", battery.percentage)
+            }
+            Some(battery) => battery.to_string(),
+            None => String::new(),
+        });
         if widgets.ht_switch.state() {
             widgets.hi_scale.set_sensitive(true);
             widgets.hi_scale.set_opacity(1.);
@@ -537,17 +1221,237 @@ impl SimpleComponent for Application {
             widgets.mm_scale.set_sensitive(false);
             widgets.mm_scale.set_opacity(0.4);
         }
-        if widgets.c_effects_dropdown.selected() == 0 {
+        let breathing = self.mode == LightingMode::Breathing;
+        if self.mode == LightingMode::Static || (breathing && self.breathing != 2) {
+            widgets.c_box.set_sensitive(true);
+            widgets.c_box.set_opacity(1.);
+        } else {
             widgets.c_box.set_sensitive(false);
             widgets.c_box.set_opacity(0.);
+        }
+        widgets.cb_box.set_visible(breathing);
+        widgets.c2_box.set_visible(breathing && self.breathing == 1);
+        widgets.a_button.set_label(if self.animation.is_some() {
+            "Stop Animation"
         } else {
-            widgets.c_box.set_sensitive(true);
-            widgets.c_box.set_opacity(1.);
+            "Play Animation"
+        });
+        #[cfg(unix)]
+        if let Some(tray) = &self.tray
+            && widgets.tray_state != Some(self.tray_state())
+        {
+            let state = self.tray_state();
+            widgets.tray_state = Some(state);
+            tray.update(|tray| tray.state = state);
+        }
+    }
+}
+
+impl Application {
+    /// Stops any running animation and sends the selected lighting effect
+    fn apply_effect(&mut self) {
+        self.stop_animation();
+        self.worker.emit(DeviceInput::SetEffect(self.effect()));
+    }
+
+    /// Takes over every setting of `state`, keeping the unused colors and levels as they are
+    fn set_state(&mut self, state: &DeviceState) {
+        self.brightness = state.brightness;
+        self.haptics_enabled = state.haptics_enabled;
+        self.haptics = state.haptic_intensity;
+        self.mic_monitor_enabled = state.mic_monitor != MIC_MONITOR_OFF;
+        if self.mic_monitor_enabled {
+            self.mic_monitor = state.mic_monitor;
+        }
+        self.mode = state.effect.mode();
+        if let Some(color) = state.effect.color() {
+            self.color = color;
+        }
+        if let LightingEffect::Breathing(breathing) = state.effect {
+            self.breathing = match breathing {
+                Breathing::Single(_) => 0,
+                Breathing::Dual(_, color) => {
+                    self.second_color = color;
+                    1
+                }
+                Breathing::Random => 2,
+            };
+        }
+    }
+
+    fn state(&self) -> DeviceState {
+        DeviceState {
+            effect: self.effect(),
+            brightness: self.brightness,
+            haptics_enabled: self.haptics_enabled,
+            haptic_intensity: self.haptics,
+            mic_monitor: if self.mic_monitor_enabled {
+                self.mic_monitor
+            } else {
+                MIC_MONITOR_OFF
+            },
+        }
+    }
+
+    #[cfg(unix)]
+    fn tray_state(&self) -> TrayState {
+        TrayState {
+            connected: self.connected,
+            model: self.model,
+            battery: self.battery,
+            lighting: self.mode != LightingMode::Off,
+            mic_monitor: self.mic_monitor_enabled,
+            haptics: self.haptics_enabled,
+            start_minimized: self.settings.start_minimized,
+        }
+    }
+
+    /// Passes the last battery reading to the monitor and shows what it has to say
+    fn monitor_battery(&mut self) {
+        for alert in self.battery_monitor.update(self.battery) {
+            if self.settings.notifications
+                && let Err(e) = alert.notify()
+            {
+                eprintln!("Unable to show a notification, Error: {e}");
+            }
+        }
+    }
+
+    fn stop_animation(&mut self) {
+        if let Some(playing) = self.animation.take() {
+            playing.timer.remove();
+        }
+    }
+
+    fn effect(&self) -> LightingEffect {
+        match self.mode {
+            LightingMode::Off => LightingEffect::Off,
+            LightingMode::Static => LightingEffect::Static(self.color),
+            LightingMode::Breathing => LightingEffect::Breathing(match self.breathing {
+                0 => Breathing::Single(self.color),
+                1 => Breathing::Dual(self.color, self.second_color),
+                _ => Breathing::Random,
+            }),
+            LightingMode::Spectrum => LightingEffect::Spectrum,
         }
     }
 }
 
+/// Shows the tray icon, `None` when the desktop has no StatusNotifierItem host
+#[cfg(unix)]
+fn spawn_tray(
+    state: TrayState,
+    sender: &ComponentSender<Application>,
+) -> Option<ksni::blocking::Handle<Tray>> {
+    use ksni::blocking::TrayMethods;
+    let input = sender.input_sender().clone();
+    let tray = Tray::new(state, move |action| input.emit(AppInput::Tray(action)));
+    match tray.spawn() {
+        Ok(handle) => Some(handle),
+        Err(e) => {
+            eprintln!("Unable to show the tray icon, Error: {e}");
+            None
+        }
+    }
+}
+
+/// Runs `set` on `widget` with `handler` blocked, so only the user changing it is reported
+fn quietly<W: IsA<glib::Object>>(
+    widget: &W,
+    handler: &glib::SignalHandlerId,
+    set: impl FnOnce(&W),
+) {
+    widget.block_signal(handler);
+    set(widget);
+    widget.unblock_signal(handler);
+}
+
+fn color_button(title: &str, (r, g, b): (u8, u8, u8)) -> gtk::ColorButton {
+    let button = gtk::ColorButton::builder()
+        .valign(gtk::Align::Center)
+        .halign(gtk::Align::Start)
+        .title(title)
+        .build();
+    button.set_size_request(1, 1);
+    let swatch = button
+        .first_child()
+        .expect("Couldn't find the colorbutton")
+        .first_child()
+        .expect("Couldn't find the color swatch");
+    swatch.set_width_request(20);
+    button.set_rgba(&rgba((r, g, b)));
+    button
+}
+
+fn rgba((r, g, b): (u8, u8, u8)) -> RGBA {
+    RGBA::new(r as f32 / 255., g as f32 / 255., b as f32 / 255., 1.)
+}
+
+fn device_text(connected: bool, model: &'static Model) -> &'static str {
+    if connected {
+        model.name
+    } else {
+        "Waiting for headset..."
+    }
+}
+
+fn device_names(devices: &[DeviceInfo]) -> Vec<String> {
+    devices
+        .iter()
+        .map(|info| format!("{} {}", info.model_name(), info.selector()))
+        .collect()
+}
+
+fn profile_names(profiles: &Profiles) -> Vec<&str> {
+    profiles.profiles.keys().map(String::as_str).collect()
+}
+
+fn selected_string(dropdown: &gtk::DropDown) -> Option<String> {
+    let item = dropdown.selected_item()?.downcast::<gtk::StringObject>().ok()?;
+    Some(item.string().to_string())
+}
+
+fn rgb(c: &RGBA) -> (u8, u8, u8) {
+    ((c.red() * 255.99) as u8, (c.green() * 255.99) as u8, (c.blue() * 255.99) as u8)
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn about_text(battery: Option<Battery>, identity: Option<&Identity>) -> String {
+    let battery_text = match battery {
+        Some(battery) => format!("\nBattery: <i>{battery}, {} mv</i>", battery.voltage),
+        None => String::new(),
+    };
+    let identity_text = match identity {
+        Some(identity) => {
+            let value = |value: &Option<String>| {
+                glib::markup_escape_text(value.as_deref().unwrap_or("unknown")).to_string()
+            };
+            format!(
+                "\nDongle firmware: <i>{}</i>\nDongle serial: <i>{}</i>\nHeadset firmware: <i>{}</i>\nHeadset serial: <i>{}</i>",
+                value(&identity.dongle_firmware),
+                value(&identity.dongle_serial),
+                value(&identity.headset_firmware),
+                value(&identity.headset_serial)
+            )
+        }
+        None => String::new(),
+    };
+    format!(
+        "<b>NariCTL</b>\n\nVersion: {}{battery_text}{identity_text}\n\n\n<small>Built and optimized for use with the Razer Nari family</small>",
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
 fn main() {
+    // NARICTL_LOG=trace logs every USB transfer
+    env_logger::Builder::from_env(env_logger::Env::new().filter_or("NARICTL_LOG", "warn")).init();
     let app = RelmApp::new("me.d22592.narictl");
     relm4::set_global_css(CSS);
     app.run::<Application>(0);
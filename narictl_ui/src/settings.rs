@@ -0,0 +1,48 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use narictl_lib::{DEFAULT_THRESHOLDS, Result, config_dir, load_toml, save_toml};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Preferences of NariCTL_UI itself, stored in `ui.toml` inside `config_dir()`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Only show the tray icon on start, the window opens from there
+    pub start_minimized: bool,
+    /// Desktop notifications for a low battery, charging and the headset coming and going
+    pub notifications: bool,
+    /// Charges (%) a low battery notification is sent at
+    pub battery_thresholds: Vec<u8>,
+    /// Seconds between two battery readings
+    pub battery_interval: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            start_minimized: false,
+            notifications: true,
+            battery_thresholds: DEFAULT_THRESHOLDS.to_vec(),
+            battery_interval: 30,
+        }
+    }
+}
+
+impl Settings {
+    pub fn path() -> Result<PathBuf> {
+        Ok(config_dir()?.join("ui.toml"))
+    }
+
+    /// Reads the settings, a missing file gives the defaults
+    pub fn load() -> Result<Self> {
+        load_toml(&Self::path()?)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        save_toml(&Self::path()?, self)
+    }
+}
@@ -0,0 +1,302 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+//! StatusNotifierItem icon showing the battery and connection state, with a menu for the
+//! settings changed most often.
+
+use ksni::menu::{CheckmarkItem, StandardItem};
+use ksni::{MenuItem, ToolTip};
+use narictl_lib::{Battery, ChargingStatus, Model};
+
+/// What a click in the tray asks the application to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrayAction {
+    ToggleWindow,
+    ToggleLighting(bool),
+    ToggleMicMonitor(bool),
+    ToggleHaptics(bool),
+    StartMinimized(bool),
+    Quit,
+}
+
+/// What the tray shows, kept up to date by the application
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrayState {
+    pub connected: bool,
+    pub model: &'static Model,
+    pub battery: Option<Battery>,
+    pub lighting: bool,
+    pub mic_monitor: bool,
+    pub haptics: bool,
+    pub start_minimized: bool,
+}
+
+pub struct Tray {
+    pub state: TrayState,
+    send: Box<dyn Fn(TrayAction) + Send>,
+}
+
+impl Tray {
+    pub fn new(state: TrayState, send: impl Fn(TrayAction) + Send + 'static) -> Self {
+        Tray {
+            state,
+            send: Box::new(send),
+        }
+    }
+
+    fn status_text(&self) -> String {
+        let TrayState {
+            connected,
+            model,
+            battery,
+            ..
+        } = self.state;
+        match (connected, battery) {
+            (false, _) => String::from("Waiting for headset..."),
+            (true, Some(battery)) if battery.status == Some(ChargingStatus::Discharging) => {
+                format!("{} {}%", model.name, battery.percentage)
+            }
+            (true, Some(battery)) => format!("{} {battery}", model.name),
+            (true, None) => model.name.to_string(),
+        }
+    }
+}
+
+impl ksni::Tray for Tray {
+    fn id(&self) -> String {
+        String::from("narictl_ui")
+    }
+
+    fn title(&self) -> String {
+        String::from("NariCTL")
+    }
+
+    fn icon_name(&self) -> String {
+        let connected = self.state.connected;
+        let Some(battery) = self.state.battery.filter(|_| connected) else {
+            return String::from(match connected {
+                true => "audio-headset",
+                false => "battery-missing",
+            });
+        };
+        let level = match battery.percentage {
+            0..=10 => "empty",
+            11..=25 => "caution",
+            26..=50 => "low",
+            51..=80 => "good",
+            _ => "full",
+        };
+        match battery.status {
+            Some(ChargingStatus::Charging) => format!("battery-{level}-charging"),
+            Some(ChargingStatus::Full) => String::from("battery-full-charged"),
+            _ => format!("battery-{level}"),
+        }
+    }
+
+    fn tool_tip(&self) -> ToolTip {
+        ToolTip {
+            title: String::from("NariCTL"),
+            description: self.status_text(),
+            ..Default::default()
+        }
+    }
+
+    fn activate(&mut self, _x: i32, _y: i32) {
+        (self.send)(TrayAction::ToggleWindow);
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        let state = self.state;
+        let capabilities = state.model.capabilities;
+        vec![
+            StandardItem {
+                label: self.status_text(),
+                enabled: false,
+                ..Default::default()
+            }
+            .into(),
+            MenuItem::Separator,
+            CheckmarkItem {
+                label: String::from("Lighting"),
+                enabled: state.connected,
+                visible: capabilities.lighting(),
+                checked: state.lighting,
+                activate: Box::new(|tray: &mut Self| {
+                    (tray.send)(TrayAction::ToggleLighting(!tray.state.lighting))
+                }),
+                ..Default::default()
+            }
+            .into(),
+            CheckmarkItem {
+                label: String::from("Mic Monitoring"),
+                enabled: state.connected,
+                visible: capabilities.sidetone,
+                checked: state.mic_monitor,
+                activate: Box::new(|tray: &mut Self| {
+                    (tray.send)(TrayAction::ToggleMicMonitor(!tray.state.mic_monitor))
+                }),
+                ..Default::default()
+            }
+            .into(),
+            CheckmarkItem {
+                label: String::from("Haptics"),
+                enabled: state.connected,
+                visible: capabilities.haptics,
+                checked: state.haptics,
+                activate: Box::new(|tray: &mut Self| {
+                    (tray.send)(TrayAction::ToggleHaptics(!tray.state.haptics))
+                }),
+                ..Default::default()
+            }
+            .into(),
+            MenuItem::Separator,
+            StandardItem {
+                label: String::from("Show/Hide NariCTL"),
+                activate: Box::new(|tray: &mut Self| (tray.send)(TrayAction::ToggleWindow)),
+                ..Default::default()
+            }
+            .into(),
+            CheckmarkItem {
+                label: String::from("Start Minimized to Tray"),
+                checked: state.start_minimized,
+                activate: Box::new(|tray: &mut Self| {
+                    (tray.send)(TrayAction::StartMinimized(!tray.state.start_minimized))
+                }),
+                ..Default::default()
+            }
+            .into(),
+            StandardItem {
+                label: String::from("Quit"),
+                icon_name: String::from("application-exit"),
+                activate: Box::new(|tray: &mut Self| (tray.send)(TrayAction::Quit)),
+                ..Default::default()
+            }
+            .into(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ksni::blocking::TrayMethods;
+    use narictl_lib::NARI_ULTIMATE;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Stdio};
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use zbus::blocking::connection::Builder;
+    use zbus::blocking::{Connection, Proxy};
+    use zbus::proxy::CacheProperties;
+
+    /// A `dbus-daemon` of its own, so the test does not depend on or disturb the session bus
+    struct PrivateBus(Child);
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    fn private_bus() -> Option<(PrivateBus, String)> {
+        let mut child = std::process::Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let stdout = child.stdout.take();
+        let bus = PrivateBus(child);
+        let mut address = String::new();
+        BufReader::new(stdout?).read_line(&mut address).ok()?;
+        Some((bus, address.trim().to_string()))
+    }
+
+    /// Stands in for the desktop's tray host, passing on the name the tray registers with
+    struct Watcher(mpsc::Sender<String>);
+
+    #[zbus::interface(name = "org.kde.StatusNotifierWatcher")]
+    impl Watcher {
+        fn register_status_notifier_item(&self, service: &str) {
+            let _ = self.0.send(service.to_string());
+        }
+
+        #[zbus(property)]
+        fn is_status_notifier_host_registered(&self) -> bool {
+            true
+        }
+    }
+
+    fn item<'a>(client: &'a Connection, name: &'a str) -> Proxy<'a> {
+        zbus::blocking::proxy::Builder::new(client)
+            .destination(name)
+            .unwrap()
+            .path("/StatusNotifierItem")
+            .unwrap()
+            .interface("org.kde.StatusNotifierItem")
+            .unwrap()
+            .cache_properties(CacheProperties::No)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn tray_follows_the_state() {
+        let Some((_bus, address)) = private_bus() else {
+            eprintln!("dbus-daemon is not installed, skipping");
+            return;
+        };
+        // SAFETY: no other test in this crate reads the environment
+        unsafe { std::env::set_var("DBUS_SESSION_BUS_ADDRESS", &address) };
+        let (registered, names) = mpsc::channel();
+        let _watcher = Builder::address(address.as_str())
+            .unwrap()
+            .name("org.kde.StatusNotifierWatcher")
+            .unwrap()
+            .serve_at("/StatusNotifierWatcher", Watcher(registered))
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let state = TrayState {
+            connected: false,
+            model: &NARI_ULTIMATE,
+            battery: None,
+            lighting: true,
+            mic_monitor: false,
+            haptics: false,
+            start_minimized: false,
+        };
+        let (sender, actions) = mpsc::channel();
+        let handle = Tray::new(state, move |action| _ = sender.send(action))
+            .spawn()
+            .unwrap();
+        let name = names.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let client = Builder::address(address.as_str()).unwrap().build().unwrap();
+        let item = item(&client, &name);
+        let icon = || item.get_property::<String>("IconName").unwrap();
+        assert_eq!(icon(), "battery-missing");
+
+        let battery = Battery {
+            voltage: 3900,
+            percentage: 60,
+            status: Some(ChargingStatus::Charging),
+        };
+        handle.update(|tray| {
+            tray.state.connected = true;
+            tray.state.battery = Some(battery);
+        });
+        assert_eq!(icon(), "battery-good-charging");
+
+        item.call_method("Activate", &(0i32, 0i32)).unwrap();
+        assert_eq!(
+            actions.recv_timeout(Duration::from_secs(5)).unwrap(),
+            TrayAction::ToggleWindow
+        );
+        handle.shutdown().wait();
+    }
+}
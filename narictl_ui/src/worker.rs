@@ -0,0 +1,207 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+//! Owns the headset and talks to it on a thread of its own, so a slow or silent headset never
+//! blocks the window. Requests are handled in the order they were sent.
+
+use narictl_lib::*;
+use relm4::{ComponentSender, Worker};
+use std::thread;
+use std::time::Duration;
+
+// Gives udev a moment to set the permissions of a freshly plugged dongle
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// What the application asks the headset to do
+#[derive(Debug)]
+pub enum DeviceInput {
+    /// Reads the model, state and identity, answered with `Connected` once the headset answers
+    Connect,
+    /// Switches to another dongle and connects to it
+    Select(DeviceSelector),
+    /// Lists the dongles plugged in, answered with `Devices`
+    ListDevices,
+    Hotplug(HotplugEvent),
+    ReadBattery,
+    SetMicMonitor(u8),
+    SetHaptics(u8, bool),
+    SetBrightness(u8),
+    SetEffect(LightingEffect),
+    /// One frame of an animation, answered with `FrameFailed` when it could not be sent
+    SetFrame(u8, u8, u8),
+    ApplyState(DeviceState),
+}
+
+/// What the headset answered
+#[derive(Debug)]
+pub enum DeviceOutput {
+    /// `state` is `None` when the headset answered but its settings could not be read
+    Connected {
+        model: &'static Model,
+        state: Option<DeviceState>,
+        identity: Identity,
+    },
+    /// The selected dongle was unplugged
+    Disconnected,
+    /// Every dongle plugged in
+    Devices(Vec<DeviceInfo>),
+    /// `None` when the headset did not answer
+    Battery(Option<Battery>),
+    FrameFailed,
+}
+
+pub struct DeviceWorker {
+    device: Device<Box<dyn Transport + Send>>,
+    selector: DeviceSelector,
+    /// Bus and port path of the dongle the headset last answered through
+    port: Option<(u8, Vec<u8>)>,
+}
+
+impl Worker for DeviceWorker {
+    type Init = ();
+    type Input = DeviceInput;
+    type Output = DeviceOutput;
+
+    fn init(_init: Self::Init, _sender: ComponentSender<Self>) -> Self {
+        DeviceWorker {
+            device: Device::connect_lazy(),
+            selector: DeviceSelector::First,
+            port: None,
+        }
+    }
+
+    fn update(&mut self, input: DeviceInput, sender: ComponentSender<Self>) {
+        // Only fails once the application is gone
+        let output = |output| {
+            let _ = sender.output(output);
+        };
+        match input {
+            DeviceInput::Connect => self.connect(&sender),
+
+            DeviceInput::Select(selector) => {
+                self.device = match Device::connect_selected(selector.clone()) {
+                    Ok(device) => device,
+                    Err(e) => {
+                        eprintln!("Unable to open the selected headset, Error: {e}");
+                        Device::with_transport(Box::new(UsbTransport::with_selector(
+                            selector.clone(),
+                        )))
+                    }
+                };
+                self.selector = selector;
+                self.connect(&sender);
+            }
+
+            DeviceInput::ListDevices => {
+                output(DeviceOutput::Devices(devices().unwrap_or_default()));
+            }
+
+            DeviceInput::Hotplug(event) => {
+                // Keeps the last list when the dongles can not be listed
+                let devices = devices();
+                // Another dongle leaving does not concern the open one
+                let still_plugged_in = self.port.as_ref().is_some_and(|(bus, port_path)| {
+                    devices.as_ref().is_ok_and(|devices| {
+                        devices
+                            .iter()
+                            .any(|info| info.bus == *bus && info.port_path == *port_path)
+                    })
+                });
+                if let Ok(devices) = devices {
+                    output(DeviceOutput::Devices(devices));
+                }
+                match event {
+                    HotplugEvent::Connected => {
+                        thread::sleep(RECONNECT_DELAY);
+                        self.connect(&sender);
+                    }
+                    HotplugEvent::Disconnected if !still_plugged_in => {
+                        output(DeviceOutput::Disconnected)
+                    }
+                    HotplugEvent::Disconnected => {}
+                }
+            }
+
+            DeviceInput::ReadBattery => output(DeviceOutput::Battery(
+                self.device
+                    .get_battery()
+                    .inspect_err(|e| eprintln!("Unable to get battery data, Error: {e}"))
+                    .ok(),
+            )),
+
+            DeviceInput::SetMicMonitor(value) => {
+                report(
+                    "change the mic monitor state",
+                    self.device.set_mic_monitor(value),
+                );
+            }
+
+            DeviceInput::SetHaptics(intensity, enabled) => report(
+                "change the haptics",
+                self.device.set_haptic_intensity(intensity, enabled),
+            ),
+
+            DeviceInput::SetBrightness(brightness) => {
+                report(
+                    "change the brightness",
+                    self.device.set_brightness(brightness),
+                );
+            }
+
+            DeviceInput::SetEffect(effect) => {
+                report("change the lighting", self.device.set_effect(effect));
+            }
+
+            DeviceInput::SetFrame(r, g, b) => {
+                if let Err(e) = self.device.set_fixed(r, g, b) {
+                    eprintln!("An error occurred while playing the animation, Error: {e}");
+                    output(DeviceOutput::FrameFailed);
+                }
+            }
+
+            DeviceInput::ApplyState(state) => {
+                report("apply the profile", self.device.apply_state(&state));
+            }
+        }
+    }
+}
+
+impl DeviceWorker {
+    fn connect(&mut self, sender: &ComponentSender<Self>) {
+        let state = match self.device.state() {
+            Ok(state) => Some(state),
+            Err(e @ (NariError::DeviceNotFound | NariError::Timeout)) => {
+                eprintln!("Still waiting for the headset, Error: {e}");
+                return;
+            }
+            Err(e) => {
+                eprintln!("Unable to read the headset state, Error: {e}");
+                None
+            }
+        };
+        self.port = match self.device.transport().device_info() {
+            Ok(info) => info.map(|info| (info.bus, info.port_path)),
+            Err(e) => {
+                eprintln!("Unable to find the port of the headset dongle, Error: {e}");
+                None
+            }
+        };
+        let _ = sender.output(DeviceOutput::Connected {
+            model: self.device.model().unwrap_or(&NARI_ULTIMATE),
+            state,
+            identity: self.device.identity(),
+        });
+    }
+}
+
+fn devices() -> Result<Vec<DeviceInfo>> {
+    Device::enumerate().inspect_err(|e| eprintln!("Unable to list the headsets, Error: {e}"))
+}
+
+fn report(action: &str, result: Result<()>) {
+    if let Err(e) = result {
+        eprintln!("An error occurred while trying to {action}, Error: {e}");
+    }
+}
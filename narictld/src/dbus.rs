@@ -0,0 +1,262 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+//! `me.d22592.narictl` on the session bus, a thin layer over the same requests the socket serves.
+
+use crate::server::Daemon;
+use narictl_lib::daemon::{Reply, Request};
+use narictl_lib::{Battery, NariError, Result};
+use std::sync::Arc;
+use zbus::blocking::Connection;
+use zbus::blocking::connection::Builder;
+use zbus::fdo;
+
+const NAME: &str = "me.d22592.narictl";
+const PATH: &str = "/me/d22592/narictl";
+
+struct Headset {
+    daemon: Arc<Daemon>,
+}
+
+#[zbus::interface(name = "me.d22592.narictl.Headset")]
+impl Headset {
+    fn set_color(&self, red: u8, green: u8, blue: u8) -> fdo::Result<()> {
+        self.execute(&Request::SetColor {
+            color: (red, green, blue),
+        })
+        .map(drop)
+    }
+
+    fn set_brightness(&self, brightness: u8) -> fdo::Result<()> {
+        self.execute(&Request::SetBrightness { brightness })
+            .map(drop)
+    }
+
+    fn set_haptics(&self, intensity: u8, enabled: bool) -> fdo::Result<()> {
+        self.execute(&Request::SetHaptics { intensity, enabled })
+            .map(drop)
+    }
+
+    /// Mic monitor level from 0 (off) to 86
+    fn set_sidetone(&self, level: u8) -> fdo::Result<()> {
+        self.execute(&Request::SetSidetone { level }).map(drop)
+    }
+
+    /// Reads the battery right away instead of returning the cached properties
+    fn get_battery(&self) -> fdo::Result<(u16, u8, String)> {
+        match self.execute(&Request::GetBattery)? {
+            Reply::Battery(battery) => Ok((
+                battery.voltage,
+                battery.percentage,
+                battery
+                    .status
+                    .map_or_else(|| String::from("unknown"), |s| s.to_string()),
+            )),
+            reply => Err(fdo::Error::Failed(format!("unexpected reply {reply:?}"))),
+        }
+    }
+
+    /// Charge in percent, -1 while it is unknown
+    #[zbus(property)]
+    fn battery_percentage(&self) -> i16 {
+        self.daemon
+            .presence
+            .lock()
+            .unwrap()
+            .battery
+            .map_or(-1, |battery| battery.percentage.into())
+    }
+
+    /// `discharging`, `charging`, `full`, or `unknown` while the headset does not tell
+    #[zbus(property)]
+    fn charging_status(&self) -> String {
+        match self.daemon.presence.lock().unwrap().battery {
+            Some(Battery {
+                status: Some(status),
+                ..
+            }) => status.to_string(),
+            _ => String::from("unknown"),
+        }
+    }
+
+    /// Whether the headset answered the last request that needed it to
+    #[zbus(property)]
+    fn connected(&self) -> bool {
+        self.daemon.presence.lock().unwrap().connected
+    }
+}
+
+impl Headset {
+    fn execute(&self, request: &Request) -> fdo::Result<Reply> {
+        self.daemon.execute(request).map_err(|e| match e {
+            NariError::InvalidArgument(_) => fdo::Error::InvalidArgs(e.to_string()),
+            e => fdo::Error::Failed(e.to_string()),
+        })
+    }
+}
+
+/// Claims `me.d22592.narictl` on the session bus, the service lives as long as the connection
+pub fn serve(daemon: Arc<Daemon>) -> Result<Connection> {
+    let connect = || serve_on(Builder::session()?, daemon);
+    connect().map_err(|e| NariError::Daemon(e.to_string()))
+}
+
+fn serve_on(builder: Builder<'_>, daemon: Arc<Daemon>) -> zbus::Result<Connection> {
+    builder
+        .name(NAME)?
+        .serve_at(PATH, Headset { daemon })?
+        .build()
+}
+
+/// Emits `PropertiesChanged` for the battery and connection properties after they changed
+pub fn presence_changed(connection: &Connection) {
+    let interface = match connection.object_server().interface::<_, Headset>(PATH) {
+        Ok(interface) => interface,
+        Err(e) => {
+            log::warn!("D-Bus interface not found: {e}");
+            return;
+        }
+    };
+    let headset = interface.get();
+    let emitter = interface.signal_emitter();
+    let result = async_io::block_on(async {
+        headset.battery_percentage_changed(emitter).await?;
+        headset.charging_status_changed(emitter).await?;
+        headset.connected_changed(emitter).await
+    });
+    if let Err(e) = result {
+        log::warn!("Failed to announce the battery on D-Bus: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use narictl_lib::{Command, Device, Direction, MockTransport, Report, Transport};
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Stdio};
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use zbus::blocking::fdo::PropertiesProxy;
+    use zbus::names::InterfaceName;
+    use zbus::zvariant::OwnedValue;
+
+    const INTERFACE: &str = "me.d22592.narictl.Headset";
+
+    /// A `dbus-daemon` of its own, so the test does not depend on or disturb the session bus
+    struct PrivateBus(Child);
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    fn private_bus() -> Option<(PrivateBus, String)> {
+        let mut child = std::process::Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let stdout = child.stdout.take();
+        let bus = PrivateBus(child);
+        let mut address = String::new();
+        BufReader::new(stdout?).read_line(&mut address).ok()?;
+        Some((bus, address.trim().to_string()))
+    }
+
+    /// Collects the properties announced by the next `count` `PropertiesChanged` signals
+    fn changes(
+        proxy: &PropertiesProxy<'static>,
+        count: usize,
+    ) -> mpsc::Receiver<(String, OwnedValue)> {
+        let signals = proxy.receive_properties_changed().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for signal in signals.take(count) {
+                let args = signal.args().unwrap();
+                for (name, value) in args.changed_properties() {
+                    let value = OwnedValue::try_from(value.try_clone().unwrap()).unwrap();
+                    let _ = sender.send((name.to_string(), value));
+                }
+            }
+        });
+        receiver
+    }
+
+    fn collect(receiver: &mpsc::Receiver<(String, OwnedValue)>) -> HashMap<String, OwnedValue> {
+        (0..3)
+            .map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn battery_reads_are_announced() {
+        let Some((_bus, address)) = private_bus() else {
+            eprintln!("dbus-daemon is not installed, skipping");
+            return;
+        };
+        let transport = MockTransport::new();
+        for (command, value) in [
+            (Command::VOLTAGE, [0x0f, 0x6e]),
+            (Command::CHARGING, [0x01, 0]),
+        ] {
+            let arguments = [0, 0, 0, value[0], value[1]];
+            let answer = Report::new(Direction::Get, command, &arguments).unwrap();
+            transport.push_response(answer.to_bytes());
+        }
+        let transport: Box<dyn Transport + Send> = Box::new(transport);
+        let device = Device::with_transport(transport).with_experimental(true);
+        let daemon = Arc::new(Daemon::new(device));
+        let connection = serve_on(Builder::address(address.as_str()).unwrap(), daemon.clone());
+        assert!(daemon.bus.set(connection.unwrap()).is_ok());
+
+        let client = Builder::address(address.as_str()).unwrap().build().unwrap();
+        let proxy = PropertiesProxy::builder(&client)
+            .destination(NAME)
+            .unwrap()
+            .path(PATH)
+            .unwrap()
+            .build()
+            .unwrap();
+        let interface = InterfaceName::from_static_str(INTERFACE).unwrap();
+        let percentage = |proxy: &PropertiesProxy| {
+            i16::try_from(proxy.get(interface.clone(), "BatteryPercentage").unwrap()).unwrap()
+        };
+        assert_eq!(percentage(&proxy), -1);
+
+        let receiver = changes(&proxy, 3);
+        let reply = client
+            .call_method(Some(NAME), PATH, Some(INTERFACE), "GetBattery", &())
+            .unwrap();
+        let (voltage, charge, status): (u16, u8, String) = reply.body().deserialize().unwrap();
+        assert_eq!((voltage, status.as_str()), (3950, "charging"));
+        let changed = collect(&receiver);
+        assert_eq!(
+            i16::try_from(&changed["BatteryPercentage"]).unwrap(),
+            i16::from(charge)
+        );
+        assert_eq!(
+            <&str>::try_from(&changed["ChargingStatus"]).unwrap(),
+            "charging"
+        );
+        assert!(bool::try_from(&changed["Connected"]).unwrap());
+        assert_eq!(percentage(&proxy), i16::from(charge));
+
+        // The headset stops answering, the next read turns it into disconnected
+        let receiver = changes(&proxy, 3);
+        assert!(
+            client
+                .call_method(Some(NAME), PATH, Some(INTERFACE), "GetBattery", &())
+                .is_err()
+        );
+        let changed = collect(&receiver);
+        assert_eq!(i16::try_from(&changed["BatteryPercentage"]).unwrap(), -1);
+        assert!(!bool::try_from(&changed["Connected"]).unwrap());
+    }
+}
@@ -0,0 +1,25 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+#[cfg(unix)]
+mod dbus;
+#[cfg(unix)]
+mod server;
+
+#[cfg(unix)]
+fn main() {
+    // NARICTL_LOG=trace logs every USB transfer
+    env_logger::Builder::from_env(env_logger::Env::new().filter_or("NARICTL_LOG", "warn")).init();
+    if let Err(e) = server::run() {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("narictld needs Unix sockets and is only available on Linux and other Unix systems");
+    std::process::exit(1);
+}
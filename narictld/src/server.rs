@@ -0,0 +1,371 @@
+/*
+Copyright d22592 and contributors
+SPDX-License-Identifier: GPL-3.0-or-later
+*/
+
+use crate::dbus;
+use narictl_lib::daemon::{
+    ErrorReply, Event, Reply, Request, Response, remove_stale_socket, socket_path,
+};
+use narictl_lib::*;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+const BATTERY_INTERVAL: Duration = Duration::from_secs(60);
+/// How long an event may take to reach a subscriber before it is dropped, so a client that
+/// stopped reading can not hold up the others or the request that caused the event
+const SUBSCRIBER_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// What the daemon knows about the headset, announced on the socket and D-Bus when it changes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Presence {
+    /// Whether the headset answered the last request that needed it to
+    pub connected: bool,
+    /// Last battery reading, `None` until one was answered and while disconnected
+    pub battery: Option<Battery>,
+}
+
+pub struct Daemon {
+    device: Mutex<Device<Box<dyn Transport + Send>>>,
+    subscribers: Mutex<Vec<UnixStream>>,
+    pub presence: Mutex<Presence>,
+    /// Session bus connection of the D-Bus service, set once it is up
+    pub bus: OnceLock<zbus::blocking::Connection>,
+}
+
+impl Daemon {
+    pub fn new(device: Device<Box<dyn Transport + Send>>) -> Self {
+        Daemon {
+            device: Mutex::new(device),
+            subscribers: Mutex::new(Vec::new()),
+            presence: Mutex::new(Presence::default()),
+            bus: OnceLock::new(),
+        }
+    }
+
+    /// Runs `request` on the headset and tells subscribers when it changed a setting
+    /// or what is known about the headset
+    pub fn execute(&self, request: &Request) -> Result<Reply> {
+        let result = handle(&self.device.lock().unwrap(), request);
+        match &result {
+            Ok(Reply::Battery(battery)) => self.update(Presence {
+                connected: true,
+                battery: Some(*battery),
+            }),
+            Ok(_) => {
+                // Copied out first, `update` takes the lock again
+                let battery = self.presence.lock().unwrap().battery;
+                self.update(Presence {
+                    connected: true,
+                    battery,
+                })
+            }
+            Err(e) if is_unreachable(e) => self.update(Presence::default()),
+            _ => {}
+        }
+        let reply = result?;
+        if changes_state(request) {
+            self.broadcast(&Event::StateChanged);
+        }
+        Ok(reply)
+    }
+
+    fn update(&self, presence: Presence) {
+        let last = std::mem::replace(&mut *self.presence.lock().unwrap(), presence);
+        if presence == last {
+            return;
+        }
+        if let Some(battery) = presence.battery
+            && presence.battery != last.battery
+        {
+            self.broadcast(&Event::Battery { battery });
+        }
+        if let Some(bus) = self.bus.get() {
+            dbus::presence_changed(bus);
+        }
+    }
+
+    pub fn broadcast(&self, event: &Event) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain_mut(|subscriber| write_line(subscriber, event).is_ok());
+    }
+}
+
+pub fn run() -> Result<()> {
+    let path = socket_path()?;
+    if UnixStream::connect(&path).is_ok() {
+        return Err(NariError::Daemon(format!(
+            "already running on {}",
+            path.display()
+        )));
+    }
+    // Nobody answered, so a socket of ours left at the path belongs to a daemon that did not exit cleanly
+    remove_stale_socket(&path)?;
+
+    let device: Box<dyn Transport + Send> = Box::new(UsbTransport::open()?);
+    let daemon = Arc::new(Daemon::new(Device::with_transport(device)));
+    let listener = UnixListener::bind(&path)
+        .map_err(|e| NariError::Daemon(format!("failed to listen on {}: {e}", path.display())))?;
+    log::info!("Listening on {}", path.display());
+
+    match dbus::serve(daemon.clone()) {
+        Ok(bus) => _ = daemon.bus.set(bus),
+        Err(e) => log::warn!("D-Bus service not available: {e}"),
+    }
+
+    thread::spawn({
+        let daemon = daemon.clone();
+        move || watch_battery(&daemon)
+    });
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let daemon = daemon.clone();
+                thread::spawn(move || serve(&daemon, stream));
+            }
+            Err(e) => log::warn!("Failed to accept a connection: {e}"),
+        }
+    }
+    Ok(())
+}
+
+fn serve(daemon: &Daemon, stream: UnixStream) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(e) => {
+            log::warn!("Failed to set up a connection: {e}");
+            return;
+        }
+    };
+
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        let request = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                let error = NariError::InvalidArgument(format!("invalid request: {e}"));
+                let response = Response::Error(ErrorReply::from(&error));
+                if write_line(&mut writer, &response).is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+
+        if request == Request::Subscribe {
+            // Holding the list makes the reply reach the client before any event
+            let mut subscribers = daemon.subscribers.lock().unwrap();
+            if writer.set_write_timeout(Some(SUBSCRIBER_TIMEOUT)).is_ok()
+                && write_line(&mut writer, &Response::Ok(Reply::Subscribed)).is_ok()
+            {
+                subscribers.push(writer);
+            }
+            return;
+        }
+
+        let response = match daemon.execute(&request) {
+            Ok(reply) => Response::Ok(reply),
+            Err(e) => Response::Error(ErrorReply::from(&e)),
+        };
+        if write_line(&mut writer, &response).is_err() {
+            return;
+        }
+    }
+}
+
+fn handle(device: &Device<Box<dyn Transport + Send>>, request: &Request) -> Result<Reply> {
+    match request {
+        Request::SetColor { color: (r, g, b) } => device.set_fixed(*r, *g, *b)?,
+        Request::SetBrightness { brightness } => device.set_brightness(*brightness)?,
+        Request::SetHaptics { intensity, enabled } => {
+            device.set_haptic_intensity(*intensity, *enabled)?
+        }
+        Request::SetSidetone { level } => {
+            if *level > MIC_MONITOR_MAX_LEVEL {
+                return Err(NariError::InvalidArgument(format!(
+                    "sidetone level must be between 0 and {MIC_MONITOR_MAX_LEVEL}, got {level}"
+                )));
+            }
+            device.set_mic_monitor(mic_monitor_to_raw(*level))?
+        }
+        Request::GetBattery => return Ok(Reply::Battery(device.get_battery()?)),
+        Request::GetProductId => return Ok(Reply::ProductId(device.model()?.product_id)),
+        Request::GetDeviceInfo => return Ok(Reply::DeviceInfo(device.transport().device_info()?)),
+        Request::Send { report } => {
+            let written = device.transport().send_report(&decode_hex(report)?)?;
+            return Ok(Reply::Written(written));
+        }
+        Request::Receive => {
+            let mut buffer = [0; REPORT_LEN];
+            let read = device.transport().receive_report(&mut buffer)?;
+            return Ok(Reply::Report(encode_hex(&buffer[..read])));
+        }
+        Request::Exchange { report } => {
+            let mut buffer = [0; REPORT_LEN];
+            let read = device
+                .transport()
+                .exchange(&decode_hex(report)?, &mut buffer)?;
+            return Ok(Reply::Report(encode_hex(&buffer[..read])));
+        }
+        Request::Subscribe => return Ok(Reply::Subscribed),
+    }
+    Ok(Reply::Done)
+}
+
+fn changes_state(request: &Request) -> bool {
+    match request {
+        Request::GetBattery
+        | Request::GetProductId
+        | Request::GetDeviceInfo
+        | Request::Subscribe
+        | Request::Receive => false,
+        // Raw reports only change something when they are set requests
+        Request::Send { report } | Request::Exchange { report } => {
+            Report::from_hex(report).is_ok_and(|report| report.direction == Direction::Set)
+        }
+        _ => true,
+    }
+}
+
+/// Whether `e` means the headset or its dongle is gone, rather than a request it refused
+fn is_unreachable(e: &NariError) -> bool {
+    matches!(
+        e,
+        NariError::DeviceNotFound
+            | NariError::Timeout
+            | NariError::ShortWrite { .. }
+            | NariError::Usb(_)
+    )
+}
+
+/// Reads the battery every `BATTERY_INTERVAL`, requests from clients update it in between
+fn watch_battery(daemon: &Daemon) {
+    loop {
+        if let Err(e) = daemon.execute(&Request::GetBattery) {
+            log::warn!("Failed to read the battery: {e}");
+        }
+        thread::sleep(BATTERY_INTERVAL);
+    }
+}
+
+fn write_line<T: serde::Serialize>(stream: &mut UnixStream, message: &T) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use narictl_lib::MockTransport;
+    use serde_json::{Value, json};
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Socket of its own in the temp directory, removed with the test
+    struct Socket(PathBuf);
+
+    impl Drop for Socket {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn listen(daemon: Arc<Daemon>, name: &str) -> Socket {
+        let path =
+            std::env::temp_dir().join(format!("narictld-{}-{name}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let daemon = daemon.clone();
+                thread::spawn(move || serve(&daemon, stream));
+            }
+        });
+        Socket(path)
+    }
+
+    fn daemon(transport: MockTransport) -> Arc<Daemon> {
+        let transport: Box<dyn Transport + Send> = Box::new(transport);
+        Arc::new(Daemon::new(
+            Device::with_transport(transport).with_experimental(false),
+        ))
+    }
+
+    fn connect(socket: &Socket) -> BufReader<UnixStream> {
+        let stream = UnixStream::connect(&socket.0).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        BufReader::new(stream)
+    }
+
+    fn read(stream: &mut BufReader<UnixStream>) -> Value {
+        let mut line = String::new();
+        stream.read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    fn request(stream: &mut BufReader<UnixStream>, line: &str) -> Value {
+        writeln!(stream.get_mut(), "{line}").unwrap();
+        read(stream)
+    }
+
+    #[test]
+    fn requests_are_answered_over_the_socket() {
+        let transport = MockTransport::new();
+        let voltage =
+            Report::new(Direction::Get, Command::VOLTAGE, &[0, 0, 0, 0x0f, 0x6e]).unwrap();
+        transport.push_response(voltage.to_bytes());
+        let socket = listen(daemon(transport), "requests");
+        let mut client = connect(&socket);
+
+        assert_eq!(
+            request(&mut client, r#"{"cmd":"set_color","color":[255,0,0]}"#),
+            json!({ "ok": "done" })
+        );
+        let battery = request(&mut client, r#"{"cmd":"get_battery"}"#);
+        assert_eq!(battery["ok"]["battery"]["voltage"], 3950);
+        // Nothing is queued anymore, so the headset does not answer
+        let error = request(&mut client, r#"{"cmd":"get_battery"}"#);
+        assert_eq!(error["error"]["code"], "timeout");
+        let error = request(&mut client, r#"{"cmd":"set_sidetone","level":200}"#);
+        assert_eq!(error["error"]["code"], "invalid_argument");
+        let error = request(&mut client, r#"{"cmd":"set_effect"}"#);
+        assert_eq!(error["error"]["code"], "invalid_argument");
+    }
+
+    #[test]
+    fn subscribers_hear_about_changes() {
+        let daemon = daemon(MockTransport::new());
+        let socket = listen(daemon.clone(), "subscribers");
+        let mut subscriber = connect(&socket);
+        assert_eq!(
+            request(&mut subscriber, r#"{"cmd":"subscribe"}"#),
+            json!({ "ok": "subscribed" })
+        );
+        let gone = connect(&socket);
+        writeln!(gone.get_ref(), r#"{{"cmd":"subscribe"}}"#).unwrap();
+        let mut client = connect(&socket);
+        // The second subscriber leaves without reading once both are in the list
+        while daemon.subscribers.lock().unwrap().len() < 2 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        drop(gone);
+
+        assert_eq!(
+            request(&mut client, r#"{"cmd":"set_brightness","brightness":80}"#),
+            json!({ "ok": "done" })
+        );
+        assert_eq!(read(&mut subscriber), json!({ "event": "state_changed" }));
+        assert_eq!(daemon.subscribers.lock().unwrap().len(), 1);
+    }
+}